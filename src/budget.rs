@@ -0,0 +1,179 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use leonidas::openai::Usage;
+use serde::{Deserialize, Serialize};
+use serenity::all::GuildId;
+use tracing::error;
+
+/// OpenAI tokens a server has used since its window started.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+struct Spent {
+    since: DateTime<Utc>,
+    tokens: u64,
+}
+
+/// Caps how many OpenAI tokens each server's jobs can use in a window, e.g.
+/// a month, so one busy server can't run up the bill. A server's window
+/// starts with its first job after the last one ended. DMs aren't counted.
+pub struct Budgets {
+    spent: Mutex<HashMap<u64, Spent>>,
+    max_tokens: Option<u64>,
+    window: Duration,
+    path: Option<PathBuf>,
+}
+
+impl Budgets {
+    /// Without `max_tokens`, servers can use as much as they like.
+    pub fn new(max_tokens: Option<u64>, window: Duration) -> Budgets {
+        Budgets {
+            spent: Mutex::new(HashMap::new()),
+            max_tokens,
+            window,
+            path: None,
+        }
+    }
+
+    /// Keep what's been spent in the JSON file at `path`, starting with
+    /// what's already there, so restarting doesn't reset every budget.
+    pub fn persisted(mut self, path: PathBuf) -> Budgets {
+        let spent = match std::fs::read_to_string(&path) {
+            Ok(contents) => serde_json::from_str(&contents).unwrap_or_else(|why| {
+                error!("Error parsing budget file {path:?}: {why:?}");
+                HashMap::new()
+            }),
+            Err(_) => HashMap::new(),
+        };
+        self.spent = Mutex::new(spent);
+        self.path = Some(path);
+        self
+    }
+
+    fn save(&self, spent: &HashMap<u64, Spent>) {
+        let Some(path) = &self.path else {
+            return;
+        };
+        let temporary = path.with_extension("tmp");
+        let written = std::fs::write(&temporary, serde_json::to_string(spent).unwrap())
+            .and_then(|()| std::fs::rename(&temporary, path));
+        if let Err(why) = written {
+            error!("Error writing budget file {path:?}: {why:?}");
+        }
+    }
+
+    fn ended(&self, spent: &Spent, now: DateTime<Utc>) -> bool {
+        (now - spent.since).to_std().unwrap_or_default() >= self.window
+    }
+
+    /// Ok while the server is under its budget. Otherwise returns when it
+    /// resets.
+    pub fn check(&self, guild_id: Option<GuildId>) -> Result<(), DateTime<Utc>> {
+        let (Some(max_tokens), Some(guild_id)) = (self.max_tokens, guild_id) else {
+            return Ok(());
+        };
+        let spent = self.spent.lock().unwrap();
+        match spent.get(&guild_id.get()) {
+            Some(spent) if spent.tokens >= max_tokens && !self.ended(spent, Utc::now()) => {
+                Err(spent.since
+                    + chrono::Duration::from_std(self.window).unwrap_or(chrono::Duration::zero()))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Tokens the server has left until its budget resets, if it has one.
+    pub fn remaining(&self, guild_id: Option<GuildId>) -> Option<u64> {
+        let (Some(max_tokens), Some(guild_id)) = (self.max_tokens, guild_id) else {
+            return None;
+        };
+        let spent = self.spent.lock().unwrap();
+        Some(match spent.get(&guild_id.get()) {
+            Some(spent) if !self.ended(spent, Utc::now()) => {
+                max_tokens.saturating_sub(spent.tokens)
+            }
+            _ => max_tokens,
+        })
+    }
+
+    /// Count a finished job's usage against the server's budget.
+    pub fn record(&self, guild_id: Option<GuildId>, usage: Usage) {
+        let Some(guild_id) = guild_id.filter(|_| self.max_tokens.is_some()) else {
+            return;
+        };
+        if usage.total_tokens == 0 {
+            return;
+        }
+        let now = Utc::now();
+        let mut spent = self.spent.lock().unwrap();
+        let entry = spent.entry(guild_id.get()).or_insert(Spent {
+            since: now,
+            tokens: 0,
+        });
+        if self.ended(entry, now) {
+            *entry = Spent {
+                since: now,
+                tokens: 0,
+            };
+        }
+        entry.tokens += usage.total_tokens;
+        self.save(&spent);
+    }
+}
+
+#[test]
+fn test_budgets() {
+    let usage = |total_tokens| Usage {
+        prompt_tokens: total_tokens,
+        completion_tokens: 0,
+        total_tokens,
+    };
+    let guild = Some(GuildId::new(1));
+    let budgets = Budgets::new(Some(1000), Duration::from_secs(30 * 24 * 60 * 60));
+
+    assert!(budgets.check(guild).is_ok());
+    assert_eq!(budgets.remaining(guild), Some(1000));
+    budgets.record(guild, usage(600));
+    assert!(budgets.check(guild).is_ok());
+    assert_eq!(budgets.remaining(guild), Some(400));
+    // The job that goes over still finishes; the next one is refused.
+    budgets.record(guild, usage(600));
+    let resets = budgets.check(guild).unwrap_err();
+    assert_eq!(budgets.remaining(guild), Some(0));
+    assert!(resets > Utc::now() + chrono::Duration::days(29));
+    assert!(budgets.check(Some(GuildId::new(2))).is_ok());
+    // DMs aren't counted.
+    budgets.record(None, usage(5000));
+    assert!(budgets.check(None).is_ok());
+
+    let unlimited = Budgets::new(None, Duration::ZERO);
+    unlimited.record(guild, usage(5000));
+    assert!(unlimited.check(guild).is_ok());
+    assert_eq!(unlimited.remaining(guild), None);
+
+    let resetting = Budgets::new(Some(1000), Duration::ZERO);
+    resetting.record(guild, usage(5000));
+    assert!(resetting.check(guild).is_ok());
+}
+
+#[test]
+fn test_budgets_persisted() {
+    let path = std::env::temp_dir().join(format!("leonidas-budget-{}.json", std::process::id()));
+    let guild = Some(GuildId::new(1));
+    let window = Duration::from_secs(60 * 60);
+
+    let budgets = Budgets::new(Some(1000), window).persisted(path.clone());
+    budgets.record(
+        guild,
+        Usage {
+            prompt_tokens: 1500,
+            completion_tokens: 0,
+            total_tokens: 1500,
+        },
+    );
+    let restarted = Budgets::new(Some(1000), window).persisted(path.clone());
+    assert!(restarted.check(guild).is_err());
+    std::fs::remove_file(path).unwrap();
+}
@@ -0,0 +1,487 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tracing::error;
+
+use crate::options::SummaryOptions;
+use crate::prompts;
+use crate::youtube::{Action, VideoInfo};
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct CacheKey {
+    video_id: String,
+    action: Action,
+    options_hash: u64,
+    prompt_version: u32,
+    #[serde(default)]
+    speakers: bool,
+    #[serde(default)]
+    timestamps: bool,
+    #[serde(default)]
+    paragraph_sentences: usize,
+    /// The OpenAI model that made it, so switching models doesn't keep
+    /// serving the old one's output.
+    #[serde(default)]
+    model: String,
+}
+
+impl CacheKey {
+    pub fn new(video_id: &str, action: Action, options: &SummaryOptions) -> CacheKey {
+        CacheKey {
+            video_id: video_id.to_string(),
+            action,
+            options_hash: hash_options(options),
+            prompt_version: prompts::PROMPT_VERSION,
+            speakers: false,
+            timestamps: false,
+            paragraph_sentences: 0,
+            model: String::new(),
+        }
+    }
+
+    /// Transcripts with speaker labels are kept apart from those without.
+    pub fn with_speakers(mut self, speakers: bool) -> CacheKey {
+        self.speakers = speakers;
+        self
+    }
+
+    /// As are transcripts with timestamp links.
+    pub fn with_timestamps(mut self, timestamps: bool) -> CacheKey {
+        self.timestamps = timestamps;
+        self
+    }
+
+    pub fn with_model(mut self, model: &str) -> CacheKey {
+        self.model = model.to_string();
+        self
+    }
+
+    /// And transcripts grouped into paragraphs of a different length.
+    pub fn with_paragraph_sentences(mut self, paragraph_sentences: usize) -> CacheKey {
+        self.paragraph_sentences = paragraph_sentences;
+        self
+    }
+}
+
+/// A checkpointed piece of a multi-chunk transcript cleanup. The chunk's own
+/// hash is part of the key so a changed transcript can't resume from output
+/// that was produced for different text.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct ChunkKey {
+    video_id: String,
+    index: usize,
+    chunk_hash: u64,
+    prompt_version: u32,
+    speakers: bool,
+    /// A server's own transcript prompt, from `/setprompt`.
+    system_prompt_hash: u64,
+    /// The OpenAI model that cleaned it up, so a resumed cleanup doesn't
+    /// mix two models' output.
+    model: String,
+}
+
+impl ChunkKey {
+    pub fn new(video_id: &str, index: usize, chunk: &str) -> ChunkKey {
+        ChunkKey {
+            video_id: video_id.to_string(),
+            index,
+            chunk_hash: stable_hash(chunk),
+            prompt_version: prompts::PROMPT_VERSION,
+            speakers: false,
+            system_prompt_hash: 0,
+            model: String::new(),
+        }
+    }
+
+    pub fn with_speakers(mut self, speakers: bool) -> ChunkKey {
+        self.speakers = speakers;
+        self
+    }
+
+    /// Chunks cleaned up under one server's prompt aren't reused for
+    /// another's, or after the prompt changes.
+    pub fn with_system_prompt(mut self, system_prompt: Option<&str>) -> ChunkKey {
+        self.system_prompt_hash = system_prompt.map_or(0, stable_hash);
+        self
+    }
+
+    pub fn with_model(mut self, model: &str) -> ChunkKey {
+        self.model = model.to_string();
+        self
+    }
+}
+
+/// FNV-1a. `DefaultHasher` isn't guaranteed to be stable between Rust
+/// releases, and we want the same input to always land on the same key.
+fn stable_hash(s: &str) -> u64 {
+    s.bytes().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+fn hash_options(options: &SummaryOptions) -> u64 {
+    stable_hash(&serde_json::to_string(&options.normalized()).unwrap())
+}
+
+/// How long raw transcripts stay cached. Captions on recent uploads (and
+/// livestreams) are still being generated or corrected, so they're refetched
+/// sooner than those on older videos.
+#[derive(Clone, Copy, Debug)]
+pub struct TranscriptTtl {
+    pub recent: Duration,
+    pub old: Duration,
+    /// Videos published longer ago than this count as old.
+    pub recent_threshold: Duration,
+}
+
+impl Default for TranscriptTtl {
+    fn default() -> TranscriptTtl {
+        TranscriptTtl {
+            recent: Duration::from_secs(60 * 60),
+            old: Duration::from_secs(7 * 24 * 60 * 60),
+            recent_threshold: Duration::from_secs(7 * 24 * 60 * 60),
+        }
+    }
+}
+
+impl TranscriptTtl {
+    /// Videos without a known publish date are treated as recent.
+    fn for_video(&self, info: &VideoInfo, now: DateTime<Utc>) -> Duration {
+        let age = info
+            .published_at
+            .and_then(|published_at| (now - published_at).to_std().ok());
+        match age {
+            Some(age) if age > self.recent_threshold => self.old,
+            _ => self.recent,
+        }
+    }
+}
+
+/// Finished output by key, with when it was cached.
+type Entries = HashMap<CacheKey, (String, VideoInfo, DateTime<Utc>)>;
+
+/// Finished output, as written to the cache file.
+#[derive(Serialize, Deserialize)]
+struct CachedEntry {
+    key: CacheKey,
+    output: String,
+    info: VideoInfo,
+    cached_at: DateTime<Utc>,
+}
+
+struct CachedTranscript {
+    transcript: String,
+    info: VideoInfo,
+    fetched_at: Instant,
+    ttl: Duration,
+}
+
+pub struct Cache {
+    entries: Mutex<Entries>,
+    /// Output is regenerated after this long, e.g. to pick up edited titles.
+    entry_ttl: Duration,
+    /// The oldest output is dropped to make room beyond this many entries.
+    max_entries: usize,
+    /// Where finished output is written so it survives restarts, if anywhere.
+    path: Option<PathBuf>,
+    chunks: Mutex<HashMap<ChunkKey, String>>,
+    /// Raw transcripts by video id, kept around for follow-up questions.
+    transcripts: Mutex<HashMap<String, CachedTranscript>>,
+    transcript_ttl: TranscriptTtl,
+}
+
+impl Cache {
+    pub fn new(entry_ttl: Duration, transcript_ttl: TranscriptTtl, max_entries: usize) -> Cache {
+        Cache {
+            entries: Mutex::default(),
+            entry_ttl,
+            max_entries,
+            path: None,
+            chunks: Mutex::default(),
+            transcripts: Mutex::default(),
+            transcript_ttl,
+        }
+    }
+
+    /// Keep finished output in the JSON file at `path`, starting with what's
+    /// already there. A missing or corrupt file just means an empty cache.
+    pub fn persisted(mut self, path: PathBuf) -> Cache {
+        let cached = match std::fs::read_to_string(&path) {
+            Ok(contents) => serde_json::from_str(&contents).unwrap_or_else(|why| {
+                error!("Error parsing cache file {path:?}: {why:?}");
+                Vec::new()
+            }),
+            Err(_) => Vec::new(),
+        };
+        let now = Utc::now();
+        let mut entries = self.entries.lock().unwrap();
+        for CachedEntry {
+            key,
+            output,
+            info,
+            cached_at,
+        } in cached
+        {
+            if !expired(cached_at, self.entry_ttl, now) {
+                entries.insert(key, (output, info, cached_at));
+            }
+        }
+        evict(&mut entries, self.max_entries);
+        drop(entries);
+        self.path = Some(path);
+        self
+    }
+
+    fn save(&self, entries: &Entries) {
+        let Some(path) = &self.path else {
+            return;
+        };
+        let cached = entries
+            .iter()
+            .map(|(key, (output, info, cached_at))| CachedEntry {
+                key: key.clone(),
+                output: output.clone(),
+                info: info.clone(),
+                cached_at: *cached_at,
+            })
+            .collect::<Vec<_>>();
+        // Written next to it first, so a crash mid-write can't corrupt it.
+        let temporary = path.with_extension("tmp");
+        let written = std::fs::write(&temporary, serde_json::to_string(&cached).unwrap())
+            .and_then(|()| std::fs::rename(&temporary, path));
+        if let Err(why) = written {
+            error!("Error writing cache file {path:?}: {why:?}");
+        }
+    }
+
+    pub fn get(&self, key: &CacheKey) -> Option<(String, VideoInfo)> {
+        let mut entries = self.entries.lock().unwrap();
+        let (output, info, cached_at) = entries.get(key)?;
+        if expired(*cached_at, self.entry_ttl, Utc::now()) {
+            entries.remove(key);
+            return None;
+        }
+        Some((output.clone(), info.clone()))
+    }
+
+    pub fn insert(&self, key: CacheKey, (output, info): (String, VideoInfo)) {
+        let mut entries = self.entries.lock().unwrap();
+        entries.insert(key, (output, info, Utc::now()));
+        evict(&mut entries, self.max_entries);
+        self.save(&entries);
+    }
+
+    /// How many finished summaries and transcripts are cached.
+    pub fn entry_count(&self) -> usize {
+        self.entries.lock().unwrap().len()
+    }
+
+    pub fn remove(&self, key: &CacheKey) {
+        let mut entries = self.entries.lock().unwrap();
+        if entries.remove(key).is_some() {
+            self.save(&entries);
+        }
+    }
+
+    pub fn get_chunk(&self, key: &ChunkKey) -> Option<String> {
+        self.chunks.lock().unwrap().get(key).cloned()
+    }
+
+    pub fn insert_chunk(&self, key: ChunkKey, value: String) {
+        self.chunks.lock().unwrap().insert(key, value);
+    }
+
+    /// Expired transcripts are dropped, so the caller refetches them.
+    pub fn get_transcript(&self, video_id: &str) -> Option<(String, VideoInfo)> {
+        let mut transcripts = self.transcripts.lock().unwrap();
+        let cached = transcripts.get(video_id)?;
+        if cached.fetched_at.elapsed() > cached.ttl {
+            transcripts.remove(video_id);
+            return None;
+        }
+        Some((cached.transcript.clone(), cached.info.clone()))
+    }
+
+    pub fn insert_transcript(&self, video_id: &str, (transcript, info): (String, VideoInfo)) {
+        let ttl = self.transcript_ttl.for_video(&info, Utc::now());
+        self.transcripts.lock().unwrap().insert(
+            video_id.to_string(),
+            CachedTranscript {
+                transcript,
+                info,
+                fetched_at: Instant::now(),
+                ttl,
+            },
+        );
+    }
+
+    /// Checkpoints are only needed until the whole job has been cached.
+    pub fn clear_chunks(&self, video_id: &str) {
+        self.chunks
+            .lock()
+            .unwrap()
+            .retain(|key, _| key.video_id != video_id);
+    }
+}
+
+fn expired(cached_at: DateTime<Utc>, ttl: Duration, now: DateTime<Utc>) -> bool {
+    (now - cached_at).to_std().is_ok_and(|age| age > ttl)
+}
+
+/// Drop the oldest output until there are at most `max_entries`.
+fn evict(entries: &mut Entries, max_entries: usize) {
+    while entries.len() > max_entries {
+        let Some(oldest) = entries
+            .iter()
+            .min_by_key(|(_, (_, _, cached_at))| *cached_at)
+            .map(|(key, _)| key.clone())
+        else {
+            return;
+        };
+        entries.remove(&oldest);
+    }
+}
+
+#[test]
+fn test_cache_key_options() {
+    use crate::options::SummaryLength;
+
+    let default = CacheKey::new("dQw4w9WgXcQ", Action::Summarize, &SummaryOptions::default());
+    let medium = CacheKey::new(
+        "dQw4w9WgXcQ",
+        Action::Summarize,
+        &SummaryOptions {
+            length: Some(SummaryLength::Medium),
+            language: Some(" ".to_string()),
+            portion: None,
+            style: None,
+            system_prompt: None,
+        },
+    );
+    let long_french = CacheKey::new(
+        "dQw4w9WgXcQ",
+        Action::Summarize,
+        &SummaryOptions {
+            length: Some(SummaryLength::Long),
+            language: Some("FR".to_string()),
+            portion: None,
+            style: None,
+            system_prompt: None,
+        },
+    );
+
+    assert_eq!(default, medium);
+    assert_ne!(default, long_french);
+    assert_ne!(
+        default,
+        CacheKey::new(
+            "dQw4w9WgXcQ",
+            Action::Transcribe,
+            &SummaryOptions::default()
+        )
+    );
+}
+
+#[test]
+fn test_chunk_key() {
+    let key = ChunkKey::new("dQw4w9WgXcQ", 0, "some words");
+    assert_eq!(key, key.clone().with_system_prompt(None));
+    assert_ne!(key, key.clone().with_system_prompt(Some("Be formal.")));
+    assert_ne!(
+        key.clone().with_system_prompt(Some("Be formal.")),
+        key.clone().with_system_prompt(Some("Be casual."))
+    );
+    assert_ne!(
+        key.clone().with_model("gpt-4"),
+        key.with_model("gpt-3.5-turbo")
+    );
+}
+
+#[test]
+fn test_cache_entry_ttl() {
+    let info = VideoInfo {
+        title: "A title".to_string(),
+        channel_name: "A channel".to_string(),
+        published_at: None,
+        description: String::new(),
+    };
+    let key = || CacheKey::new("dQw4w9WgXcQ", Action::Summarize, &SummaryOptions::default());
+
+    let cache = Cache::new(Duration::from_secs(60), TranscriptTtl::default(), 10);
+    cache.insert(key(), ("A summary".to_string(), info.clone()));
+    assert_eq!(
+        cache.get(&key()).map(|(output, _)| output),
+        Some("A summary".to_string())
+    );
+
+    let cache = Cache::new(Duration::ZERO, TranscriptTtl::default(), 10);
+    cache.insert(key(), ("A summary".to_string(), info));
+    std::thread::sleep(Duration::from_millis(1));
+    assert!(cache.get(&key()).is_none());
+}
+
+#[test]
+fn test_cache_persisted() {
+    let info = VideoInfo {
+        title: "A title".to_string(),
+        channel_name: "A channel".to_string(),
+        published_at: None,
+        description: String::new(),
+    };
+    let key = |video_id| CacheKey::new(video_id, Action::Summarize, &SummaryOptions::default());
+    let path = std::env::temp_dir().join(format!("leonidas-cache-{}.json", std::process::id()));
+    let cache =
+        || Cache::new(Duration::from_secs(60), TranscriptTtl::default(), 2).persisted(path.clone());
+
+    let first = cache();
+    for video_id in ["first", "second", "third"] {
+        first.insert(
+            key(video_id),
+            (format!("A summary of {video_id}"), info.clone()),
+        );
+    }
+
+    // Only the newest two are kept, and they're still there after a restart.
+    let restarted = cache();
+    assert!(restarted.get(&key("first")).is_none());
+    assert_eq!(
+        restarted.get(&key("third")).map(|(output, _)| output),
+        Some("A summary of third".to_string())
+    );
+
+    std::fs::write(&path, "not json").unwrap();
+    assert!(cache().get(&key("third")).is_none());
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_transcript_ttl() {
+    let ttl = TranscriptTtl::default();
+    let now = DateTime::parse_from_rfc3339("2023-11-01T00:00:00Z")
+        .unwrap()
+        .with_timezone(&Utc);
+    let info = |published_at: Option<&str>| VideoInfo {
+        title: "A title".to_string(),
+        channel_name: "A channel".to_string(),
+        published_at: published_at.map(|published_at| {
+            DateTime::parse_from_rfc3339(published_at)
+                .unwrap()
+                .with_timezone(&Utc)
+        }),
+        description: String::new(),
+    };
+
+    assert_eq!(
+        ttl.for_video(&info(Some("2023-10-31T00:00:00Z")), now),
+        ttl.recent
+    );
+    assert_eq!(
+        ttl.for_video(&info(Some("2020-01-01T00:00:00Z")), now),
+        ttl.old
+    );
+    assert_eq!(ttl.for_video(&info(None), now), ttl.recent);
+}
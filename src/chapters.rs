@@ -0,0 +1,152 @@
+//! Chapter markers from video descriptions, the `0:00 Intro` lines YouTube
+//! turns into chapters.
+
+use regex::Regex;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Chapter {
+    /// Seconds from the start of the video.
+    pub start: u32,
+    pub title: String,
+}
+
+/// Characters trimmed from around a chapter title once its timestamps are
+/// removed: bullets, brackets and the separators people put between the
+/// time and the title.
+const TITLE_TRIM: &[char] = &[
+    '-', '–', '—', '|', ':', '.', ',', '•', '·', '*', '>', '▶', '►', '~', '[', ']', '(', ')',
+];
+
+fn timestamp_regex() -> Regex {
+    Regex::new(r"\b(?:(\d{1,2}):)?(\d{1,2}):(\d{2})\b").unwrap()
+}
+
+/// `1:02:03` → 3723. `None` for things that look like times but can't be,
+/// e.g. `1:75`.
+fn parse_timestamp(captures: &regex::Captures) -> Option<u32> {
+    let number = |index| {
+        captures
+            .get(index)
+            .map(|m| m.as_str().parse::<u32>().unwrap())
+    };
+    let (hours, minutes, seconds) = (number(1), number(2)?, number(3)?);
+    if seconds >= 60 || (hours.is_some() && minutes >= 60) {
+        return None;
+    }
+    Some(hours.unwrap_or(0) * 3600 + minutes * 60 + seconds)
+}
+
+/// Each line in the list becomes a chapter starting at the first timestamp
+/// on it, titled with what's left of the line. Like YouTube, the list has to
+/// start at `0:00` and go in increasing order, and it ends at the first line
+/// without a timestamp, so stray times elsewhere in the description
+/// (`Live at 8:00!`) aren't picked up.
+pub fn parse_chapters(description: &str) -> Vec<Chapter> {
+    let regex = timestamp_regex();
+    let mut chapters: Vec<Chapter> = Vec::new();
+    for line in description.lines() {
+        let Some(start) = regex
+            .captures(line)
+            .and_then(|captures| parse_timestamp(&captures))
+        else {
+            if chapters.is_empty() {
+                continue;
+            }
+            break;
+        };
+        if chapters.is_empty() && start != 0 {
+            continue;
+        }
+        let title = regex.replace_all(line, "");
+        let title = title
+            .trim_matches(|c: char| c.is_whitespace() || TITLE_TRIM.contains(&c))
+            .split_whitespace()
+            .collect::<Vec<_>>()
+            .join(" ");
+        if title.is_empty() {
+            continue;
+        }
+        if chapters.last().is_some_and(|last| last.start >= start) {
+            continue;
+        }
+        chapters.push(Chapter { start, title });
+    }
+    chapters
+}
+
+#[test]
+fn test_parse_timestamp() {
+    let regex = timestamp_regex();
+    let parse = |text: &str| parse_timestamp(&regex.captures(text).unwrap());
+    assert_eq!(parse("0:00"), Some(0));
+    assert_eq!(parse("00:00"), Some(0));
+    assert_eq!(parse("12:34"), Some(754));
+    assert_eq!(parse("1:02:03"), Some(3723));
+    assert_eq!(parse("01:02:03"), Some(3723));
+    assert_eq!(parse("1:75"), None);
+    assert_eq!(parse("1:75:00"), None);
+}
+
+#[test]
+fn test_parse_chapters() {
+    let chapter = |start, title: &str| Chapter {
+        start,
+        title: title.to_string(),
+    };
+
+    assert_eq!(
+        parse_chapters("0:00 Intro\n1:30 - The problem\n12:05 – Solutions\n1:02:03 | Outro"),
+        vec![
+            chapter(0, "Intro"),
+            chapter(90, "The problem"),
+            chapter(725, "Solutions"),
+            chapter(3723, "Outro"),
+        ]
+    );
+    // Timestamps after the title, in brackets, or as ranges.
+    assert_eq!(
+        parse_chapters("Intro (0:00)\nSetup [02:15]\n• 05:00 - 07:30: Benchmarks\nWrap up - 10:00"),
+        vec![
+            chapter(0, "Intro"),
+            chapter(135, "Setup"),
+            chapter(300, "Benchmarks"),
+            chapter(600, "Wrap up"),
+        ]
+    );
+}
+
+#[test]
+fn test_parse_chapters_messy_description() {
+    let description = "\
+Thanks for watching! Sponsored by Example, get 10% off at example.com
+
+Timestamps:
+00:00 Intro
+00:45 Why this matters
+03:12 — Part 1: the setup
+3:00 (oops, out of order)
+10:00
+
+Join the stream live at 19:00 on Fridays!
+Music: Some Track by Some Artist";
+
+    assert_eq!(
+        parse_chapters(description),
+        vec![
+            Chapter {
+                start: 0,
+                title: "Intro".to_string()
+            },
+            Chapter {
+                start: 45,
+                title: "Why this matters".to_string()
+            },
+            Chapter {
+                start: 192,
+                title: "Part 1: the setup".to_string()
+            },
+        ]
+    );
+    assert_eq!(parse_chapters("No chapters here.\nJust text."), vec![]);
+    assert_eq!(parse_chapters("Live at 8:00!\n9:00 Doors open"), vec![]);
+}
@@ -0,0 +1,109 @@
+use leonidas::{cache, options, sources, youtube};
+
+use crate::config::Config;
+
+/// Running the pipeline once from the terminal, e.g.
+/// `leonidas summarize <url> length:short`, instead of starting the bot.
+#[derive(Debug, PartialEq)]
+pub enum CliCommand {
+    /// The video, and any options after the link, like in a message.
+    Summarize(sources::Video, String),
+    Transcribe(sources::Video, String),
+}
+
+pub const USAGE: &str = "Usage: leonidas [summarize|transcribe <url> [options]]";
+
+impl CliCommand {
+    /// `None` without a subcommand, meaning the bot should start.
+    pub fn parse(args: &[String]) -> Result<Option<CliCommand>, String> {
+        let Some((subcommand, rest)) = args.split_first() else {
+            return Ok(None);
+        };
+        let Some((url, options)) = rest.split_first() else {
+            return Err(USAGE.to_string());
+        };
+        let video = sources::find(url).ok_or_else(|| format!("{url} isn't a YouTube link."))?;
+        let options = options.join(" ");
+        match subcommand.as_str() {
+            "summarize" => Ok(Some(CliCommand::Summarize(video, options))),
+            "transcribe" => Ok(Some(CliCommand::Transcribe(video, options))),
+            _ => Err(USAGE.to_string()),
+        }
+    }
+}
+
+/// Print the summary or transcript as markdown, with what it took on
+/// stderr when `SHOW_USAGE=true`. Returns the exit code.
+pub async fn run(command: CliCommand, config: &Config) -> i32 {
+    let cache = cache::Cache::new(
+        config.cache_ttl,
+        config.transcript_ttl,
+        config.cache_max_entries,
+    );
+    let cache = match &config.cache_dir {
+        Some(cache_dir) => cache.persisted(cache_dir.join("cache.json")),
+        None => cache,
+    };
+    let result = match &command {
+        CliCommand::Summarize(video, text) => {
+            let options = options::resolve(
+                options::SummaryOptions::parse(text),
+                None,
+                &config.default_options,
+            );
+            video
+                .source
+                .summary(
+                    &video.id,
+                    &options,
+                    &cache,
+                    config.describe_no_speech,
+                    &config.model,
+                    None,
+                )
+                .await
+        }
+        CliCommand::Transcribe(video, text) => {
+            let options = youtube::TranscriptOptions {
+                language: options::transcript_language(text),
+                ..config.transcript_options()
+            };
+            video.source.transcript(&video.id, &cache, &options).await
+        }
+    };
+    match result {
+        Ok(((content, info), usage)) => {
+            println!("# {}\n\n*{}*\n\n{content}", info.title, info.channel_name);
+            if config.show_usage && usage.total_tokens > 0 {
+                eprintln!("{}", usage.describe(config.cost_per_1k_tokens));
+            }
+            0
+        }
+        Err(why) => {
+            eprintln!("{why}");
+            1
+        }
+    }
+}
+
+#[test]
+fn test_parse() {
+    let args = |args: &[&str]| args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+    let video = sources::Video::new(&youtube::YouTube, "dQw4w9WgXcQ".to_string());
+    assert_eq!(CliCommand::parse(&[]), Ok(None));
+    assert_eq!(
+        CliCommand::parse(&args(&["summarize", "https://youtu.be/dQw4w9WgXcQ"])),
+        Ok(Some(CliCommand::Summarize(video.clone(), String::new())))
+    );
+    assert_eq!(
+        CliCommand::parse(&args(&[
+            "transcribe",
+            "https://www.youtube.com/watch?v=dQw4w9WgXcQ",
+            "lang:es"
+        ])),
+        Ok(Some(CliCommand::Transcribe(video, "lang:es".to_string())))
+    );
+    assert!(CliCommand::parse(&args(&["summarize"])).is_err());
+    assert!(CliCommand::parse(&args(&["summarize", "https://example.com"])).is_err());
+    assert!(CliCommand::parse(&args(&["explain", "https://youtu.be/dQw4w9WgXcQ"])).is_err());
+}
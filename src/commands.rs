@@ -0,0 +1,447 @@
+use serenity::all::{
+    CommandInteraction, CommandOptionType, CommandType, CreateCommand, CreateCommandOption,
+    CreateInteractionResponse, CreateInteractionResponseMessage, EditInteractionResponse, GuildId,
+    Permissions, ResolvedValue,
+};
+use serenity::prelude::*;
+use tracing::error;
+
+use crate::config::Config;
+use crate::options::{SummaryLength, SummaryOptions};
+use crate::prompts;
+use crate::settings::{Delivery, PromptKind, Settings};
+use crate::triggers::{Trigger, TriggerEmoji};
+use crate::youtube;
+
+/// Names of the message commands, shown in a message's Apps menu.
+pub const SUMMARIZE_MESSAGE: &str = "Summarize video";
+pub const TRANSCRIBE_MESSAGE: &str = "Transcribe video";
+
+/// Every slash and message command the bot registers on `ready`.
+pub fn commands() -> Vec<CreateCommand> {
+    vec![
+        CreateCommand::new("defaults")
+            .description("Set the default summary options for this channel")
+            .default_member_permissions(Permissions::MANAGE_CHANNELS)
+            .dm_permission(false)
+            .add_option(
+                CreateCommandOption::new(CommandOptionType::String, "length", "Summary length")
+                    .add_string_choice("short", "short")
+                    .add_string_choice("medium", "medium")
+                    .add_string_choice("long", "long"),
+            )
+            .add_option(CreateCommandOption::new(
+                CommandOptionType::String,
+                "language",
+                "Language code to write summaries in, e.g. `es`",
+            ))
+            .add_option(CreateCommandOption::new(
+                CommandOptionType::Boolean,
+                "clear",
+                "Remove this channel's defaults",
+            )),
+        CreateCommand::new("enable")
+            .description("Let the bot summarize videos in this server")
+            .default_member_permissions(Permissions::MANAGE_GUILD)
+            .dm_permission(false),
+        CreateCommand::new("disable")
+            .description("Stop the bot from summarizing videos in this server")
+            .default_member_permissions(Permissions::MANAGE_GUILD)
+            .dm_permission(false),
+        CreateCommand::new("emoji")
+            .description("Change the emoji people react with to summarize or transcribe")
+            .default_member_permissions(Permissions::MANAGE_GUILD)
+            .dm_permission(false)
+            .add_option(
+                CreateCommandOption::new(CommandOptionType::String, "action", "Action")
+                    .required(true)
+                    .add_string_choice("summarize", "summarize")
+                    .add_string_choice("transcribe", "transcribe")
+                    .add_string_choice("bullets", "bullets")
+                    .add_string_choice("notes", "notes"),
+            )
+            .add_option(CreateCommandOption::new(
+                CommandOptionType::String,
+                "emoji",
+                "A Unicode or server emoji; leave out to go back to the default",
+            )),
+        CreateCommand::new("setprompt")
+            .description("Change the instructions summaries or transcripts are written with")
+            .default_member_permissions(Permissions::ADMINISTRATOR)
+            .dm_permission(false)
+            .add_option(
+                CreateCommandOption::new(CommandOptionType::String, "kind", "Which prompt")
+                    .required(true)
+                    .add_string_choice("summary", "summary")
+                    .add_string_choice("transcript", "transcript"),
+            )
+            .add_option(CreateCommandOption::new(
+                CommandOptionType::String,
+                "prompt",
+                "Summaries can use {goal_length}, {language} and {source_language}; leave out to go back to the default",
+            )),
+        CreateCommand::new("prefs")
+            .description("Choose where your summaries and transcripts are posted")
+            .add_option(
+                CreateCommandOption::new(
+                    CommandOptionType::String,
+                    "delivery",
+                    "Where to post them",
+                )
+                .add_string_choice("channel", "channel")
+                .add_string_choice("thread", "thread")
+                .add_string_choice("dm", "dm"),
+            ),
+        CreateCommand::new("stats")
+            .description("Show cache, usage and budget figures")
+            .default_member_permissions(Permissions::ADMINISTRATOR)
+            .dm_permission(false),
+        CreateCommand::new("guild")
+            .description("Enable or disable the bot in any server (bot owner only)")
+            .default_member_permissions(Permissions::ADMINISTRATOR)
+            .add_option(
+                CreateCommandOption::new(CommandOptionType::String, "id", "Server id")
+                    .required(true),
+            )
+            .add_option(
+                CreateCommandOption::new(CommandOptionType::Boolean, "enabled", "Enabled")
+                    .required(true),
+            ),
+        CreateCommand::new("summarize")
+            .description("Summarize a YouTube video")
+            .add_option(
+                CreateCommandOption::new(CommandOptionType::String, "url", "YouTube link")
+                    .required(true),
+            )
+            .add_option(CreateCommandOption::new(
+                CommandOptionType::String,
+                "portion",
+                "Only summarize part of the video, e.g. `first-half`, `last-third` or `25%-50%`",
+            ))
+            .add_option(
+                CreateCommandOption::new(CommandOptionType::String, "length", "Summary length")
+                    .add_string_choice("short", "short")
+                    .add_string_choice("medium", "medium")
+                    .add_string_choice("long", "long"),
+            )
+            .add_option(CreateCommandOption::new(
+                CommandOptionType::String,
+                "language",
+                "Language code to write the summary in, e.g. `es`",
+            )),
+        CreateCommand::new("transcribe")
+            .description("Post a cleaned-up transcript of a YouTube video")
+            .add_option(
+                CreateCommandOption::new(CommandOptionType::String, "url", "YouTube link")
+                    .required(true),
+            )
+            .add_option(CreateCommandOption::new(
+                CommandOptionType::String,
+                "language",
+                "Language code of the captions to use, e.g. `es`",
+            )),
+        CreateCommand::new("selftest")
+            .description("Run the summary pipeline on a test video (bot owner only)")
+            .default_member_permissions(Permissions::ADMINISTRATOR),
+        CreateCommand::new("raw")
+            .description("Post a video's auto-generated transcript without cleanup")
+            .add_option(
+                CreateCommandOption::new(CommandOptionType::String, "url", "YouTube link")
+                    .required(true),
+            ),
+        CreateCommand::new("estimate")
+            .description("How many tokens summarizing and transcribing a video would take")
+            .add_option(
+                CreateCommandOption::new(CommandOptionType::String, "url", "YouTube link")
+                    .required(true),
+            ),
+        CreateCommand::new(SUMMARIZE_MESSAGE).kind(CommandType::Message),
+        CreateCommand::new(TRANSCRIBE_MESSAGE).kind(CommandType::Message),
+    ]
+}
+
+pub async fn respond(ctx: &Context, command: &CommandInteraction, content: String) {
+    let response = CreateInteractionResponse::Message(
+        CreateInteractionResponseMessage::new()
+            .content(content)
+            .ephemeral(true),
+    );
+    if let Err(why) = command.create_response(&ctx.http, response).await {
+        error!("Error responding to command: {:?}", why);
+    }
+}
+
+/// Replace a deferred response's "thinking" placeholder.
+pub async fn edit_response(ctx: &Context, command: &CommandInteraction, content: String) {
+    if let Err(why) = command
+        .edit_response(&ctx.http, EditInteractionResponse::new().content(content))
+        .await
+    {
+        error!("Error responding to command: {:?}", why);
+    }
+}
+
+pub fn string_option(command: &CommandInteraction, name: &str) -> Option<String> {
+    command
+        .data
+        .options()
+        .into_iter()
+        .find_map(|option| match option.value {
+            ResolvedValue::String(value) if option.name == name => Some(value.to_string()),
+            _ => None,
+        })
+}
+
+fn describe_options(options: &SummaryOptions) -> String {
+    let length = options
+        .length
+        .map(|length| match length {
+            SummaryLength::Words(words) => format!("{words} words"),
+            length => format!("{length:?}").to_lowercase(),
+        })
+        .unwrap_or("unset".to_string());
+    let language = options.language.as_deref().unwrap_or("unset");
+    format!("length: `{length}`, language: `{language}`")
+}
+
+/// `/defaults`: with no arguments, show the channel's defaults. Otherwise
+/// update them, keeping any option that wasn't passed.
+pub async fn defaults(ctx: &Context, command: &CommandInteraction, settings: &Settings) {
+    let mut options = SummaryOptions::default();
+    let mut clear = false;
+    let mut changed = false;
+    for option in command.data.options() {
+        match (option.name, option.value) {
+            ("length", ResolvedValue::String(length)) => {
+                options.length = SummaryLength::parse(length);
+                changed = true;
+            }
+            ("language", ResolvedValue::String(language)) => {
+                options.language = Some(language.to_string());
+                changed = true;
+            }
+            ("clear", ResolvedValue::Boolean(value)) => clear = value,
+            _ => {}
+        }
+    }
+
+    let current = settings.channel_defaults(command.channel_id);
+    let content = if clear {
+        settings.set_channel_defaults(command.channel_id, None);
+        "Cleared this channel's summary defaults.".to_string()
+    } else if changed {
+        let options = match &current {
+            Some(current) => options.or(current),
+            None => options,
+        };
+        let content = format!(
+            "Summary defaults for this channel: {}",
+            describe_options(&options)
+        );
+        settings.set_channel_defaults(command.channel_id, Some(options));
+        content
+    } else {
+        match current {
+            Some(current) => format!(
+                "Summary defaults for this channel: {}",
+                describe_options(&current)
+            ),
+            None => "This channel has no summary defaults.".to_string(),
+        }
+    };
+    respond(ctx, command, content).await;
+}
+
+/// `/prefs [delivery:<channel|thread|dm>]`: with no arguments, show where the
+/// user's output goes. Otherwise change it.
+pub async fn prefs(ctx: &Context, command: &CommandInteraction, settings: &Settings) {
+    let delivery = match string_option(command, "delivery") {
+        Some(delivery) => {
+            let Some(delivery) = Delivery::parse(&delivery) else {
+                respond(ctx, command, format!("`{delivery}` isn't a place to post.")).await;
+                return;
+            };
+            settings.set_delivery(command.user.id, delivery);
+            delivery
+        }
+        None => settings.delivery(command.user.id),
+    };
+    let content = match delivery {
+        Delivery::Channel => "Your summaries and transcripts are posted in the channel.",
+        Delivery::Thread => "Your summaries and transcripts are posted in a thread.",
+        Delivery::Dm => "Your summaries and transcripts are sent to you in a DM.",
+    };
+    respond(ctx, command, content.to_string()).await;
+}
+
+/// `/enable` and `/disable` for the server the command was run in.
+pub async fn set_enabled(
+    ctx: &Context,
+    command: &CommandInteraction,
+    settings: &Settings,
+    enabled: bool,
+) {
+    let Some(guild_id) = command.guild_id else {
+        respond(ctx, command, "This only works in a server.".to_string()).await;
+        return;
+    };
+    settings.set_guild_enabled(guild_id, enabled);
+    let content = if enabled {
+        "Enabled for this server."
+    } else {
+        "Disabled for this server."
+    };
+    respond(ctx, command, content.to_string()).await;
+}
+
+/// `/emoji action:<action> [emoji:<emoji>]` for the server the command was
+/// run in.
+pub async fn emoji(
+    ctx: &Context,
+    command: &CommandInteraction,
+    settings: &Settings,
+    config: &Config,
+) {
+    let Some(guild_id) = command.guild_id else {
+        respond(ctx, command, "This only works in a server.".to_string()).await;
+        return;
+    };
+    let trigger = match string_option(command, "action").as_deref() {
+        Some("transcribe") => Trigger::Transcribe,
+        Some("bullets") => Trigger::Bullets,
+        Some("notes") => Trigger::Notes,
+        _ => Trigger::Summarize,
+    };
+    let emoji = match string_option(command, "emoji") {
+        Some(emoji) => match TriggerEmoji::parse(&emoji) {
+            Some(emoji) => Some(emoji),
+            None => {
+                respond(ctx, command, format!("`{emoji}` isn't an emoji.")).await;
+                return;
+            }
+        },
+        None => None,
+    };
+
+    settings.set_trigger(guild_id, trigger, emoji);
+    let triggers = settings.triggers(Some(guild_id), &config.triggers);
+    respond(
+        ctx,
+        command,
+        format!(
+            "React with {} to summarize, {} for bullet points, {} for a summary with key takeaways and {} to transcribe.",
+            triggers.summarize.display(),
+            triggers.bullets.display(),
+            triggers.notes.display(),
+            triggers.transcribe.display()
+        ),
+    )
+    .await;
+}
+
+/// `/setprompt kind:<summary|transcript> [prompt:<text>]` for the server the
+/// command was run in. Leaving out the prompt goes back to the built-in one.
+pub async fn setprompt(
+    ctx: &Context,
+    command: &CommandInteraction,
+    settings: &Settings,
+    config: &Config,
+) {
+    let Some(guild_id) = command.guild_id else {
+        respond(ctx, command, "This only works in a server.".to_string()).await;
+        return;
+    };
+    let Some(kind) = string_option(command, "kind").and_then(|kind| PromptKind::parse(&kind))
+    else {
+        respond(ctx, command, "That isn't a kind of prompt.".to_string()).await;
+        return;
+    };
+    let prompt = string_option(command, "prompt");
+    if let Some(prompt) = &prompt {
+        let placeholders: &[&str] = match kind {
+            PromptKind::Summary => &prompts::SUMMARY_PLACEHOLDERS,
+            PromptKind::Transcript => &[],
+        };
+        if let Err(why) = prompts::check_template(prompt, placeholders, &config.model) {
+            respond(ctx, command, why).await;
+            return;
+        }
+    }
+
+    let content = match (kind, prompt.is_some()) {
+        (PromptKind::Summary, true) => "Summaries in this server are now written with that prompt.",
+        (PromptKind::Transcript, true) => {
+            "Transcripts in this server are now cleaned up with that prompt."
+        }
+        (PromptKind::Summary, false) => "Summaries are back to the default prompt.",
+        (PromptKind::Transcript, false) => "Transcripts are back to the default prompt.",
+    };
+    settings.set_prompt(guild_id, kind, prompt);
+    respond(ctx, command, content.to_string()).await;
+}
+
+/// `/guild id:<id> enabled:<bool>`, restricted to `Config::owner_id`.
+pub async fn guild(
+    ctx: &Context,
+    command: &CommandInteraction,
+    settings: &Settings,
+    config: &Config,
+) {
+    if config.owner_id != Some(command.user.id) {
+        respond(ctx, command, "Only the bot owner can do that.".to_string()).await;
+        return;
+    }
+
+    let mut guild_id = None;
+    let mut enabled = None;
+    for option in command.data.options() {
+        match (option.name, option.value) {
+            ("id", ResolvedValue::String(id)) => {
+                guild_id = id.trim().parse::<u64>().ok().filter(|id| *id != 0)
+            }
+            ("enabled", ResolvedValue::Boolean(value)) => enabled = Some(value),
+            _ => {}
+        }
+    }
+
+    let content = match (guild_id, enabled) {
+        (Some(guild_id), Some(enabled)) => {
+            settings.set_guild_enabled(GuildId::new(guild_id), enabled);
+            format!(
+                "{} server `{guild_id}`.",
+                if enabled { "Enabled" } else { "Disabled" }
+            )
+        }
+        _ => "Expected a numeric server id.".to_string(),
+    };
+    respond(ctx, command, content).await;
+}
+
+/// `/selftest`, restricted to `Config::owner_id`. Checks that the YouTube
+/// API, transcript API and OpenAI are all reachable from where we're deployed.
+pub async fn selftest(ctx: &Context, command: &CommandInteraction, config: &Config) {
+    if config.owner_id != Some(command.user.id) {
+        respond(ctx, command, "Only the bot owner can do that.".to_string()).await;
+        return;
+    }
+
+    if let Err(why) = command.defer_ephemeral(&ctx.http).await {
+        error!("Error deferring command: {:?}", why);
+        return;
+    }
+    let timings = youtube::self_test(&config.selftest_video_id, &config.model).await;
+    let passed = timings.len() == 3 && timings.iter().all(|timing| timing.result.is_ok());
+    let mut content = format!(
+        "Self-test on `{}` {}\n",
+        config.selftest_video_id,
+        if passed { "passed" } else { "failed" }
+    );
+    for timing in timings {
+        let line = match timing.result {
+            Ok(()) => format!("✅ {}: {:.2?}\n", timing.stage, timing.elapsed),
+            Err(why) => format!("❌ {}: {:.2?}\n```{why}```\n", timing.stage, timing.elapsed),
+        };
+        content.push_str(&line);
+    }
+    edit_response(ctx, command, content).await;
+}
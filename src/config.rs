@@ -0,0 +1,666 @@
+use std::collections::HashSet;
+use std::env;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use serde::Deserialize;
+use serenity::all::{ChannelId, UserId};
+
+use crate::cache::TranscriptTtl;
+use crate::embed;
+use crate::options::SummaryOptions;
+use crate::prompts;
+use crate::triggers::{
+    TriggerEmoji, Triggers, BULLETS_EMOJI, NOTES_EMOJI, SUMMARIZE_EMOJI, TRANSCRIBE_EMOJI,
+};
+use crate::webhook::WebhookConfig;
+use crate::youtube::{self, PartialTranscripts, RetryPolicy, TranscriptOptions};
+
+fn env_var(name: &str) -> Option<String> {
+    for (key, value) in env::vars() {
+        if key == name {
+            return Some(value);
+        }
+    }
+    None
+}
+
+/// The settings `leonidas.toml` can have, each named after its environment
+/// variable in lower case, e.g. `openai_model = "gpt-4"` for `OPENAI_MODEL`.
+/// A misspelled or unknown setting is an error rather than ignored.
+#[derive(Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FileConfig {
+    activity: Option<String>,
+    allowed_channels: Option<Vec<u64>>,
+    announce_channel_id: Option<u64>,
+    bullets_emoji: Option<String>,
+    cache_dir: Option<String>,
+    cache_max_entries: Option<u64>,
+    cache_ttl_secs: Option<u64>,
+    cost_per_1k_tokens: Option<f64>,
+    default_summary_options: Option<String>,
+    describe_no_speech: Option<bool>,
+    discord_token: Option<String>,
+    embed_chunk_chars: Option<u64>,
+    file_threshold_chars: Option<u64>,
+    guilds_enabled_by_default: Option<bool>,
+    guild_budget_days: Option<u64>,
+    guild_dollar_budget: Option<f64>,
+    guild_token_budget: Option<u64>,
+    health_port: Option<u64>,
+    http_timeout_secs: Option<u64>,
+    ignore_bots: Option<String>,
+    job_timeout_secs: Option<u64>,
+    max_follow_ups: Option<u64>,
+    max_playlist_videos: Option<u64>,
+    max_videos_per_message: Option<u64>,
+    min_summary_words: Option<u64>,
+    multi_video_errors: Option<String>,
+    notes_emoji: Option<String>,
+    openai_api_base: Option<String>,
+    openai_api_token: Option<String>,
+    openai_attempts: Option<u64>,
+    openai_concurrency: Option<u64>,
+    openai_max_retry_wait_secs: Option<u64>,
+    openai_model: Option<String>,
+    openai_retry_delay_ms: Option<u64>,
+    owner_id: Option<u64>,
+    paragraph_sentences: Option<u64>,
+    partial_transcripts: Option<String>,
+    qa_threads: Option<bool>,
+    rate_limit_requests: Option<u64>,
+    rate_limit_window_secs: Option<u64>,
+    regenerate_anyone: Option<bool>,
+    selftest_video_id: Option<String>,
+    settings_path: Option<String>,
+    show_publish_date: Option<bool>,
+    show_usage: Option<bool>,
+    speaker_labels: Option<bool>,
+    stream_summaries: Option<bool>,
+    summarize_emoji: Option<String>,
+    transcribe_emoji: Option<String>,
+    transcript_api_base: Option<String>,
+    transcript_recent_threshold_secs: Option<u64>,
+    transcript_timestamps: Option<bool>,
+    transcript_ttl_recent_secs: Option<u64>,
+    transcript_ttl_secs: Option<u64>,
+    webhook_only: Option<bool>,
+    webhook_secret: Option<String>,
+    webhook_url: Option<String>,
+    youtube_api_token: Option<String>,
+}
+
+/// Load `leonidas.toml`, or the file at `CONFIG_PATH`. A missing file is
+/// fine, but one that can't be parsed stops the bot from starting.
+pub fn load_file() -> FileConfig {
+    let path = env_var("CONFIG_PATH").unwrap_or_else(|| "leonidas.toml".to_string());
+    let Ok(contents) = std::fs::read_to_string(&path) else {
+        return FileConfig::default();
+    };
+    toml::from_str(&contents).unwrap_or_else(|why| panic!("Error parsing {path}: {why}"))
+}
+
+/// Only the bot itself needs `DISCORD_TOKEN`; running the pipeline from the
+/// command line doesn't.
+const DISCORD_VARS: [&str; 1] = ["DISCORD_TOKEN"];
+
+/// Where settings are looked up: the environment, or failing that the
+/// config file. Like `.env`, variables that are already set win.
+struct Settings<'a> {
+    env: fn(&str) -> Option<String>,
+    file: &'a FileConfig,
+}
+
+impl Settings<'_> {
+    /// The environment variable `name`, or failing that the file's `value`
+    /// for it.
+    fn get<T: ToString>(&self, name: &str, value: &Option<T>) -> Option<String> {
+        (self.env)(name).or_else(|| value.as_ref().map(T::to_string))
+    }
+
+    /// A trigger emoji. Panics on startup if it's set to something that
+    /// isn't an emoji, rather than silently never matching.
+    fn trigger_emoji(&self, name: &str, value: &Option<String>, default: &str) -> TriggerEmoji {
+        match self.get(name, value) {
+            Some(emoji) => TriggerEmoji::parse(&emoji)
+                .unwrap_or_else(|| panic!("{name} should be an emoji, but it's {emoji:?}")),
+            None => TriggerEmoji::Unicode(default.to_string()),
+        }
+    }
+
+    /// A base URL, without the trailing slash endpoints get appended after.
+    fn api_base(&self, name: &str, value: &Option<String>, default: &str) -> String {
+        self.get(name, value)
+            .as_deref()
+            .unwrap_or(default)
+            .trim_end_matches('/')
+            .to_string()
+    }
+
+    /// Like `api_base`, for a comma-separated list of them.
+    fn api_bases(&self, name: &str, value: &Option<String>, default: &str) -> Vec<String> {
+        let bases: Vec<String> = self
+            .api_base(name, value, default)
+            .split(',')
+            .map(|base| base.trim().trim_end_matches('/').to_string())
+            .filter(|base| !base.is_empty())
+            .collect();
+        if bases.is_empty() {
+            vec![default.to_string()]
+        } else {
+            bases
+        }
+    }
+
+    /// `GUILD_TOKEN_BUDGET`, or failing that `GUILD_DOLLAR_BUDGET` in tokens
+    /// at `COST_PER_1K_TOKENS`.
+    fn guild_token_budget(&self) -> Option<u64> {
+        let tokens = self.get("GUILD_TOKEN_BUDGET", &self.file.guild_token_budget);
+        if let Some(tokens) = tokens.and_then(|tokens| tokens.parse().ok()) {
+            return Some(tokens);
+        }
+        let dollars: f64 = self
+            .get("GUILD_DOLLAR_BUDGET", &self.file.guild_dollar_budget)?
+            .parse()
+            .ok()?;
+        let cost: f64 = self
+            .get("COST_PER_1K_TOKENS", &self.file.cost_per_1k_tokens)?
+            .parse()
+            .ok()?;
+        (cost > 0.0).then(|| (dollars / cost * 1000.0) as u64)
+    }
+
+    /// `ALLOWED_CHANNELS` as a comma-separated list, or the file's list.
+    fn allowed_channels(&self) -> HashSet<ChannelId> {
+        match (self.env)("ALLOWED_CHANNELS") {
+            Some(ids) => parse_channel_ids(&ids),
+            None => self
+                .file
+                .allowed_channels
+                .iter()
+                .flatten()
+                .filter(|id| **id != 0)
+                .map(|id| ChannelId::new(*id))
+                .collect(),
+        }
+    }
+}
+
+/// Comma-separated channel ids, e.g. `ALLOWED_CHANNELS=123,456`. Anything
+/// that isn't an id is skipped.
+fn parse_channel_ids(ids: &str) -> HashSet<ChannelId> {
+    ids.split(',')
+        .filter_map(|id| id.trim().parse().ok())
+        .filter(|id| *id != 0)
+        .map(ChannelId::new)
+        .collect()
+}
+
+/// Which bot-authored messages and reactions to ignore. Our own are always
+/// ignored.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IgnoreBots {
+    All,
+    /// Only ignore ourselves, so other bots can post links for us to
+    /// summarize.
+    OnlySelf,
+}
+
+/// What to do when one video in a multi-video message fails.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ErrorPolicy {
+    /// Keep going and report every failure at the end.
+    Continue,
+    /// Skip the remaining videos.
+    Stop,
+}
+
+pub struct Config {
+    pub discord_token: Option<String>,
+    pub youtube_api_token: Option<String>,
+    pub openai_api_token: Option<String>,
+    /// The OpenAI model every request goes to, from `OPENAI_MODEL`.
+    pub model: String,
+    /// Applied when neither the request nor the channel specifies an option,
+    /// written the same way as in a message, e.g. `length:short language:en`.
+    pub default_options: SummaryOptions,
+    pub settings_path: PathBuf,
+    /// Set via `WEBHOOK_URL`; completed jobs are also POSTed here.
+    pub webhook: Option<WebhookConfig>,
+    /// Whether guilds that haven't run `/enable` or `/disable` get processed.
+    /// Set `GUILDS_ENABLED_BY_DEFAULT=false` to require explicit onboarding.
+    pub guilds_enabled_by_default: bool,
+    /// The user allowed to run owner-only commands, e.g. `/guild`.
+    pub owner_id: Option<UserId>,
+    /// Show the video's upload date on output embeds. On unless
+    /// `SHOW_PUBLISH_DATE=false`.
+    pub show_publish_date: bool,
+    /// A short video with captions that `/selftest` runs the pipeline on.
+    pub selftest_video_id: String,
+    /// `MULTI_VIDEO_ERRORS=stop` to stop at the first failing video.
+    pub error_policy: ErrorPolicy,
+    /// `QA_THREADS=true` posts summaries in a thread where people can ask
+    /// follow-up questions about the video.
+    pub qa_threads: bool,
+    /// Questions answered per thread, from `MAX_FOLLOW_UPS`.
+    pub max_follow_ups: usize,
+    /// How long fetched transcripts are reused. Recently published videos
+    /// expire sooner since their captions are often still being replaced.
+    pub transcript_ttl: TranscriptTtl,
+    /// How long finished summaries and transcripts are reused, from
+    /// `CACHE_TTL_SECS`.
+    pub cache_ttl: Duration,
+    /// `HEALTH_PORT` serves `/health` and `/metrics` on that port.
+    pub health_port: Option<u16>,
+    /// `CACHE_DIR` keeps finished summaries and transcripts in a file there,
+    /// so they're still cached after a restart.
+    pub cache_dir: Option<PathBuf>,
+    /// Finished summaries and transcripts kept, from `CACHE_MAX_ENTRIES`.
+    pub cache_max_entries: usize,
+    /// `IGNORE_BOTS=self` to only ignore our own messages.
+    pub ignore_bots: IgnoreBots,
+    /// `PARTIAL_TRANSCRIPTS=true` posts what was cleaned up so far when a
+    /// later part of a transcript fails, instead of only an error.
+    /// `PARTIAL_TRANSCRIPTS=best-effort` posts every part that worked, with
+    /// a marker where the others were.
+    pub partial_transcripts: PartialTranscripts,
+    /// `TRANSCRIPT_TIMESTAMPS=true` starts each part of a transcript with a
+    /// link to that point in the video.
+    pub transcript_timestamps: bool,
+    /// `SPEAKER_LABELS=true` lays transcripts out as `**Speaker A:**` blocks,
+    /// e.g. for interviews.
+    pub speaker_labels: bool,
+    /// How many sentences go in each paragraph of a transcript, from
+    /// `PARAGRAPH_SENTENCES`. 0 keeps the paragraphs the cleanup wrote.
+    pub paragraph_sentences: usize,
+    /// Where to post a message each time the bot connects, from
+    /// `ANNOUNCE_CHANNEL_ID`.
+    pub announce_channel: Option<ChannelId>,
+    /// Shown as "Watching <activity>" under the bot's name, from `ACTIVITY`.
+    pub activity: Option<String>,
+    /// How long a single video may take before it's abandoned, from
+    /// `JOB_TIMEOUT_SECS`.
+    pub job_timeout: Duration,
+    /// How long connecting to any API, and fetching a transcript or video
+    /// info, may take, from `HTTP_TIMEOUT_SECS`.
+    pub http_timeout: Duration,
+    /// Videos past this many in one message are ignored, from
+    /// `MAX_VIDEOS_PER_MESSAGE`.
+    pub max_videos_per_message: usize,
+    /// Videos taken from each linked playlist, from `MAX_PLAYLIST_VIDEOS`.
+    pub max_playlist_videos: usize,
+    /// `DESCRIBE_NO_SPEECH=true` summarizes the description of videos with
+    /// no speech in them, like music videos, instead of giving up.
+    pub describe_no_speech: bool,
+    /// `SUMMARIZE_EMOJI`, `TRANSCRIBE_EMOJI`, `BULLETS_EMOJI` and
+    /// `NOTES_EMOJI`, used wherever a guild hasn't picked its own with
+    /// `/emoji`.
+    pub triggers: Triggers,
+    /// `STREAM_SUMMARIES=true` shows summaries of linked videos as they're
+    /// written, instead of only once they're done.
+    pub stream_summaries: bool,
+    /// Jobs each user can start by reacting per `rate_limit_window`, from
+    /// `RATE_LIMIT_REQUESTS` and `RATE_LIMIT_WINDOW_SECS`.
+    pub rate_limit_requests: usize,
+    pub rate_limit_window: Duration,
+    /// Channels the bot works in, from `ALLOWED_CHANNELS`. Empty means all of
+    /// them.
+    pub allowed_channels: HashSet<ChannelId>,
+    /// `OPENAI_ATTEMPTS` tries per OpenAI request, backing off from
+    /// `OPENAI_RETRY_DELAY_MS` between them. Rate limits asking for a longer
+    /// wait than `OPENAI_MAX_RETRY_WAIT_SECS` fail the request.
+    pub retry_policy: RetryPolicy,
+    /// `SHOW_USAGE=true` posts the tokens each summary or transcript took,
+    /// priced at `COST_PER_1K_TOKENS` dollars if that's set.
+    pub show_usage: bool,
+    pub cost_per_1k_tokens: Option<f64>,
+    /// OpenAI tokens each server can use per `guild_budget_window`, from
+    /// `GUILD_TOKEN_BUDGET`, or `GUILD_DOLLAR_BUDGET` priced at
+    /// `COST_PER_1K_TOKENS`. Unlimited when neither is set.
+    pub guild_token_budget: Option<u64>,
+    /// `GUILD_BUDGET_DAYS`, 30 by default.
+    pub guild_budget_window: Duration,
+    /// `TRANSCRIPT_API_BASE` and `OPENAI_API_BASE`, for a self-hosted
+    /// transcript proxy or a different OpenAI endpoint. Several transcript
+    /// proxies can be given, separated by commas, to fall back on in order.
+    pub transcript_api_bases: Vec<String>,
+    pub openai_api_base: String,
+    /// OpenAI requests in flight at once across all jobs, from
+    /// `OPENAI_CONCURRENCY`.
+    pub openai_concurrency: usize,
+    /// Output longer than this many characters is posted as a markdown file
+    /// instead of embeds, from `FILE_THRESHOLD_CHARS`.
+    pub file_threshold: usize,
+    /// Output is split into pieces of at most this many characters, one per
+    /// embed, from `EMBED_CHUNK_CHARS`. Embeds can't hold more than 4096.
+    pub embed_chunk_chars: usize,
+    /// `REGENERATE_ANYONE=true` lets anyone redo output, not only whoever
+    /// asked for it.
+    pub regenerate_anyone: bool,
+    /// Transcripts this many words long or shorter aren't summarized, from
+    /// `MIN_SUMMARY_WORDS`.
+    pub min_summary_words: usize,
+}
+
+impl Config {
+    pub fn transcript_options(&self) -> TranscriptOptions {
+        TranscriptOptions {
+            model: self.model.clone(),
+            language: None,
+            partial: self.partial_transcripts,
+            timestamps: self.transcript_timestamps,
+            speakers: self.speaker_labels,
+            paragraph_sentences: self.paragraph_sentences,
+            system_prompt: None,
+        }
+    }
+
+    /// The required settings that aren't set, checked once at startup so a
+    /// misconfigured deployment doesn't fail request by request.
+    pub fn missing_vars(&self, discord: bool) -> Vec<&'static str> {
+        [
+            ("DISCORD_TOKEN", &self.discord_token),
+            ("YOUTUBE_API_TOKEN", &self.youtube_api_token),
+            ("OPENAI_API_TOKEN", &self.openai_api_token),
+        ]
+        .into_iter()
+        .filter(|(name, _)| discord || !DISCORD_VARS.contains(name))
+        .filter(|(_, value)| value.is_none())
+        .map(|(name, _)| name)
+        .collect()
+    }
+
+    /// Every setting from the environment, or failing that `file`.
+    pub fn from_env(file: &FileConfig) -> Config {
+        Config::from_settings(&Settings { env: env_var, file })
+    }
+
+    fn from_settings(settings: &Settings) -> Config {
+        let file = settings.file;
+        Config {
+            discord_token: settings.get("DISCORD_TOKEN", &file.discord_token),
+            youtube_api_token: settings.get("YOUTUBE_API_TOKEN", &file.youtube_api_token),
+            openai_api_token: settings.get("OPENAI_API_TOKEN", &file.openai_api_token),
+            model: settings
+                .get("OPENAI_MODEL", &file.openai_model)
+                .unwrap_or_else(|| "gpt-4-1106-preview".to_string()),
+            default_options: settings
+                .get("DEFAULT_SUMMARY_OPTIONS", &file.default_summary_options)
+                .map(|options| SummaryOptions::parse(&options))
+                .unwrap_or_default(),
+            settings_path: settings
+                .get("SETTINGS_PATH", &file.settings_path)
+                .unwrap_or_else(|| "leonidas-settings.json".to_string())
+                .into(),
+            webhook: settings
+                .get("WEBHOOK_URL", &file.webhook_url)
+                .map(|url| WebhookConfig {
+                    url,
+                    secret: settings.get("WEBHOOK_SECRET", &file.webhook_secret),
+                    only: settings
+                        .get("WEBHOOK_ONLY", &file.webhook_only)
+                        .is_some_and(|only| only == "true"),
+                }),
+            guilds_enabled_by_default: settings
+                .get("GUILDS_ENABLED_BY_DEFAULT", &file.guilds_enabled_by_default)
+                .map_or(true, |enabled| enabled != "false"),
+            owner_id: settings
+                .get("OWNER_ID", &file.owner_id)
+                .and_then(|id| id.parse().ok())
+                .map(UserId::new),
+            show_publish_date: settings
+                .get("SHOW_PUBLISH_DATE", &file.show_publish_date)
+                .map_or(true, |show| show != "false"),
+            selftest_video_id: settings
+                .get("SELFTEST_VIDEO_ID", &file.selftest_video_id)
+                .unwrap_or_else(|| "arj7oStGLkU".to_string()),
+            error_policy: match settings
+                .get("MULTI_VIDEO_ERRORS", &file.multi_video_errors)
+                .as_deref()
+            {
+                Some("stop") => ErrorPolicy::Stop,
+                _ => ErrorPolicy::Continue,
+            },
+            qa_threads: settings
+                .get("QA_THREADS", &file.qa_threads)
+                .is_some_and(|enabled| enabled == "true"),
+            max_follow_ups: settings
+                .get("MAX_FOLLOW_UPS", &file.max_follow_ups)
+                .and_then(|max| max.parse().ok())
+                .unwrap_or(10),
+            cache_ttl: Duration::from_secs(
+                settings
+                    .get("CACHE_TTL_SECS", &file.cache_ttl_secs)
+                    .and_then(|secs| secs.parse().ok())
+                    .unwrap_or(24 * 60 * 60),
+            ),
+            health_port: settings
+                .get("HEALTH_PORT", &file.health_port)
+                .and_then(|port| port.parse().ok()),
+            cache_dir: settings
+                .get("CACHE_DIR", &file.cache_dir)
+                .map(PathBuf::from),
+            cache_max_entries: settings
+                .get("CACHE_MAX_ENTRIES", &file.cache_max_entries)
+                .and_then(|max| max.parse().ok())
+                .unwrap_or(1000),
+            transcript_ttl: {
+                let default = TranscriptTtl::default();
+                let secs = |name: &str, value: &Option<u64>, default: Duration| {
+                    settings
+                        .get(name, value)
+                        .and_then(|secs| secs.parse().ok())
+                        .map_or(default, Duration::from_secs)
+                };
+                TranscriptTtl {
+                    recent: secs(
+                        "TRANSCRIPT_TTL_RECENT_SECS",
+                        &file.transcript_ttl_recent_secs,
+                        default.recent,
+                    ),
+                    old: secs(
+                        "TRANSCRIPT_TTL_SECS",
+                        &file.transcript_ttl_secs,
+                        default.old,
+                    ),
+                    recent_threshold: secs(
+                        "TRANSCRIPT_RECENT_THRESHOLD_SECS",
+                        &file.transcript_recent_threshold_secs,
+                        default.recent_threshold,
+                    ),
+                }
+            },
+            ignore_bots: match settings.get("IGNORE_BOTS", &file.ignore_bots).as_deref() {
+                Some("self") => IgnoreBots::OnlySelf,
+                _ => IgnoreBots::All,
+            },
+            partial_transcripts: settings
+                .get("PARTIAL_TRANSCRIPTS", &file.partial_transcripts)
+                .map(|partial| PartialTranscripts::parse(&partial))
+                .unwrap_or_default(),
+            transcript_timestamps: settings
+                .get("TRANSCRIPT_TIMESTAMPS", &file.transcript_timestamps)
+                .is_some_and(|timestamps| timestamps == "true"),
+            speaker_labels: settings
+                .get("SPEAKER_LABELS", &file.speaker_labels)
+                .is_some_and(|labels| labels == "true"),
+            paragraph_sentences: settings
+                .get("PARAGRAPH_SENTENCES", &file.paragraph_sentences)
+                .and_then(|sentences| sentences.parse().ok())
+                .unwrap_or(3),
+            announce_channel: settings
+                .get("ANNOUNCE_CHANNEL_ID", &file.announce_channel_id)
+                .and_then(|id| id.parse().ok())
+                .filter(|id| *id != 0)
+                .map(ChannelId::new),
+            activity: settings.get("ACTIVITY", &file.activity),
+            job_timeout: Duration::from_secs(
+                settings
+                    .get("JOB_TIMEOUT_SECS", &file.job_timeout_secs)
+                    .and_then(|secs| secs.parse().ok())
+                    .unwrap_or(10 * 60),
+            ),
+            http_timeout: settings
+                .get("HTTP_TIMEOUT_SECS", &file.http_timeout_secs)
+                .and_then(|secs| secs.parse().ok())
+                .map(Duration::from_secs)
+                .unwrap_or(youtube::DEFAULT_HTTP_TIMEOUT),
+            max_videos_per_message: settings
+                .get("MAX_VIDEOS_PER_MESSAGE", &file.max_videos_per_message)
+                .and_then(|max| max.parse().ok())
+                .unwrap_or(10),
+            triggers: Triggers {
+                summarize: settings.trigger_emoji(
+                    "SUMMARIZE_EMOJI",
+                    &file.summarize_emoji,
+                    SUMMARIZE_EMOJI,
+                ),
+                transcribe: settings.trigger_emoji(
+                    "TRANSCRIBE_EMOJI",
+                    &file.transcribe_emoji,
+                    TRANSCRIBE_EMOJI,
+                ),
+                bullets: settings.trigger_emoji(
+                    "BULLETS_EMOJI",
+                    &file.bullets_emoji,
+                    BULLETS_EMOJI,
+                ),
+                notes: settings.trigger_emoji("NOTES_EMOJI", &file.notes_emoji, NOTES_EMOJI),
+            },
+            max_playlist_videos: settings
+                .get("MAX_PLAYLIST_VIDEOS", &file.max_playlist_videos)
+                .and_then(|max| max.parse().ok())
+                .unwrap_or(25),
+            describe_no_speech: settings
+                .get("DESCRIBE_NO_SPEECH", &file.describe_no_speech)
+                .is_some_and(|describe| describe == "true"),
+            stream_summaries: settings
+                .get("STREAM_SUMMARIES", &file.stream_summaries)
+                .is_some_and(|stream| stream == "true"),
+            rate_limit_requests: settings
+                .get("RATE_LIMIT_REQUESTS", &file.rate_limit_requests)
+                .and_then(|max| max.parse().ok())
+                .unwrap_or(5),
+            rate_limit_window: Duration::from_secs(
+                settings
+                    .get("RATE_LIMIT_WINDOW_SECS", &file.rate_limit_window_secs)
+                    .and_then(|secs| secs.parse().ok())
+                    .unwrap_or(10 * 60),
+            ),
+            allowed_channels: settings.allowed_channels(),
+            retry_policy: {
+                let default = RetryPolicy::default();
+                RetryPolicy {
+                    attempts: settings
+                        .get("OPENAI_ATTEMPTS", &file.openai_attempts)
+                        .and_then(|attempts| attempts.parse().ok())
+                        .filter(|attempts| *attempts > 0)
+                        .unwrap_or(default.attempts),
+                    base_delay: settings
+                        .get("OPENAI_RETRY_DELAY_MS", &file.openai_retry_delay_ms)
+                        .and_then(|ms| ms.parse().ok())
+                        .map_or(default.base_delay, Duration::from_millis),
+                    max_wait: settings
+                        .get(
+                            "OPENAI_MAX_RETRY_WAIT_SECS",
+                            &file.openai_max_retry_wait_secs,
+                        )
+                        .and_then(|secs| secs.parse().ok())
+                        .map_or(default.max_wait, Duration::from_secs),
+                }
+            },
+            show_usage: settings
+                .get("SHOW_USAGE", &file.show_usage)
+                .is_some_and(|show| show == "true"),
+            cost_per_1k_tokens: settings
+                .get("COST_PER_1K_TOKENS", &file.cost_per_1k_tokens)
+                .and_then(|cost| cost.parse().ok()),
+            guild_token_budget: settings.guild_token_budget(),
+            guild_budget_window: Duration::from_secs(
+                settings
+                    .get("GUILD_BUDGET_DAYS", &file.guild_budget_days)
+                    .and_then(|days| days.parse().ok())
+                    .unwrap_or(30)
+                    * 24
+                    * 60
+                    * 60,
+            ),
+            transcript_api_bases: settings.api_bases(
+                "TRANSCRIPT_API_BASE",
+                &file.transcript_api_base,
+                youtube::DEFAULT_TRANSCRIPT_BASE,
+            ),
+            openai_api_base: settings.api_base(
+                "OPENAI_API_BASE",
+                &file.openai_api_base,
+                youtube::DEFAULT_OPENAI_BASE,
+            ),
+            openai_concurrency: settings
+                .get("OPENAI_CONCURRENCY", &file.openai_concurrency)
+                .and_then(|max| max.parse().ok())
+                .filter(|max| *max > 0)
+                .unwrap_or(youtube::DEFAULT_OPENAI_CONCURRENCY),
+            file_threshold: settings
+                .get("FILE_THRESHOLD_CHARS", &file.file_threshold_chars)
+                .and_then(|chars| chars.parse().ok())
+                .unwrap_or(20_000),
+            embed_chunk_chars: settings
+                .get("EMBED_CHUNK_CHARS", &file.embed_chunk_chars)
+                .and_then(|chars| chars.parse().ok())
+                .filter(|chars| *chars > 0)
+                .map_or(embed::DESCRIPTION_LIMIT, |chars: usize| {
+                    chars.min(embed::DESCRIPTION_LIMIT)
+                }),
+            regenerate_anyone: settings
+                .get("REGENERATE_ANYONE", &file.regenerate_anyone)
+                .is_some_and(|anyone| anyone == "true"),
+            min_summary_words: settings
+                .get("MIN_SUMMARY_WORDS", &file.min_summary_words)
+                .and_then(|min| min.parse().ok())
+                .unwrap_or(prompts::DEFAULT_MIN_SUMMARY_WORDS),
+        }
+    }
+}
+
+#[test]
+fn test_parse_channel_ids() {
+    assert_eq!(
+        parse_channel_ids("123, 456,,789 ,abc,0"),
+        HashSet::from([
+            ChannelId::new(123),
+            ChannelId::new(456),
+            ChannelId::new(789)
+        ])
+    );
+    assert!(parse_channel_ids("").is_empty());
+}
+
+#[test]
+fn test_file_config() {
+    let contents = r#"
+openai_model = "gpt-4"
+max_follow_ups = 5
+qa_threads = true
+allowed_channels = [123, 456]
+"#;
+    let file: FileConfig = toml::from_str(contents).unwrap();
+    // Nothing from the environment, whatever's set where the tests run.
+    let config = Config::from_settings(&Settings {
+        env: |_| None,
+        file: &file,
+    });
+    assert_eq!(config.model, "gpt-4");
+    assert_eq!(config.max_follow_ups, 5);
+    assert!(config.qa_threads);
+    assert_eq!(
+        config.allowed_channels,
+        HashSet::from([ChannelId::new(123), ChannelId::new(456)])
+    );
+
+    let parse = toml::from_str::<FileConfig>;
+    assert!(parse("openai_modle = \"gpt-4\"").is_err());
+    assert!(parse("max_follow_ups = \"five\"").is_err());
+    assert!(parse("[webhook]\nurl = \"https://example.com\"").is_err());
+    assert!(parse("not toml").is_err());
+}
@@ -0,0 +1,142 @@
+use tracing::{debug, warn};
+
+// Discord's per-embed limits, all counted in characters.
+pub const TITLE_LIMIT: usize = 256;
+pub const DESCRIPTION_LIMIT: usize = 4096;
+pub const FOOTER_LIMIT: usize = 2048;
+pub const FIELD_NAME_LIMIT: usize = 256;
+pub const FIELD_VALUE_LIMIT: usize = 1024;
+pub const TOTAL_LIMIT: usize = 6000;
+/// Plain message content, as opposed to an embed, is capped at this.
+pub const MESSAGE_LIMIT: usize = 2000;
+
+/// Descriptions always get at least this much room; fields are dropped to
+/// make it. The title and footer limits alone can't eat into it.
+const MIN_DESCRIPTION_BUDGET: usize = 1024;
+
+/// Room kept at the end of the title for a ` (part NN/NN)` suffix.
+const PART_SUFFIX_RESERVE: usize = 13;
+
+fn truncate(s: &str, limit: usize, what: &str) -> String {
+    let len = s.chars().count();
+    if len <= limit {
+        return s.to_string();
+    }
+    debug!("Trimmed embed {what} from {len} to {limit} characters");
+    let mut trimmed: String = s.chars().take(limit - 1).collect();
+    trimmed.push('…');
+    trimmed
+}
+
+/// Everything in an embed except the description, trimmed to fit Discord's
+/// limits. The description gets whatever room is left, see
+/// `description_budget`.
+pub struct EmbedFrame {
+    title: String,
+    pub footer: String,
+    pub fields: Vec<(String, String)>,
+}
+
+impl EmbedFrame {
+    pub fn new(title: &str, footer: &str, fields: Vec<(String, String)>) -> EmbedFrame {
+        let mut frame = EmbedFrame {
+            title: truncate(title, TITLE_LIMIT - PART_SUFFIX_RESERVE, "title"),
+            footer: truncate(footer, FOOTER_LIMIT, "footer"),
+            fields: fields
+                .into_iter()
+                .map(|(name, value)| {
+                    (
+                        truncate(&name, FIELD_NAME_LIMIT, "field name"),
+                        truncate(&value, FIELD_VALUE_LIMIT, "field value"),
+                    )
+                })
+                .collect(),
+        };
+
+        let max_len = TOTAL_LIMIT - MIN_DESCRIPTION_BUDGET;
+        while frame.len() > max_len {
+            let Some((name, _)) = frame.fields.pop() else {
+                break;
+            };
+            warn!("Dropped embed field {name:?} to stay within the total limit");
+        }
+        frame
+    }
+
+    fn len(&self) -> usize {
+        self.title.chars().count()
+            + PART_SUFFIX_RESERVE
+            + self.footer.chars().count()
+            + self
+                .fields
+                .iter()
+                .map(|(name, value)| name.chars().count() + value.chars().count())
+                .sum::<usize>()
+    }
+
+    /// How long each description can be while keeping the whole embed within
+    /// the 6000 character total.
+    pub fn description_budget(&self) -> usize {
+        DESCRIPTION_LIMIT.min(TOTAL_LIMIT.saturating_sub(self.len()))
+    }
+
+    /// The title for chunk `index` (zero-based) of `count`.
+    pub fn title(&self, index: usize, count: usize) -> String {
+        if count != 1 {
+            format!("{} (part {}/{})", self.title, index + 1, count)
+        } else {
+            self.title.clone()
+        }
+    }
+}
+
+#[test]
+fn test_embed_frame_short() {
+    let frame = EmbedFrame::new("A title", "A channel", vec![]);
+    assert_eq!(frame.title(0, 1), "A title");
+    assert_eq!(frame.title(1, 3), "A title (part 2/3)");
+    assert_eq!(frame.footer, "A channel");
+    assert_eq!(frame.description_budget(), DESCRIPTION_LIMIT);
+}
+
+#[test]
+fn test_embed_frame_long_title() {
+    let frame = EmbedFrame::new(&"t".repeat(500), "A channel", vec![]);
+    assert!(frame.title(0, 1).chars().count() <= TITLE_LIMIT);
+    assert!(frame.title(98, 99).chars().count() <= TITLE_LIMIT);
+    assert!(frame.title(0, 1).ends_with('…'));
+}
+
+#[test]
+fn test_embed_frame_long_footer() {
+    let frame = EmbedFrame::new("A title", &"f".repeat(3000), vec![]);
+    assert_eq!(frame.footer.chars().count(), FOOTER_LIMIT);
+    assert!(frame.len() + frame.description_budget() <= TOTAL_LIMIT);
+    assert!(frame.description_budget() < DESCRIPTION_LIMIT);
+}
+
+#[test]
+fn test_embed_frame_fields() {
+    let frame = EmbedFrame::new(
+        "A title",
+        "A channel",
+        vec![("n".repeat(300), "v".repeat(2000)); 2],
+    );
+    for (name, value) in &frame.fields {
+        assert_eq!(name.chars().count(), FIELD_NAME_LIMIT);
+        assert_eq!(value.chars().count(), FIELD_VALUE_LIMIT);
+    }
+    assert!(frame.len() + frame.description_budget() <= TOTAL_LIMIT);
+}
+
+#[test]
+fn test_embed_frame_everything_long() {
+    let frame = EmbedFrame::new(
+        &"t".repeat(300),
+        &"f".repeat(3000),
+        vec![("n".repeat(300), "v".repeat(2000)); 3],
+    );
+    assert!(frame.fields.len() < 3);
+    assert!(frame.len() + frame.description_budget() <= TOTAL_LIMIT);
+    assert!(frame.description_budget() >= MIN_DESCRIPTION_BUDGET);
+}
@@ -0,0 +1,64 @@
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Request, Response, Server, StatusCode};
+use leonidas::metrics::METRICS;
+use tokio::sync::watch;
+use tracing::{error, info};
+
+/// Whether the shard is connected to Discord, set from the `ready` event and
+/// cleared when it disconnects or the bot is shutting down.
+pub static CONNECTED: AtomicBool = AtomicBool::new(false);
+
+fn respond(request: &Request<Body>) -> Response<Body> {
+    let (status, body) = match request.uri().path() {
+        "/health" if CONNECTED.load(Ordering::Relaxed) => (StatusCode::OK, "ok".to_string()),
+        "/health" => (StatusCode::SERVICE_UNAVAILABLE, "not connected".to_string()),
+        "/metrics" => (StatusCode::OK, METRICS.render()),
+        _ => (StatusCode::NOT_FOUND, "not found".to_string()),
+    };
+    let mut response = Response::new(Body::from(body));
+    *response.status_mut() = status;
+    response
+}
+
+/// Serve `/health` and `/metrics` on `port` until `stop` changes.
+pub async fn serve(port: u16, mut stop: watch::Receiver<bool>) {
+    let address = SocketAddr::from(([0, 0, 0, 0], port));
+    let service = make_service_fn(|_| async {
+        Ok::<_, Infallible>(service_fn(|request| async move {
+            Ok::<_, Infallible>(respond(&request))
+        }))
+    });
+    let server = match Server::try_bind(&address) {
+        Ok(builder) => builder.serve(service),
+        Err(why) => {
+            error!("Error starting health server on {address}: {why:?}");
+            return;
+        }
+    };
+    info!("Serving /health and /metrics on {address}");
+    let stopped = async move {
+        let _ = stop.changed().await;
+    };
+    if let Err(why) = server.with_graceful_shutdown(stopped).await {
+        error!("Health server error: {why:?}");
+    }
+}
+
+#[test]
+fn test_respond() {
+    let request = |path: &str| Request::get(path).body(Body::empty()).unwrap();
+
+    CONNECTED.store(false, Ordering::Relaxed);
+    assert_eq!(
+        respond(&request("/health")).status(),
+        StatusCode::SERVICE_UNAVAILABLE
+    );
+    CONNECTED.store(true, Ordering::Relaxed);
+    assert_eq!(respond(&request("/health")).status(), StatusCode::OK);
+    assert_eq!(respond(&request("/metrics")).status(), StatusCode::OK);
+    assert_eq!(respond(&request("/other")).status(), StatusCode::NOT_FOUND);
+}
@@ -0,0 +1,13 @@
+//! The summarization pipeline on its own: fetching videos' transcripts and
+//! details, and summarizing or cleaning them up with OpenAI. The Discord bot
+//! in `main.rs` is built on this, but nothing here depends on Discord.
+
+pub mod cache;
+pub mod chapters;
+pub mod metrics;
+pub mod openai;
+pub mod options;
+pub mod prompts;
+pub mod sources;
+pub mod utils;
+pub mod youtube;
@@ -1,84 +1,256 @@
-#![feature(iter_intersperse)]
-
-mod openai;
-mod prompts;
-mod utils;
-mod youtube;
+mod budget;
+mod cli;
+mod commands;
+mod config;
+mod embed;
+mod health;
+mod outputs;
+mod progress;
+mod qa;
+mod ratelimit;
+mod settings;
+mod triggers;
+mod webhook;
 
 use std::env;
+use std::future::Future;
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
 
 use dotenv::dotenv;
+use futures::{future, FutureExt};
+use leonidas::metrics::{self, METRICS};
+use leonidas::{cache, openai, options, prompts, sources, utils, youtube};
 use linkify::{LinkFinder, LinkKind};
-use serenity::all::{ChannelId, ReactionType};
+use serenity::all::{
+    ActivityData, Attachment, ChannelId, ChannelType, Command, CommandInteraction, ConnectionStage,
+    CreateAttachment, CreateInteractionResponse, CreateInteractionResponseMessage, CreateThread,
+    Embed, GuildId, Interaction, MessageId, MessageUpdateEvent, ReactionType, ResolvedTarget,
+    ShardStageUpdateEvent, Timestamp, UserId,
+};
 use serenity::async_trait;
-use serenity::builder::{CreateEmbed, CreateEmbedFooter, CreateMessage};
+use serenity::builder::{CreateEmbed, CreateEmbedFooter, CreateMessage, EditMessage};
 use serenity::model::channel::{Message, Reaction};
 use serenity::model::gateway::Ready;
 use serenity::prelude::*;
+use tokio::sync::{watch, Semaphore};
+use tracing::{debug, error, info, warn};
+use tracing_subscriber::EnvFilter;
+
+struct Handler {
+    config: config::Config,
+    cache: cache::Cache,
+    settings: settings::Settings,
+    qa: qa::QaThreads,
+    in_progress: progress::InProgress,
+    /// Shared with `main`, which waits for these to finish on shutdown.
+    running: Arc<progress::Running>,
+    requested: progress::Requested,
+    offered: progress::Offered,
+    cancellable: progress::Cancellable,
+    rate_limiter: ratelimit::RateLimiter,
+    budgets: budget::Budgets,
+    outputs: outputs::Outputs,
+    /// Our own user id, known once `ready` fires.
+    bot_id: OnceLock<UserId>,
+}
+
+const WARNING_EMOJI: &str = "⚠️";
+
+/// Shown on a message while it's being worked on, then replaced with
+/// `DONE_EMOJI` or `FAILED_EMOJI`.
+const WORKING_EMOJI: &str = "⏳";
+const DONE_EMOJI: &str = "✅";
+const FAILED_EMOJI: &str = "❌";
+
+/// Offered on output, to make it again for whoever asked for it.
+const REGENERATE_EMOJI: &str = "🔄";
+
+const DISABLED_MESSAGE: &str = "I'm not enabled in this server.";
 
-struct Handler;
+/// Heads `/raw` output, so it isn't mistaken for a cleaned-up transcript.
+const RAW_TRANSCRIPT_NOTE: &str =
+    "*Auto-generated transcript, as YouTube has it: not cleaned up, so expect missing punctuation and misheard words.*";
 
-const TRANSCRIBE_EMOJI: &str = "📜";
-const SUMMARIZE_EMOJI: &str = "💭";
+/// Text attachments bigger than this aren't downloaded to look for links.
+const MAX_ATTACHMENT_BYTES: u64 = 64 * 1024;
+
+/// How long running jobs get to finish once the bot is asked to stop.
+const SHUTDOWN_GRACE: Duration = Duration::from_secs(30);
+
+/// How long more reactions asking for a job that was just done are ignored.
+/// 🔄 on the output redoes it sooner.
+const REPEAT_WINDOW: Duration = Duration::from_secs(10 * 60);
+
+/// How many messages offered the reactions are remembered, so they aren't
+/// offered them again when an event is redelivered.
+const MAX_OFFERED: usize = 1000;
 
 #[async_trait]
 impl EventHandler for Handler {
     async fn message(&self, ctx: Context, msg: Message) {
+        let _running = self.running.start();
         // make sure the message isn't from a bot
-        if msg.author.bot {
+        if self.ignores(msg.author.id, msg.author.bot) || !self.enabled_in(msg.guild_id) {
             return;
         }
 
-        let video_ids = video_ids_for_message(&msg.content);
-
-        if !video_ids.is_empty() {
-            // Sending a message can fail, due to a network error, an
-            // authentication error, or lack of permissions to post in the
-            // channel, so log to stdout when some error happens, with a
-            // description of it.
+        if let Some((summary, output)) = self.replied_summary(&msg) {
+            self.refine_summary(&ctx, &msg, summary, output).await;
+            return;
+        }
 
-            msg.react(
-                &ctx.http,
-                ReactionType::Unicode(SUMMARIZE_EMOJI.to_string()),
-            )
-            .await
-            .unwrap();
+        if let Some(question) = self.qa.ask(msg.channel_id) {
+            self.answer_question(&ctx, &msg, question).await;
+            return;
+        }
 
-            msg.react(
-                &ctx.http,
-                ReactionType::Unicode(TRANSCRIBE_EMOJI.to_string()),
-            )
-            .await
-            .unwrap();
+        // Replies and Q&A threads still work outside the allowed channels,
+        // but links there are ignored. Attachments and playlists are only
+        // looked into once someone asks for a job.
+        if self.allowed_in(msg.channel_id, msg.guild_id) && might_link_videos(&msg) {
+            self.offer_reactions(&ctx, &msg).await;
         }
 
         let twitter_links = twitter_links_for_message(&msg.content);
         for twitter_link in twitter_links {
             let message = CreateMessage::new().content(twitter_link);
             if let Err(why) = msg.channel_id.send_message(&ctx.http, message).await {
-                println!("Error sending message: {:?}", why);
+                error!("Error sending message: {:?}", why);
             }
         }
     }
 
+    /// Taking back the reaction that started a job cancels it, if it's still
+    /// running.
+    async fn reaction_remove(&self, _ctx: Context, reaction: Reaction) {
+        let Some(user_id) = reaction.user_id else {
+            return;
+        };
+        let triggers = self
+            .settings
+            .triggers(reaction.guild_id, &self.config.triggers);
+        if let Some(trigger) = triggers.matching(&reaction.emoji) {
+            self.cancellable
+                .cancel(reaction.message_id, trigger, user_id);
+        }
+    }
+
+    /// Links added by editing a message get offered the reactions too,
+    /// unless the original version already was.
+    async fn message_update(
+        &self,
+        ctx: Context,
+        _old_if_available: Option<Message>,
+        _new: Option<Message>,
+        event: MessageUpdateEvent,
+    ) {
+        if !self.enabled_in(event.guild_id) || !self.allowed_in(event.channel_id, event.guild_id) {
+            return;
+        }
+        // Updates also come in for e.g. link previews being added, so only
+        // edited content that links to something is worth fetching for.
+        let Some(content) = &event.content else {
+            return;
+        };
+        if !links_videos(content) {
+            return;
+        }
+        let msg = match event.channel_id.message(&ctx.http, event.id).await {
+            Ok(msg) => msg,
+            Err(why) => {
+                error!("Error fetching edited message {}: {:?}", event.id, why);
+                return;
+            }
+        };
+        if self.ignores(msg.author.id, msg.author.bot)
+            || msg.reactions.iter().any(|reaction| reaction.me)
+        {
+            return;
+        }
+        self.offer_reactions(&ctx, &msg).await;
+    }
+
     async fn reaction_add(&self, ctx: Context, reaction: Reaction) {
-        if reaction
-            .member
-            .as_ref()
-            .map(|user| user.user.bot)
-            .unwrap_or(true)
+        let _running = self.running.start();
+        let Some((requester, requester_is_bot)) = reactor(&reaction) else {
+            return;
+        };
+        if self.ignores(requester, requester_is_bot) {
+            return;
+        }
+        if !self.enabled_in(reaction.guild_id)
+            || !self.allowed_in(reaction.channel_id, reaction.guild_id)
         {
             return;
         }
-        if reaction.emoji.unicode_eq(TRANSCRIBE_EMOJI) {
-            if let Ok(message) = reaction.message(&ctx.http).await {
-                transcribe_videos(ctx, &message).await;
+        if reaction.emoji.unicode_eq(REGENERATE_EMOJI) {
+            if let Some(output) = self.outputs.get(reaction.message_id) {
+                self.regenerate(
+                    &ctx,
+                    reaction.channel_id,
+                    reaction.guild_id,
+                    output,
+                    requester,
+                )
+                .await;
             }
-        } else if reaction.emoji.unicode_eq(SUMMARIZE_EMOJI) {
-            if let Ok(message) = reaction.message(&ctx.http).await {
-                summarize_videos(ctx, &message).await;
+            return;
+        }
+        let triggers = self
+            .settings
+            .triggers(reaction.guild_id, &self.config.triggers);
+        let Some(trigger) = triggers.matching(&reaction.emoji) else {
+            return;
+        };
+
+        // Fetching can fail for old messages or ones we can no longer see.
+        // Flag the message so the user knows their reaction wasn't ignored.
+        let message = match reaction.message(&ctx.http).await {
+            Ok(message) => message,
+            Err(why) => {
+                error!(
+                    "Error fetching message {} in channel {} for reaction: {:?}",
+                    reaction.message_id, reaction.channel_id, why
+                );
+                if let Err(why) = reaction
+                    .channel_id
+                    .create_reaction(
+                        &ctx.http,
+                        reaction.message_id,
+                        ReactionType::Unicode(WARNING_EMOJI.to_string()),
+                    )
+                    .await
+                {
+                    error!("Error adding reaction: {:?}", why);
+                }
+                return;
             }
         };
+        // Our own output can contain links too, e.g. a forwarded tweet.
+        if self.ignores(message.author.id, message.author.bot) {
+            return;
+        }
+        // Someone else already reacted the same way.
+        if !self.requested.claim(message.id, trigger) {
+            return;
+        }
+        if !self
+            .within_budget(&ctx, reaction.channel_id, reaction.guild_id)
+            .await
+            || !self
+                .within_rate_limit(&ctx, reaction.channel_id, requester)
+                .await
+        {
+            self.requested.finish(message.id, trigger, false);
+            return;
+        }
+
+        self.remove_own_reaction(&ctx, &message, triggers.get(trigger).reaction())
+            .await;
+        let succeeded = self.run_job(&ctx, &message, trigger, requester).await;
+        self.requested.finish(message.id, trigger, succeeded);
     }
 
     // Set a handler to be called on the `ready` event. This is called when a
@@ -86,28 +258,174 @@ impl EventHandler for Handler {
     // contains data like the current user's guild Ids, current user data,
     // private channels, and more.
     //
-    // In this case, just print what the current user's username is.
-    async fn ready(&self, _: Context, ready: Ready) {
-        println!("{} is connected!", ready.user.name);
+    // Here it remembers the bot's own id, marks it connected for the health
+    // check, sets its activity, announces it's online and registers the
+    // slash commands.
+    async fn ready(&self, ctx: Context, ready: Ready) {
+        info!("{} is connected!", ready.user.name);
+        let _ = self.bot_id.set(ready.user.id);
+        health::CONNECTED.store(true, Ordering::Relaxed);
+
+        if let Some(activity) = &self.config.activity {
+            ctx.set_activity(Some(ActivityData::watching(activity.clone())));
+        }
+        if let Some(channel_id) = self.config.announce_channel {
+            let announcement = format!(
+                "{} is online — version {}, model `{}`",
+                ready.user.name,
+                env!("CARGO_PKG_VERSION"),
+                self.config.model
+            );
+            if let Err(why) = channel_id.say(&ctx.http, announcement).await {
+                error!("Error sending announcement: {:?}", why);
+            }
+        }
+
+        if let Err(why) = Command::set_global_commands(&ctx.http, commands::commands()).await {
+            error!("Error registering commands: {:?}", why);
+        }
+    }
+
+    /// `ready` marks the shard connected again once it's back.
+    async fn shard_stage_update(&self, _ctx: Context, event: ShardStageUpdateEvent) {
+        if event.new != ConnectionStage::Connected {
+            health::CONNECTED.store(false, Ordering::Relaxed);
+        }
+    }
+
+    async fn interaction_create(&self, ctx: Context, interaction: Interaction) {
+        let _running = self.running.start();
+        if let Interaction::Command(command) = interaction {
+            match command.data.name.as_str() {
+                "defaults" => commands::defaults(&ctx, &command, &self.settings).await,
+                "enable" => commands::set_enabled(&ctx, &command, &self.settings, true).await,
+                "disable" => commands::set_enabled(&ctx, &command, &self.settings, false).await,
+                "emoji" => commands::emoji(&ctx, &command, &self.settings, &self.config).await,
+                "prefs" => commands::prefs(&ctx, &command, &self.settings).await,
+                "setprompt" => {
+                    commands::setprompt(&ctx, &command, &self.settings, &self.config).await
+                }
+                "guild" => commands::guild(&ctx, &command, &self.settings, &self.config).await,
+                "raw"
+                | "estimate"
+                | "summarize"
+                | "transcribe"
+                | commands::SUMMARIZE_MESSAGE
+                | commands::TRANSCRIBE_MESSAGE
+                    if !self.enabled_in(command.guild_id) =>
+                {
+                    commands::respond(&ctx, &command, DISABLED_MESSAGE.to_string()).await
+                }
+                "selftest" => commands::selftest(&ctx, &command, &self.config).await,
+                "stats" => self.stats_command(&ctx, &command).await,
+                "raw" => self.raw_transcript(&ctx, &command).await,
+                "estimate" => self.estimate_command(&ctx, &command).await,
+                "summarize" => self.summarize_command(&ctx, &command).await,
+                "transcribe" => self.transcribe_command(&ctx, &command).await,
+                commands::SUMMARIZE_MESSAGE => {
+                    self.message_command(&ctx, &command, triggers::Trigger::Summarize)
+                        .await
+                }
+                commands::TRANSCRIBE_MESSAGE => {
+                    self.message_command(&ctx, &command, triggers::Trigger::Transcribe)
+                        .await
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Everything in a message that might link to a video: its content, and any
+/// embeds, e.g. from Discord's link previews, webhooks or bridges. The same
+/// video linked in several places is only picked up once.
+fn message_text(msg: &Message) -> String {
+    let mut text = vec![msg.content.clone()];
+    for embed in &msg.embeds {
+        text.extend(embed.url.clone());
+        text.extend(embed.title.clone());
+        text.extend(embed.description.clone());
+        text.extend(embed.fields.iter().map(|field| field.value.clone()));
+        // A video's own preview links to its player.
+        text.extend(embed.video.as_ref().map(|video| video.url.clone()));
+        text.extend(embed.author.as_ref().and_then(|author| author.url.clone()));
+        text.extend(embed.footer.as_ref().map(|footer| footer.text.clone()));
     }
+    text.join("\n")
 }
 
-fn discord_token() -> Option<String> {
-    for (key, value) in env::vars() {
-        if key == "DISCORD_TOKEN" {
-            return Some(value);
+fn playlist_ids_for_message(msg: &str) -> Vec<String> {
+    let mut playlist_ids = Vec::new();
+    LinkFinder::new()
+        .links(msg)
+        .filter(|link| link.kind() == &LinkKind::Url)
+        .filter_map(|url| youtube::playlist_id(url.as_str()))
+        .for_each(|playlist_id| {
+            if !playlist_ids.contains(&playlist_id) {
+                playlist_ids.push(playlist_id);
+            }
+        });
+    playlist_ids
+}
+
+/// Small text files attached to the message, which may list links.
+fn text_attachments(msg: &Message) -> impl Iterator<Item = &Attachment> {
+    msg.attachments.iter().filter(|attachment| {
+        let is_text = attachment.filename.ends_with(".txt")
+            || attachment
+                .content_type
+                .as_deref()
+                .is_some_and(|content_type| content_type.starts_with("text/plain"));
+        is_text && u64::from(attachment.size) <= MAX_ATTACHMENT_BYTES
+    })
+}
+
+/// The contents of any small text files attached to the message, for people
+/// who upload a list of links instead of pasting them.
+async fn attachment_text(msg: &Message) -> String {
+    let mut text = Vec::new();
+    for attachment in text_attachments(msg) {
+        let client = youtube::client();
+        let request = client.http.get(&attachment.url).timeout(client.timeout);
+        let contents = match request.send().await {
+            Ok(response) => response.text().await,
+            Err(why) => Err(why),
+        };
+        match contents {
+            Ok(contents) => text.push(contents),
+            Err(why) => error!(
+                "Error downloading attachment {}: {:?}",
+                attachment.filename, why
+            ),
         }
     }
-    None
+    text.join("\n")
+}
+
+fn links_videos(text: &str) -> bool {
+    !videos_for_message(text).is_empty() || !playlist_ids_for_message(text).is_empty()
 }
 
-fn video_ids_for_message(msg: &str) -> Vec<String> {
+/// Whether the message is worth offering the reactions on, without
+/// downloading its attachments or expanding its playlists.
+fn might_link_videos(msg: &Message) -> bool {
+    links_videos(&message_text(msg)) || text_attachments(msg).next().is_some()
+}
+
+/// Each video is only returned once, in the order it's first linked.
+fn videos_for_message(msg: &str) -> Vec<sources::Video> {
+    let mut videos = Vec::new();
     LinkFinder::new()
         .links(msg)
         .filter(|link| link.kind() == &LinkKind::Url)
-        // get the ids of youtube videos linked in the message
-        .filter_map(|url| youtube::video_id(url.as_str()))
-        .collect()
+        // get the videos linked in the message, from whichever source
+        .filter_map(|url| sources::find(url.as_str()))
+        .for_each(|video| {
+            if !videos.contains(&video) {
+                videos.push(video);
+            }
+        });
+    videos
 }
 
 fn twitter_links_for_message(msg: &str) -> Vec<String> {
@@ -126,103 +444,1447 @@ fn twitter_links_for_message(msg: &str) -> Vec<String> {
         .collect()
 }
 
+/// How many messages `send_video_description` posts `content` in.
+fn messages_needed(content: &str, info: &youtube::VideoInfo, config: &config::Config) -> usize {
+    if content.chars().count() > config.file_threshold {
+        return 1;
+    }
+    let frame = embed::EmbedFrame::new(&info.title, &info.channel_name, Vec::new());
+    let chunk_chars = frame.description_budget().min(config.embed_chunk_chars);
+    utils::break_text_into_chunks(content.to_string(), chunk_chars).len()
+}
+
+/// Post `content` as embeds of up to `embed_chunk_chars`, the first linking
+/// to the video and showing its thumbnail. Returns the messages that were
+/// posted.
+///
+/// Output longer than `file_threshold` characters is attached as a markdown
+/// file instead, with an embed saying what it is.
 async fn send_video_description(
     ctx: &Context,
     content: String,
+    video: &sources::Video,
     info: youtube::VideoInfo,
     channel_id: ChannelId,
-) {
-    let summary_chunks = utils::break_text_into_chunks(content, 4096);
+    config: &config::Config,
+) -> Vec<MessageId> {
+    let timestamp = info
+        .published_at
+        .filter(|_| config.show_publish_date)
+        .and_then(|published_at| Timestamp::from_unix_timestamp(published_at.timestamp()).ok());
+    let frame = embed::EmbedFrame::new(&info.title, &info.channel_name, Vec::new());
+    if content.chars().count() > config.file_threshold {
+        let filename = format!("{}.md", utils::file_stem(&info.title));
+        let embed = CreateEmbed::new()
+            .title(frame.title(0, 1))
+            .description(format!(
+                "This is too long to read here, so it's attached as `{filename}` ({} words).",
+                content.split_whitespace().count()
+            ))
+            .url(video.url())
+            .thumbnail(video.thumbnail_url())
+            .footer(CreateEmbedFooter::new(frame.footer.clone()));
+        let embed = match timestamp {
+            Some(timestamp) => embed.timestamp(timestamp),
+            None => embed,
+        };
+        let message = CreateMessage::new()
+            .embed(embed)
+            .add_file(CreateAttachment::bytes(content.into_bytes(), filename));
+        return match channel_id.send_message(&ctx.http, message).await {
+            Ok(message) => vec![message.id],
+            Err(why) => {
+                error!("Error sending message: {:?}", why);
+                Vec::new()
+            }
+        };
+    }
+    let chunk_chars = frame.description_budget().min(config.embed_chunk_chars);
+    let summary_chunks = utils::break_text_into_chunks(content, chunk_chars);
     let num_chunks = summary_chunks.len();
+    let mut message_ids = Vec::new();
     for (index, summary_chunk) in summary_chunks.into_iter().enumerate() {
-        let part = if num_chunks != 1 {
-            format!(" (part {}/{})", index + 1, num_chunks)
-        } else {
-            String::new()
-        };
-
         let embed = CreateEmbed::new()
-            .title(format!("{}{part}", info.title.clone()))
+            .title(frame.title(index, num_chunks))
             .description(summary_chunk)
-            .footer(CreateEmbedFooter::new(info.channel_name.clone()));
+            .fields(
+                frame
+                    .fields
+                    .iter()
+                    .map(|(name, value)| (name.clone(), value.clone(), false)),
+            )
+            .footer(CreateEmbedFooter::new(frame.footer.clone()));
+        // Discord renders this next to the footer in the reader's timezone.
+        let embed = match timestamp {
+            Some(timestamp) => embed.timestamp(timestamp),
+            None => embed,
+        };
+        // Later parts are read straight after the first, so only it links
+        // back to the video.
+        let embed = if index == 0 {
+            embed.url(video.url()).thumbnail(video.thumbnail_url())
+        } else {
+            embed
+        };
         let message = CreateMessage::new().embed(embed);
-        if let Err(why) = channel_id.send_message(&ctx.http, message).await {
-            println!("Error sending message: {:?}", why);
+        match channel_id.send_message(&ctx.http, message).await {
+            Ok(message) => message_ids.push(message.id),
+            Err(why) => error!("Error sending message: {:?}", why),
         }
     }
+    message_ids
 }
 
-async fn summarize_videos(ctx: Context, msg: &Message) {
-    let video_ids = video_ids_for_message(&msg.content);
-    for video_id in video_ids {
-        let typing = msg.channel_id.start_typing(&ctx.http);
-        match youtube::get_video_summary(&video_id).await {
-            Ok((summary, info)) => {
-                send_video_description(&ctx, summary, info, msg.channel_id).await;
+/// Most often a streaming summary's preview is edited, to stay clear of
+/// Discord's rate limits.
+const PREVIEW_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Post a summary as it's being written, editing the message as more comes
+/// in until the sender is dropped. Returns the message to clean up, if one
+/// was posted.
+async fn stream_preview(
+    ctx: &Context,
+    channel_id: ChannelId,
+    receiver: Option<watch::Receiver<String>>,
+) -> Option<MessageId> {
+    let mut receiver = receiver?;
+    let mut preview: Option<MessageId> = None;
+    while receiver.changed().await.is_ok() {
+        let text = receiver.borrow_and_update().clone();
+        // Embed descriptions are capped at 4096 characters, so only the end
+        // of a long summary is shown.
+        let skip = text.chars().count().saturating_sub(4000);
+        let text: String = match skip {
+            0 => text,
+            _ => std::iter::once('…')
+                .chain(text.chars().skip(skip))
+                .collect(),
+        };
+        let embed = CreateEmbed::new().title("Summarizing…").description(text);
+        let result = match preview {
+            Some(message_id) => {
+                channel_id
+                    .edit_message(&ctx.http, message_id, EditMessage::new().embed(embed))
+                    .await
             }
-            Err(why) => {
-                if let Err(why) = msg
-                    .channel_id
-                    .say(&ctx.http, format!("Summary error: {why:?}"))
+            None => {
+                channel_id
+                    .send_message(&ctx.http, CreateMessage::new().embed(embed))
                     .await
-                {
-                    println!("Error sending message: {:?}", why);
+            }
+        };
+        match result {
+            Ok(message) => preview = Some(message.id),
+            Err(why) => warn!("Error updating preview: {:?}", why),
+        }
+        tokio::time::sleep(PREVIEW_INTERVAL).await;
+    }
+    preview
+}
+
+/// Whether to skip a message or reaction from `author`.
+fn ignores_author(
+    policy: config::IgnoreBots,
+    bot_id: Option<UserId>,
+    author: UserId,
+    author_is_bot: bool,
+) -> bool {
+    bot_id == Some(author) || (author_is_bot && policy == config::IgnoreBots::All)
+}
+
+/// Who reacted, and whether they're a bot. Reactions in DMs come without a
+/// member, but bots can't DM each other, so there it's a person or us.
+fn reactor(reaction: &Reaction) -> Option<(UserId, bool)> {
+    let user_id = reaction.user_id?;
+    let is_bot = reaction
+        .member
+        .as_ref()
+        .is_some_and(|member| member.user.bot);
+    Some((user_id, is_bot))
+}
+
+/// What to post once a multi-video job is done, if anything failed. Failures
+/// are collected into one message rather than one per video.
+fn error_report(
+    label: &str,
+    total: usize,
+    failures: &[(String, youtube::Error)],
+    stopped_at: Option<usize>,
+) -> Option<String> {
+    match (failures, stopped_at) {
+        ([], _) => None,
+        ([(_, why)], _) if total == 1 => Some(format!("{label}: {why}")),
+        ([.., (video_id, why)], Some(position)) => Some(format!(
+            "{label}: stopped after video {position} of {total} failed.\n- `{video_id}`: {why}"
+        )),
+        _ => Some(
+            std::iter::once(format!("{} of {total} videos failed:", failures.len()))
+                .chain(
+                    failures
+                        .iter()
+                        .map(|(video_id, why)| format!("- `{video_id}`: {why}")),
+                )
+                .collect::<Vec<_>>()
+                .join("\n"),
+        ),
+    }
+}
+
+/// Give up on a job that's still running after `timeout`. Dropping the job
+/// cancels it; cleanup chunks it already finished stay checkpointed. A job
+/// that panics fails like any other, so the videos after it still get done.
+async fn with_timeout<T>(
+    timeout: Duration,
+    job: impl Future<Output = Result<T, youtube::Error>>,
+) -> Result<T, youtube::Error> {
+    let job = std::panic::AssertUnwindSafe(job).catch_unwind();
+    match tokio::time::timeout(timeout, job).await {
+        Ok(Ok(result)) => result,
+        Ok(Err(panic)) => {
+            let message = panic
+                .downcast_ref::<&str>()
+                .map(|message| message.to_string())
+                .or_else(|| panic.downcast_ref::<String>().cloned())
+                .unwrap_or_else(|| "unknown panic".to_string());
+            error!("Job panicked: {message}");
+            Err(youtube::Error::Panicked(message))
+        }
+        Err(_) => Err(youtube::Error::TimedOut(timeout)),
+    }
+}
+
+impl Handler {
+    /// Where to post a video's output. Requesters who set `/prefs` get it in a
+    /// DM or a thread. Otherwise output that takes more than one embed goes in
+    /// a new thread named after the video, so it doesn't crowd the channel.
+    /// With Q&A threads on, summaries always get one, registered so messages
+    /// in it are answered as questions about the video.
+    async fn output_channel(
+        &self,
+        ctx: &Context,
+        channel_id: ChannelId,
+        guild_id: Option<GuildId>,
+        message_id: Option<MessageId>,
+        (content, info): &(String, youtube::VideoInfo),
+        job: &outputs::Output,
+    ) -> ChannelId {
+        let qa_video = Some(&job.video)
+            .filter(|_| self.config.qa_threads && job.action == youtube::Action::Summarize);
+        let webhook_only = self
+            .config
+            .webhook
+            .as_ref()
+            .is_some_and(|webhook| webhook.only);
+        if webhook_only {
+            return channel_id;
+        }
+        let delivery = self.settings.delivery(job.requester);
+        if delivery == settings::Delivery::Dm {
+            match job.requester.create_dm_channel(&ctx.http).await {
+                Ok(dm) => return dm.id,
+                // e.g. they don't accept DMs from server members.
+                Err(why) => error!("Error opening DM with {}: {:?}", job.requester, why),
+            }
+        }
+        if guild_id.is_none()
+            || (qa_video.is_none()
+                && delivery != settings::Delivery::Thread
+                && messages_needed(content, info, &self.config) <= 1)
+        {
+            return channel_id;
+        }
+
+        // Thread names are capped at 100 characters.
+        let name: String = info.title.chars().take(100).collect();
+        let from_message = match message_id {
+            Some(message_id) => channel_id
+                .create_thread_from_message(&ctx.http, message_id, CreateThread::new(name.clone()))
+                .await
+                .ok(),
+            None => None,
+        };
+        let thread = match from_message {
+            Some(thread) => Ok(thread),
+            // The message may already have a thread, e.g. from an earlier
+            // video linked in it.
+            None => {
+                channel_id
+                    .create_thread(
+                        &ctx.http,
+                        CreateThread::new(name).kind(ChannelType::PublicThread),
+                    )
+                    .await
+            }
+        };
+        match thread {
+            Ok(thread) => {
+                if let Some(video) = qa_video {
+                    self.qa.register(thread.id, video.clone());
                 }
+                thread.id
+            }
+            Err(why) => {
+                error!("Error creating thread: {:?}", why);
+                channel_id
             }
         }
+    }
+
+    async fn remove_own_reaction(&self, ctx: &Context, message: &Message, emoji: ReactionType) {
+        if let Err(why) = message
+            .channel_id
+            .delete_reaction(&ctx.http, message.id, None, emoji)
+            .await
+        {
+            error!("Error removing reaction: {:?}", why);
+        }
+    }
+
+    async fn answer_question(&self, ctx: &Context, msg: &Message, question: qa::Question) {
+        let content = match question {
+            qa::Question::LimitReached(max) => {
+                format!("This thread has reached its limit of {max} questions.")
+            }
+            qa::Question::Ask(video) => match self.over_budget(msg.guild_id) {
+                Some(reason) => reason,
+                None => self.ask_about(ctx, msg, &video).await,
+            },
+        };
+        for chunk in utils::break_text_into_chunks(content, embed::MESSAGE_LIMIT) {
+            if let Err(why) = msg.reply(&ctx.http, chunk).await {
+                error!("Error sending message: {:?}", why);
+            }
+        }
+    }
+
+    /// The answer to the question in `msg`, or what went wrong, counting the
+    /// tokens against the server's budget.
+    async fn ask_about(&self, ctx: &Context, msg: &Message, video: &sources::Video) -> String {
+        let typing = msg.channel_id.start_typing(&ctx.http);
+        let answer = video
+            .source
+            .answer_question(
+                &video.id,
+                msg.content.clone(),
+                &self.cache,
+                &self.config.model,
+            )
+            .await;
         let _ = typing.stop();
+        match answer {
+            Ok((answer, usage)) => {
+                self.budgets.record(msg.guild_id, usage);
+                answer
+            }
+            Err(why) => format!("Question error: {why}"),
+        }
     }
-}
 
-async fn transcribe_videos(ctx: Context, msg: &Message) {
-    let video_ids = video_ids_for_message(&msg.content);
-    for video_id in video_ids {
+    fn ignores(&self, author: UserId, author_is_bot: bool) -> bool {
+        ignores_author(
+            self.config.ignore_bots,
+            self.bot_id.get().copied(),
+            author,
+            author_is_bot,
+        )
+    }
+
+    /// Videos linked anywhere in the message, including text attachments and
+    /// playlists, which are expanded to their first
+    /// `Config::max_playlist_videos` videos. At most
+    /// `Config::max_videos_per_message` are returned, with a note saying what
+    /// was left out, as well as for each playlist that was cut short.
+    async fn videos(&self, msg: &Message) -> (Vec<sources::Video>, Vec<String>) {
+        let text = format!("{}\n{}", message_text(msg), attachment_text(msg).await);
+        let mut videos = videos_for_message(&text);
+
+        let mut notes = Vec::new();
+        for playlist_id in playlist_ids_for_message(&text) {
+            let playlist = youtube::client()
+                .playlist_video_ids(&playlist_id, self.config.max_playlist_videos)
+                .await;
+            match playlist {
+                Ok((playlist_video_ids, truncated)) => {
+                    if truncated {
+                        notes.push(format!(
+                            "Playlist `{playlist_id}` is long, so only its first {} videos are included.",
+                            self.config.max_playlist_videos
+                        ));
+                    }
+                    for video_id in playlist_video_ids {
+                        let video = sources::Video::new(&youtube::YouTube, video_id);
+                        if !videos.contains(&video) {
+                            videos.push(video);
+                        }
+                    }
+                }
+                Err(why) => error!("Error fetching playlist {playlist_id}: {why}"),
+            }
+        }
+        let max = self.config.max_videos_per_message;
+        if videos.len() > max {
+            notes.push(format!(
+                "That's {} videos, so only the first {max} are included.",
+                videos.len()
+            ));
+            videos.truncate(max);
+        }
+        if videos.is_empty() {
+            notes.push("There's no YouTube link in that message.".to_string());
+        }
+        (videos, notes)
+    }
+
+    /// The summary a message replies to, if it's one we posted whole in a
+    /// single embed. Transcripts, parts of longer output and anything else
+    /// of ours aren't summaries to refine.
+    fn replied_summary(&self, msg: &Message) -> Option<(Embed, outputs::Output)> {
+        let replied = msg.referenced_message.as_ref()?;
+        if Some(&replied.author.id) != self.bot_id.get() {
+            return None;
+        }
+        let output = self
+            .outputs
+            .get(replied.id)
+            .filter(|output| output.action == youtube::Action::Summarize && output.whole)?;
+        let summary = replied
+            .embeds
+            .first()
+            .filter(|embed| embed.description.is_some())?;
+        Some((summary.clone(), output))
+    }
+
+    /// Rewrite a summary the way a reply to it asks, e.g. "make it shorter".
+    /// Only the summary is sent to the model, not the whole transcript. A
+    /// refined summary that fits in one embed can be refined again.
+    async fn refine_summary(
+        &self,
+        ctx: &Context,
+        msg: &Message,
+        summary: Embed,
+        output: outputs::Output,
+    ) {
         let typing = msg.channel_id.start_typing(&ctx.http);
-        match youtube::get_video_transcript(&video_id).await {
-            Ok((summary, info)) => {
-                send_video_description(&ctx, summary, info, msg.channel_id).await;
+        let refined = youtube::refine_summary(
+            summary.description.clone().unwrap_or_default(),
+            msg.content.clone(),
+            &self.config.model,
+        )
+        .await;
+        let _ = typing.stop();
+        let refined = match refined {
+            Ok(refined) => refined,
+            Err(why) => {
+                if let Err(why) = msg.reply(&ctx.http, format!("Refine error: {why}")).await {
+                    error!("Error sending message: {:?}", why);
+                }
+                return;
+            }
+        };
+
+        let frame = embed::EmbedFrame::new(
+            summary.title.as_deref().unwrap_or_default(),
+            summary
+                .footer
+                .as_ref()
+                .map(|footer| footer.text.as_str())
+                .unwrap_or_default(),
+            Vec::new(),
+        );
+        let chunks = utils::break_text_into_chunks(refined, frame.description_budget());
+        let num_chunks = chunks.len();
+        for (index, chunk) in chunks.into_iter().enumerate() {
+            let embed = CreateEmbed::new()
+                .title(frame.title(index, num_chunks))
+                .description(chunk)
+                .footer(CreateEmbedFooter::new(frame.footer.clone()));
+            let message = CreateMessage::new().embed(embed).reference_message(msg);
+            match msg.channel_id.send_message(&ctx.http, message).await {
+                Ok(message) if num_chunks == 1 => self.outputs.remember(message.id, output.clone()),
+                Ok(_) => {}
+                Err(why) => error!("Error sending message: {:?}", why),
+            }
+        }
+    }
+
+    /// Whether links in the channel are picked up. `Config::allowed_channels`
+    /// only limits server channels, not DMs.
+    fn allowed_in(&self, channel_id: ChannelId, guild_id: Option<GuildId>) -> bool {
+        guild_id.is_none()
+            || self.config.allowed_channels.is_empty()
+            || self.config.allowed_channels.contains(&channel_id)
+    }
+
+    /// DMs are always enabled; guilds follow their stored flag.
+    fn enabled_in(&self, guild_id: Option<GuildId>) -> bool {
+        guild_id.map_or(true, |guild_id| {
+            self.settings
+                .guild_enabled(guild_id, self.config.guilds_enabled_by_default)
+        })
+    }
+
+    /// Do what `trigger` asks for with the videos `message` links to, marking
+    /// it ⏳ while working and ✅ or ❌ once done. Returns whether it worked.
+    async fn run_job(
+        &self,
+        ctx: &Context,
+        message: &Message,
+        trigger: triggers::Trigger,
+        requester: UserId,
+    ) -> bool {
+        if self.in_progress.start(message.id) {
+            for emoji in [DONE_EMOJI, FAILED_EMOJI] {
+                self.remove_own_reaction(ctx, message, ReactionType::Unicode(emoji.to_string()))
+                    .await;
+            }
+            if let Err(why) = message
+                .react(&ctx.http, ReactionType::Unicode(WORKING_EMOJI.to_string()))
+                .await
+            {
+                error!("Error adding reaction: {:?}", why);
+            }
+        }
+
+        let (job, handle) = future::abortable(async {
+            match trigger {
+                triggers::Trigger::Transcribe => {
+                    self.transcribe_videos(ctx.clone(), message, requester)
+                        .await
+                }
+                triggers::Trigger::Summarize
+                | triggers::Trigger::Bullets
+                | triggers::Trigger::Notes => {
+                    let mut options = self.summary_options(
+                        options::SummaryOptions::parse(&message.content),
+                        message.channel_id,
+                        message.guild_id,
+                    );
+                    match trigger {
+                        triggers::Trigger::Bullets => {
+                            options.style = Some(options::SummaryStyle::Bullets)
+                        }
+                        triggers::Trigger::Notes => {
+                            options.style = Some(options::SummaryStyle::Notes)
+                        }
+                        _ => {}
+                    }
+                    self.summarize_videos(ctx.clone(), message, &options, requester)
+                        .await
+                }
+            }
+        });
+        self.cancellable
+            .start(message.id, trigger, requester, handle);
+        let succeeded = match job.await {
+            Ok(succeeded) => succeeded,
+            Err(future::Aborted) => {
+                info!("{requester} cancelled their job on message {}", message.id);
+                if let Err(why) = message.reply(&ctx.http, "Cancelled.").await {
+                    error!("Error sending message: {:?}", why);
+                }
+                false
+            }
+        };
+        self.cancellable.finish(message.id, trigger, requester);
+
+        if let Some(succeeded) = self.in_progress.finish(message.id, succeeded) {
+            self.remove_own_reaction(
+                ctx,
+                message,
+                ReactionType::Unicode(WORKING_EMOJI.to_string()),
+            )
+            .await;
+            let outcome = if succeeded { DONE_EMOJI } else { FAILED_EMOJI };
+            if let Err(why) = message
+                .react(&ctx.http, ReactionType::Unicode(outcome.to_string()))
+                .await
+            {
+                error!("Error adding reaction: {:?}", why);
+            }
+        }
+        succeeded
+    }
+
+    /// React to a message with links with the emoji that start jobs on it,
+    /// once per message.
+    async fn offer_reactions(&self, ctx: &Context, msg: &Message) {
+        if !self.offered.offer(msg.id) {
+            debug!("Already offered reactions on message {}", msg.id);
+            return;
+        }
+        let triggers = self.settings.triggers(msg.guild_id, &self.config.triggers);
+
+        // Each is tried on its own so one failing doesn't skip the other.
+        for reaction in [
+            triggers.summarize.reaction(),
+            triggers.bullets.reaction(),
+            triggers.notes.reaction(),
+            triggers.transcribe.reaction(),
+        ] {
+            if let Err(why) = msg.react(&ctx.http, reaction).await {
+                error!(
+                    "Error adding reaction to message {} in channel {}: {:?}",
+                    msg.id, msg.channel_id, why
+                );
+            }
+        }
+    }
+
+    /// `options::resolve`, with the server's own summary prompt if it has one.
+    fn summary_options(
+        &self,
+        explicit: options::SummaryOptions,
+        channel_id: ChannelId,
+        guild_id: Option<GuildId>,
+    ) -> options::SummaryOptions {
+        let mut options = options::resolve(
+            explicit,
+            self.settings.channel_defaults(channel_id).as_ref(),
+            &self.config.default_options,
+        );
+        options.system_prompt = self.settings.prompts(guild_id).summary;
+        options
+    }
+
+    /// `Config::transcript_options`, with the server's own transcript prompt
+    /// if it has one.
+    fn transcript_options(&self, guild_id: Option<GuildId>) -> youtube::TranscriptOptions {
+        youtube::TranscriptOptions {
+            system_prompt: self.settings.prompts(guild_id).transcript,
+            ..self.config.transcript_options()
+        }
+    }
+
+    /// Why a server that's used up its token budget can't start a job.
+    fn over_budget(&self, guild_id: Option<GuildId>) -> Option<String> {
+        let resets = self.budgets.check(guild_id).err()?;
+        Some(format!(
+            "This server has used up its OpenAI budget. It resets <t:{}:R>.",
+            resets.timestamp()
+        ))
+    }
+
+    /// Whether the server has budget left for another job. If not, says
+    /// when it resets.
+    async fn within_budget(
+        &self,
+        ctx: &Context,
+        channel_id: ChannelId,
+        guild_id: Option<GuildId>,
+    ) -> bool {
+        let Some(content) = self.over_budget(guild_id) else {
+            return true;
+        };
+        if let Err(why) = channel_id.say(&ctx.http, content).await {
+            error!("Error sending message: {:?}", why);
+        }
+        false
+    }
+
+    /// Whether `requester` can start another job. If not, tells them how long
+    /// to wait.
+    async fn within_rate_limit(
+        &self,
+        ctx: &Context,
+        channel_id: ChannelId,
+        requester: UserId,
+    ) -> bool {
+        let Err(wait) = self.rate_limiter.check(requester) else {
+            return true;
+        };
+        let minutes = wait.as_secs() / 60 + 1;
+        let content = format!(
+            "<@{requester}>, you've made too many requests. Try again in {minutes} minute{}.",
+            if minutes == 1 { "" } else { "s" }
+        );
+        if let Err(why) = channel_id.say(&ctx.http, content).await {
+            error!("Error sending message: {:?}", why);
+        }
+        false
+    }
+
+    /// Like `within_rate_limit`, for a slash or message command, answering it
+    /// privately with how long to wait.
+    async fn command_within_rate_limit(&self, ctx: &Context, command: &CommandInteraction) -> bool {
+        let Err(wait) = self.rate_limiter.check(command.user.id) else {
+            return true;
+        };
+        let minutes = wait.as_secs() / 60 + 1;
+        let content = format!(
+            "You've made too many requests. Try again in {minutes} minute{}.",
+            if minutes == 1 { "" } else { "s" }
+        );
+        commands::respond(ctx, command, content).await;
+        false
+    }
+
+    /// Post a finished job to Discord and, if configured, the webhook. The
+    /// messages it's posted in are remembered, and the last offered
+    /// `REGENERATE_EMOJI`.
+    async fn deliver(
+        &self,
+        ctx: &Context,
+        job: outputs::Output,
+        (content, info): (String, youtube::VideoInfo),
+        channel_id: ChannelId,
+    ) {
+        metrics::increment(match job.action {
+            youtube::Action::Summarize => &METRICS.videos_summarized,
+            youtube::Action::Transcribe => &METRICS.videos_transcribed,
+        });
+        if let Some(webhook) = &self.config.webhook {
+            webhook::dispatch(
+                webhook,
+                webhook::WebhookPayload {
+                    video_id: job.video.id.clone(),
+                    title: info.title.clone(),
+                    channel: info.channel_name.clone(),
+                    action: job.action,
+                    tokens: openai::count_text_tokens(&self.config.model, &content),
+                    summary: content.clone(),
+                    requester: job.requester.get(),
+                },
+            );
+            if webhook.only {
+                return;
+            }
+        }
+        let job = outputs::Output {
+            whole: content.chars().count() <= self.config.file_threshold
+                && messages_needed(&content, &info, &self.config) == 1,
+            ..job
+        };
+        let message_ids =
+            send_video_description(ctx, content, &job.video, info, channel_id, &self.config).await;
+        let Some(&last) = message_ids.last() else {
+            return;
+        };
+        for message_id in message_ids {
+            self.outputs.remember(message_id, job.clone());
+        }
+        let regenerate = ReactionType::Unicode(REGENERATE_EMOJI.to_string());
+        if let Err(why) = channel_id
+            .create_reaction(&ctx.http, last, regenerate)
+            .await
+        {
+            error!("Error adding reaction: {:?}", why);
+        }
+    }
+
+    /// Redo `output` and post it again, if `requester` asked for it in the
+    /// first place or `REGENERATE_ANYONE=true`. The cached copy is dropped so
+    /// it's really made again.
+    async fn regenerate(
+        &self,
+        ctx: &Context,
+        channel_id: ChannelId,
+        guild_id: Option<GuildId>,
+        output: outputs::Output,
+        requester: UserId,
+    ) {
+        if requester != output.requester && !self.config.regenerate_anyone {
+            return;
+        }
+        if !self.within_budget(ctx, channel_id, guild_id).await
+            || !self.within_rate_limit(ctx, channel_id, requester).await
+        {
+            return;
+        }
+        let transcript_options = youtube::TranscriptOptions {
+            language: output.options.language.clone(),
+            ..self.transcript_options(guild_id)
+        };
+        self.cache.remove(&match output.action {
+            youtube::Action::Summarize => {
+                youtube::summary_cache_key(&output.video.id, &output.options, &self.config.model)
+            }
+            youtube::Action::Transcribe => {
+                youtube::transcript_cache_key(&output.video.id, &transcript_options)
+            }
+        });
+        let typing = channel_id.start_typing(&ctx.http);
+        let (result, label) = match output.action {
+            youtube::Action::Summarize => {
+                let job = output.video.source.summary(
+                    &output.video.id,
+                    &output.options,
+                    &self.cache,
+                    self.config.describe_no_speech,
+                    &self.config.model,
+                    None,
+                );
+                (
+                    with_timeout(self.config.job_timeout, job).await,
+                    "Summary error",
+                )
+            }
+            youtube::Action::Transcribe => {
+                let job = output.video.source.transcript(
+                    &output.video.id,
+                    &self.cache,
+                    &transcript_options,
+                );
+                (
+                    with_timeout(self.config.job_timeout, job).await,
+                    "Transcription error",
+                )
+            }
+        };
+        let _ = typing.stop();
+        match result {
+            Ok((result, usage)) => {
+                self.budgets.record(guild_id, usage);
+                self.deliver(ctx, output, result, channel_id).await;
+                self.report_usage(ctx, channel_id, usage).await;
             }
             Err(why) => {
-                if let Err(why) = msg
-                    .channel_id
-                    .say(&ctx.http, format!("Transcription error: {why:?}"))
-                    .await
-                {
-                    println!("Error sending message: {:?}", why);
+                if let Err(why) = channel_id.say(&ctx.http, format!("{label}: {why}")).await {
+                    error!("Error sending message: {:?}", why);
+                }
+            }
+        }
+    }
+
+    /// How many tokens a job took, with `SHOW_USAGE=true`. Nothing for
+    /// cached results, which didn't take any.
+    fn usage_note(&self, usage: openai::Usage) -> Option<String> {
+        (self.config.show_usage && usage.total_tokens > 0)
+            .then(|| usage.describe(self.config.cost_per_1k_tokens))
+    }
+
+    /// Post `usage_note` after a delivered result, unless it only went to the
+    /// webhook.
+    async fn report_usage(&self, ctx: &Context, channel_id: ChannelId, usage: openai::Usage) {
+        if self
+            .config
+            .webhook
+            .as_ref()
+            .is_some_and(|webhook| webhook.only)
+        {
+            return;
+        }
+        if let Some(note) = self.usage_note(usage) {
+            if let Err(why) = channel_id.say(&ctx.http, format!("*{note}*")).await {
+                error!("Error sending message: {:?}", why);
+            }
+        }
+    }
+
+    /// `/raw url:<link>`: post the transcript as-is, skipping the OpenAI cleanup.
+    async fn raw_transcript(&self, ctx: &Context, command: &CommandInteraction) {
+        let Some(video) =
+            commands::string_option(command, "url").and_then(|url| sources::find(url.trim()))
+        else {
+            commands::respond(ctx, command, "That isn't a YouTube link.".to_string()).await;
+            return;
+        };
+
+        if let Err(why) = command.defer(&ctx.http).await {
+            error!("Error deferring command: {:?}", why);
+            return;
+        }
+        let content = match sources::raw_transcript(&video).await {
+            Ok((transcript, info)) => {
+                let content = format!("Raw transcript of **{}**", info.title);
+                let transcript = format!("{RAW_TRANSCRIPT_NOTE}\n\n{transcript}");
+                send_video_description(
+                    ctx,
+                    transcript,
+                    &video,
+                    info,
+                    command.channel_id,
+                    &self.config,
+                )
+                .await;
+                content
+            }
+            Err(why) => format!("Transcript error: {why}"),
+        };
+        commands::edit_response(ctx, command, content).await;
+    }
+
+    /// `/stats`: what the cache holds and the work done since startup, plus
+    /// the server's budget left when there are budgets.
+    async fn stats_command(&self, ctx: &Context, command: &CommandInteraction) {
+        let tokens = |total_tokens| {
+            openai::Usage {
+                total_tokens,
+                ..openai::Usage::default()
+            }
+            .describe(self.config.cost_per_1k_tokens)
+        };
+        let count = |counter: &std::sync::atomic::AtomicU64| {
+            utils::thousands(counter.load(Ordering::Relaxed))
+        };
+        let hit_rate = match METRICS.cache_hit_rate() {
+            Some(rate) => format!("{:.0}%", rate * 100.0),
+            None => "No lookups yet".to_string(),
+        };
+        let mut fields = vec![
+            (
+                "Cached outputs",
+                utils::thousands(self.cache.entry_count() as u64),
+            ),
+            ("Cache hit rate", hit_rate),
+            ("Videos summarized", count(&METRICS.videos_summarized)),
+            ("Videos transcribed", count(&METRICS.videos_transcribed)),
+            (
+                "Tokens used",
+                tokens(METRICS.tokens_used.load(Ordering::Relaxed)),
+            ),
+            ("OpenAI errors", count(&METRICS.openai_errors)),
+        ];
+        if let Some(remaining) = self.budgets.remaining(command.guild_id) {
+            fields.push(("Budget left", tokens(remaining)));
+        }
+        let embed = CreateEmbed::new()
+            .title("Stats")
+            .footer(CreateEmbedFooter::new(
+                "Counts are since the bot last started",
+            ))
+            .fields(fields.into_iter().map(|(name, value)| (name, value, true)));
+        let response = CreateInteractionResponse::Message(
+            CreateInteractionResponseMessage::new()
+                .embed(embed)
+                .ephemeral(true),
+        );
+        if let Err(why) = command.create_response(&ctx.http, response).await {
+            error!("Error responding to command: {:?}", why);
+        }
+    }
+
+    /// `/estimate url:<link>`: the tokens and cost of summarizing and
+    /// transcribing a video, without calling OpenAI.
+    async fn estimate_command(&self, ctx: &Context, command: &CommandInteraction) {
+        let Some(video) =
+            commands::string_option(command, "url").and_then(|url| sources::find(url.trim()))
+        else {
+            commands::respond(ctx, command, "That isn't a YouTube link.".to_string()).await;
+            return;
+        };
+        let options = self.summary_options(
+            options::SummaryOptions::default(),
+            command.channel_id,
+            command.guild_id,
+        );
+
+        if let Err(why) = command.defer_ephemeral(&ctx.http).await {
+            error!("Error deferring command: {:?}", why);
+            return;
+        }
+        let estimate = video
+            .source
+            .estimate(&video.id, &options, &self.config.model)
+            .await;
+        let content = match estimate {
+            Ok(estimate) => {
+                let cost = self.config.cost_per_1k_tokens;
+                let summary = if estimate.summary_in_parts {
+                    format!(
+                        "{}, plus a bit more to summarize it in parts",
+                        estimate.summary.describe(cost)
+                    )
+                } else {
+                    estimate.summary.describe(cost)
+                };
+                let transcript = if estimate.transcript_too_long {
+                    "too long to clean up".to_string()
+                } else {
+                    estimate.transcript.describe(cost)
+                };
+                format!(
+                    "**{}** with `{}`:\n- Summary: {summary}\n- Transcript: {transcript}",
+                    estimate.info.title, self.config.model
+                )
+            }
+            Err(why) => format!("Couldn't estimate that video: {why}"),
+        };
+        commands::edit_response(ctx, command, content).await;
+    }
+
+    /// `/summarize url:<link>`, with optional `portion`, `length` and
+    /// `language` that take precedence over the channel's defaults.
+    async fn summarize_command(&self, ctx: &Context, command: &CommandInteraction) {
+        let Some(video) =
+            commands::string_option(command, "url").and_then(|url| sources::find(url.trim()))
+        else {
+            commands::respond(ctx, command, "That isn't a YouTube link.".to_string()).await;
+            return;
+        };
+
+        let mut explicit = options::SummaryOptions {
+            length: commands::string_option(command, "length")
+                .and_then(|length| options::SummaryLength::parse(&length)),
+            language: commands::string_option(command, "language"),
+            portion: None,
+            style: None,
+            system_prompt: None,
+        };
+        if let Some(portion) = commands::string_option(command, "portion") {
+            match options::Portion::parse(&portion) {
+                Ok(portion) => explicit.portion = Some(portion),
+                Err(why) => {
+                    commands::respond(ctx, command, why).await;
+                    return;
+                }
+            }
+        }
+        let options = self.summary_options(explicit, command.channel_id, command.guild_id);
+        if let Some(content) = self.over_budget(command.guild_id) {
+            commands::respond(ctx, command, content).await;
+            return;
+        }
+        if !self.command_within_rate_limit(ctx, command).await {
+            return;
+        }
+
+        if let Err(why) = command.defer(&ctx.http).await {
+            error!("Error deferring command: {:?}", why);
+            return;
+        }
+        let job = video.source.summary(
+            &video.id,
+            &options,
+            &self.cache,
+            self.config.describe_no_speech,
+            &self.config.model,
+            None,
+        );
+        let content = match with_timeout(self.config.job_timeout, job).await {
+            Ok((output, usage)) => {
+                self.budgets.record(command.guild_id, usage);
+                let content = format!("Summary of **{}**", output.1.title);
+                let job = outputs::Output::summary(video, &options, command.user.id);
+                let channel_id = self
+                    .output_channel(
+                        ctx,
+                        command.channel_id,
+                        command.guild_id,
+                        None,
+                        &output,
+                        &job,
+                    )
+                    .await;
+                self.deliver(ctx, job, output, channel_id).await;
+                match self.usage_note(usage) {
+                    Some(note) => format!("{content}\n{note}"),
+                    None => content,
+                }
+            }
+            Err(why) => format!("Summary error: {why}"),
+        };
+        commands::edit_response(ctx, command, content).await;
+    }
+
+    /// The "Summarize video" and "Transcribe video" entries in a message's
+    /// Apps menu, which work like reacting to it.
+    async fn message_command(
+        &self,
+        ctx: &Context,
+        command: &CommandInteraction,
+        trigger: triggers::Trigger,
+    ) {
+        let Some(ResolvedTarget::Message(message)) = command.data.target() else {
+            return;
+        };
+        // Messages resolved for an interaction don't say which guild they're in.
+        let mut message = message.clone();
+        message.guild_id = command.guild_id;
+        if !might_link_videos(&message) {
+            let content = "There's no YouTube link in that message.".to_string();
+            commands::respond(ctx, command, content).await;
+            return;
+        }
+        if !self.requested.claim(message.id, trigger) {
+            let content = "Someone else already asked for that.".to_string();
+            commands::respond(ctx, command, content).await;
+            return;
+        }
+        if let Some(content) = self.over_budget(command.guild_id) {
+            self.requested.finish(message.id, trigger, false);
+            commands::respond(ctx, command, content).await;
+            return;
+        }
+        if !self.command_within_rate_limit(ctx, command).await {
+            self.requested.finish(message.id, trigger, false);
+            return;
+        }
+
+        if let Err(why) = command.defer_ephemeral(&ctx.http).await {
+            error!("Error deferring command: {:?}", why);
+            self.requested.finish(message.id, trigger, false);
+            return;
+        }
+        let succeeded = self.run_job(ctx, &message, trigger, command.user.id).await;
+        self.requested.finish(message.id, trigger, succeeded);
+        let content = if succeeded {
+            "Done."
+        } else {
+            "That didn't work, see the replies to the message for why."
+        };
+        commands::edit_response(ctx, command, content.to_string()).await;
+    }
+
+    /// `/transcribe url:<link>`.
+    async fn transcribe_command(&self, ctx: &Context, command: &CommandInteraction) {
+        let Some(video) =
+            commands::string_option(command, "url").and_then(|url| sources::find(url.trim()))
+        else {
+            commands::respond(ctx, command, "That isn't a YouTube link.".to_string()).await;
+            return;
+        };
+        if let Some(content) = self.over_budget(command.guild_id) {
+            commands::respond(ctx, command, content).await;
+            return;
+        }
+        if !self.command_within_rate_limit(ctx, command).await {
+            return;
+        }
+
+        if let Err(why) = command.defer(&ctx.http).await {
+            error!("Error deferring command: {:?}", why);
+            return;
+        }
+        let options = youtube::TranscriptOptions {
+            language: commands::string_option(command, "language")
+                .map(|language| language.trim().to_lowercase()),
+            ..self.transcript_options(command.guild_id)
+        };
+        let job = video.source.transcript(&video.id, &self.cache, &options);
+        let content = match with_timeout(self.config.job_timeout, job).await {
+            Ok((output, usage)) => {
+                self.budgets.record(command.guild_id, usage);
+                let content = format!("Transcript of **{}**", output.1.title);
+                let job =
+                    outputs::Output::transcript(video, options.language.clone(), command.user.id);
+                let channel_id = self
+                    .output_channel(
+                        ctx,
+                        command.channel_id,
+                        command.guild_id,
+                        None,
+                        &output,
+                        &job,
+                    )
+                    .await;
+                self.deliver(ctx, job, output, channel_id).await;
+                match self.usage_note(usage) {
+                    Some(note) => format!("{content}\n{note}"),
+                    None => content,
                 }
             }
+            Err(why) => format!("Transcription error: {why}"),
+        };
+        commands::edit_response(ctx, command, content).await;
+    }
+
+    /// Whether every video was summarized, so the reaction that asked for
+    /// it can be marked done or failed.
+    async fn summarize_videos(
+        &self,
+        ctx: Context,
+        msg: &Message,
+        options: &options::SummaryOptions,
+        requester: UserId,
+    ) -> bool {
+        let (videos, notes) = self.videos(msg).await;
+        for note in notes {
+            if let Err(why) = msg.channel_id.say(&ctx.http, note).await {
+                error!("Error sending message: {:?}", why);
+            }
+        }
+        if videos.is_empty() {
+            return false;
+        }
+        let mut failures = Vec::new();
+        let mut stopped_at = None;
+        // Serenity re-sends the typing indicator every few seconds until it's
+        // stopped, so one covers every video without a gap between them.
+        let typing = msg.channel_id.start_typing(&ctx.http);
+        for (index, video) in videos.iter().enumerate() {
+            // Nobody would see a preview if only the webhook gets the result.
+            let stream = self.config.stream_summaries
+                && !self
+                    .config
+                    .webhook
+                    .as_ref()
+                    .is_some_and(|webhook| webhook.only);
+            let (progress, receiver) = stream.then(|| watch::channel(String::new())).unzip();
+            let job = video.source.summary(
+                &video.id,
+                options,
+                &self.cache,
+                self.config.describe_no_speech,
+                &self.config.model,
+                progress,
+            );
+            let (result, preview) = tokio::join!(
+                with_timeout(self.config.job_timeout, job),
+                stream_preview(&ctx, msg.channel_id, receiver),
+            );
+            // The finished summary is posted like any other, which may be in
+            // a thread or split over several embeds.
+            if let Some(preview) = preview {
+                if let Err(why) = msg.channel_id.delete_message(&ctx.http, preview).await {
+                    error!("Error deleting preview: {:?}", why);
+                }
+            }
+            match result {
+                Ok((output, usage)) => {
+                    let job = outputs::Output::summary(video.clone(), options, requester);
+                    let channel_id = self
+                        .output_channel(
+                            &ctx,
+                            msg.channel_id,
+                            msg.guild_id,
+                            Some(msg.id),
+                            &output,
+                            &job,
+                        )
+                        .await;
+                    self.budgets.record(msg.guild_id, usage);
+                    self.deliver(&ctx, job, output, channel_id).await;
+                    self.report_usage(&ctx, channel_id, usage).await;
+                }
+                Err(why) => {
+                    failures.push((video.id.clone(), why));
+                    if self.config.error_policy == config::ErrorPolicy::Stop {
+                        stopped_at = Some(index + 1);
+                    }
+                }
+            }
+            if stopped_at.is_some() {
+                break;
+            }
         }
         let _ = typing.stop();
+        if let Some(report) = error_report("Summary error", videos.len(), &failures, stopped_at) {
+            if let Err(why) = msg.channel_id.say(&ctx.http, report).await {
+                error!("Error sending message: {:?}", why);
+            }
+        }
+        failures.is_empty()
+    }
+
+    /// Whether every video was transcribed, like `summarize_videos`.
+    async fn transcribe_videos(&self, ctx: Context, msg: &Message, requester: UserId) -> bool {
+        let options = youtube::TranscriptOptions {
+            language: options::transcript_language(&msg.content),
+            ..self.transcript_options(msg.guild_id)
+        };
+        let (videos, notes) = self.videos(msg).await;
+        for note in notes {
+            if let Err(why) = msg.channel_id.say(&ctx.http, note).await {
+                error!("Error sending message: {:?}", why);
+            }
+        }
+        if videos.is_empty() {
+            return false;
+        }
+        let mut failures = Vec::new();
+        let mut stopped_at = None;
+        let typing = msg.channel_id.start_typing(&ctx.http);
+        for (index, video) in videos.iter().enumerate() {
+            let job = video.source.transcript(&video.id, &self.cache, &options);
+            match with_timeout(self.config.job_timeout, job).await {
+                Ok((output, usage)) => {
+                    let job = outputs::Output::transcript(
+                        video.clone(),
+                        options.language.clone(),
+                        requester,
+                    );
+                    let channel_id = self
+                        .output_channel(
+                            &ctx,
+                            msg.channel_id,
+                            msg.guild_id,
+                            Some(msg.id),
+                            &output,
+                            &job,
+                        )
+                        .await;
+                    self.budgets.record(msg.guild_id, usage);
+                    self.deliver(&ctx, job, output, channel_id).await;
+                    self.report_usage(&ctx, channel_id, usage).await;
+                }
+                Err(why) => {
+                    failures.push((video.id.clone(), why));
+                    if self.config.error_policy == config::ErrorPolicy::Stop {
+                        stopped_at = Some(index + 1);
+                    }
+                }
+            }
+            if stopped_at.is_some() {
+                break;
+            }
+        }
+        let _ = typing.stop();
+        if let Some(report) =
+            error_report("Transcription error", videos.len(), &failures, stopped_at)
+        {
+            if let Err(why) = msg.channel_id.say(&ctx.http, report).await {
+                error!("Error sending message: {:?}", why);
+            }
+        }
+        failures.is_empty()
+    }
+}
+
+/// Resolves on Ctrl-C, or on SIGTERM on Unix.
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        let mut terminate =
+            signal(SignalKind::terminate()).expect("Err installing SIGTERM handler");
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => {}
+            _ = terminate.recv() => {}
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = tokio::signal::ctrl_c().await;
+}
+
+/// Wait until no jobs are running, whether asked for by reaction, message
+/// command, slash command, reply or Q&A thread, or `grace` has passed, so they
+/// aren't cut off halfway through posting.
+async fn wait_for_jobs(running: &progress::Running, grace: Duration) {
+    let deadline = tokio::time::Instant::now() + grace;
+    while !running.is_idle() {
+        if tokio::time::Instant::now() >= deadline {
+            warn!("Gave up waiting for running jobs after {grace:?}");
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(250)).await;
     }
 }
 
 #[tokio::main]
 async fn main() {
+    // `RUST_LOG` picks what's logged, e.g. `RUST_LOG=leonidas=debug`.
+    // Logs go to stderr, so command line output can be piped.
+    tracing_subscriber::fmt()
+        .with_writer(std::io::stderr)
+        .with_env_filter(
+            EnvFilter::try_from_default_env()
+                .unwrap_or_else(|_| EnvFilter::new("warn,leonidas=info")),
+        )
+        .init();
     dotenv().ok();
+    let file_config = config::load_file();
+
+    let args = env::args().skip(1).collect::<Vec<_>>();
+    let cli_command = cli::CliCommand::parse(&args).unwrap_or_else(|why| {
+        eprintln!("{why}");
+        std::process::exit(2);
+    });
+    let config = config::Config::from_env(&file_config);
+    let missing = config.missing_vars(cli_command.is_none());
+    if !missing.is_empty() {
+        error!(
+            "Missing required environment variables: {}",
+            missing.join(", ")
+        );
+        std::process::exit(1);
+    }
 
-    // Configure the client with your Discord bot token in the environment.
-    let token = discord_token().expect("Expected a token in the environment");
+    youtube::set_retry_policy(config.retry_policy);
+    prompts::set_min_summary_words(config.min_summary_words);
+    youtube::set_client(youtube::YoutubeClient {
+        transcript_bases: config.transcript_api_bases.clone(),
+        openai_base: config.openai_api_base.clone(),
+        openai_permits: Arc::new(Semaphore::new(config.openai_concurrency)),
+        youtube_token: config.youtube_api_token.clone(),
+        openai_token: config.openai_api_token.clone(),
+        ..youtube::YoutubeClient::with_timeout(config.http_timeout)
+    });
+    if let Some(cli_command) = cli_command {
+        std::process::exit(cli::run(cli_command, &config).await);
+    }
+
+    // Configure the client with your Discord bot token from the environment
+    // or config file.
+    let token = config
+        .discord_token
+        .clone()
+        .expect("Expected a token in the environment");
+    let health_port = config.health_port;
     // Set gateway intents, which decides what events the bot will be notified about
     let intents = GatewayIntents::GUILD_MESSAGES
         | GatewayIntents::DIRECT_MESSAGES
         | GatewayIntents::MESSAGE_CONTENT
-        | GatewayIntents::GUILD_MESSAGE_REACTIONS;
+        | GatewayIntents::GUILD_MESSAGE_REACTIONS
+        | GatewayIntents::DIRECT_MESSAGE_REACTIONS;
 
     // Create a new instance of the Client, logging in as a bot. This will
     // automatically prepend your bot token with "Bot ", which is a requirement
     // by Discord for bot users.
+    let running = Arc::new(progress::Running::new());
+    let cache = cache::Cache::new(
+        config.cache_ttl,
+        config.transcript_ttl,
+        config.cache_max_entries,
+    );
+    let budgets = budget::Budgets::new(config.guild_token_budget, config.guild_budget_window);
+    let (cache, budgets) = match &config.cache_dir {
+        Some(cache_dir) => {
+            if let Err(why) = std::fs::create_dir_all(cache_dir) {
+                error!("Error creating cache directory {cache_dir:?}: {why:?}");
+            }
+            (
+                cache.persisted(cache_dir.join("cache.json")),
+                budgets.persisted(cache_dir.join("budget.json")),
+            )
+        }
+        None => (cache, budgets),
+    };
     let mut client = Client::builder(&token, intents)
-        .event_handler(Handler)
+        .event_handler(Handler {
+            settings: settings::Settings::load(config.settings_path.clone()),
+            qa: qa::QaThreads::new(config.max_follow_ups),
+            in_progress: progress::InProgress::new(),
+            running: running.clone(),
+            requested: progress::Requested::new(REPEAT_WINDOW),
+            offered: progress::Offered::new(MAX_OFFERED),
+            cancellable: progress::Cancellable::new(),
+            outputs: outputs::Outputs::new(),
+            rate_limiter: ratelimit::RateLimiter::new(
+                config.rate_limit_requests,
+                config.rate_limit_window,
+            ),
+            budgets,
+            cache,
+            config,
+            bot_id: OnceLock::new(),
+        })
         .await
         .expect("Err creating client");
 
+    // Stop taking new events on Ctrl-C or SIGTERM. `start` then returns, and
+    // jobs already running get a little while to finish below.
+    let shard_manager = client.shard_manager.clone();
+    let (stop, stopped) = watch::channel(false);
+    tokio::spawn(async move {
+        shutdown_signal().await;
+        info!("Shutting down, waiting up to {SHUTDOWN_GRACE:?} for running jobs");
+        health::CONNECTED.store(false, Ordering::Relaxed);
+        let _ = stop.send(true);
+        shard_manager.shutdown_all().await;
+    });
+    if let Some(port) = health_port {
+        tokio::spawn(health::serve(port, stopped));
+    }
+
     // Finally, start a single shard, and start listening to events.
     //
     // Shards will automatically attempt to reconnect, and will perform
     // exponential backoff until it reconnects.
     if let Err(why) = client.start().await {
-        println!("Client error: {:?}", why);
+        error!("Client error: {:?}", why);
     }
+    wait_for_jobs(&running, SHUTDOWN_GRACE).await;
+    info!("Shut down");
 }
 
 #[test]
@@ -234,3 +1896,123 @@ fn test_twitter_links_for_message() {
         vec!["https://fxtwitter.com/nytimes/status/1715113106312155502"]
     );
 }
+
+#[test]
+fn test_videos_for_message() {
+    let ids = |msg: &str| {
+        videos_for_message(msg)
+            .into_iter()
+            .map(|video| video.id)
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(
+        ids("https://www.youtube.com/watch?v=dQw4w9WgXcQ and https://youtu.be/arj7oStGLkU\nhttps://youtu.be/dQw4w9WgXcQ"),
+        vec!["dQw4w9WgXcQ", "arj7oStGLkU"]
+    );
+    // The same video as a short, with a timestamp, and on mobile.
+    assert_eq!(
+        ids("https://youtube.com/shorts/arj7oStGLkU https://www.youtube.com/watch?v=arj7oStGLkU&t=30s\nhttps://m.youtube.com/watch?v=arj7oStGLkU https://youtu.be/dQw4w9WgXcQ?si=abc https://www.youtube.com/embed/dQw4w9WgXcQ"),
+        vec!["arj7oStGLkU", "dQw4w9WgXcQ"]
+    );
+    assert!(videos_for_message("https://youtu.be/dQw4w9WgXcQ")
+        .iter()
+        .all(|video| video.source.name() == "YouTube"));
+}
+
+#[test]
+fn test_ignores_author() {
+    use config::IgnoreBots;
+    let us = UserId::new(1);
+    let other = UserId::new(2);
+
+    assert!(ignores_author(IgnoreBots::All, Some(us), us, true));
+    assert!(ignores_author(IgnoreBots::OnlySelf, Some(us), us, true));
+    assert!(ignores_author(IgnoreBots::All, Some(us), other, true));
+    assert!(!ignores_author(IgnoreBots::OnlySelf, Some(us), other, true));
+    assert!(!ignores_author(IgnoreBots::All, Some(us), other, false));
+    // Before `ready`, other bots are still ignored by default.
+    assert!(ignores_author(IgnoreBots::All, None, other, true));
+}
+
+#[test]
+fn test_reactor() {
+    let reaction = |user_id: Option<u64>| {
+        serde_json::from_value::<Reaction>(serde_json::json!({
+            "user_id": user_id.map(|id| id.to_string()),
+            "channel_id": "2",
+            "message_id": "3",
+            "emoji": { "id": null, "name": "📜" },
+            "burst": false,
+            "burst_colors": [],
+            "type": 0,
+        }))
+        .unwrap()
+    };
+
+    // A DM has no member to tell whether it's a bot; they're still a person.
+    let dm = reaction(Some(1));
+    assert_eq!(dm.guild_id, None);
+    assert_eq!(reactor(&dm), Some((UserId::new(1), false)));
+    assert!(!ignores_author(
+        config::IgnoreBots::All,
+        Some(UserId::new(4)),
+        UserId::new(1),
+        false
+    ));
+    // Our own reactions in a DM are still recognized as ours.
+    let (us, is_bot) = reactor(&reaction(Some(4))).unwrap();
+    assert!(ignores_author(
+        config::IgnoreBots::All,
+        Some(UserId::new(4)),
+        us,
+        is_bot
+    ));
+    assert_eq!(reactor(&reaction(None)), None);
+}
+
+#[test]
+fn test_error_report() {
+    let failure = |id: &str| (id.to_string(), youtube::Error::VideoNotFound);
+    let why = youtube::Error::VideoNotFound.to_string();
+
+    assert_eq!(error_report("Summary error", 3, &[], None), None);
+    assert_eq!(
+        error_report("Summary error", 1, &[failure("a")], None).unwrap(),
+        format!("Summary error: {why}")
+    );
+    assert_eq!(
+        error_report("Summary error", 5, &[failure("a"), failure("b")], None).unwrap(),
+        format!("2 of 5 videos failed:\n- `a`: {why}\n- `b`: {why}")
+    );
+    assert_eq!(
+        error_report("Summary error", 5, &[failure("b")], Some(2)).unwrap(),
+        format!("Summary error: stopped after video 2 of 5 failed.\n- `b`: {why}")
+    );
+}
+
+#[tokio::test]
+async fn test_with_timeout() {
+    let timeout = Duration::from_millis(100);
+    assert!(matches!(
+        with_timeout(timeout, async { Ok(1) }).await,
+        Ok(1)
+    ));
+    assert!(matches!(
+        with_timeout(timeout, async {
+            tokio::time::sleep(Duration::from_secs(5)).await;
+            Ok(1)
+        })
+        .await,
+        Err(youtube::Error::TimedOut(after)) if after == timeout
+    ));
+    let panicking = async {
+        if timeout > Duration::ZERO {
+            panic!("index out of bounds");
+        }
+        Ok(1)
+    };
+    assert!(matches!(
+        with_timeout(timeout, panicking).await,
+        Err(youtube::Error::Panicked(message)) if message == "index out of bounds"
+    ));
+}
@@ -0,0 +1,90 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// How much work has been done since startup.
+pub struct Metrics {
+    pub videos_summarized: AtomicU64,
+    pub videos_transcribed: AtomicU64,
+    pub cache_hits: AtomicU64,
+    pub cache_misses: AtomicU64,
+    pub openai_errors: AtomicU64,
+    /// OpenAI tokens used, estimated for streamed replies.
+    pub tokens_used: AtomicU64,
+}
+
+pub static METRICS: Metrics = Metrics {
+    videos_summarized: AtomicU64::new(0),
+    videos_transcribed: AtomicU64::new(0),
+    cache_hits: AtomicU64::new(0),
+    cache_misses: AtomicU64::new(0),
+    openai_errors: AtomicU64::new(0),
+    tokens_used: AtomicU64::new(0),
+};
+
+pub fn increment(counter: &AtomicU64) {
+    add(counter, 1);
+}
+
+pub fn add(counter: &AtomicU64, n: u64) {
+    counter.fetch_add(n, Ordering::Relaxed);
+}
+
+impl Metrics {
+    /// In Prometheus' text format.
+    pub fn render(&self) -> String {
+        [
+            ("videos_summarized_total", &self.videos_summarized),
+            ("videos_transcribed_total", &self.videos_transcribed),
+            ("cache_hits_total", &self.cache_hits),
+            ("cache_misses_total", &self.cache_misses),
+            ("openai_errors_total", &self.openai_errors),
+            ("openai_tokens_total", &self.tokens_used),
+        ]
+        .into_iter()
+        .map(|(name, counter)| {
+            format!(
+                "# TYPE leonidas_{name} counter\nleonidas_{name} {}\n",
+                counter.load(Ordering::Relaxed)
+            )
+        })
+        .collect()
+    }
+
+    /// The fraction of finished summaries and transcripts that came from the
+    /// cache, once there's been at least one.
+    pub fn cache_hit_rate(&self) -> Option<f64> {
+        let hits = self.cache_hits.load(Ordering::Relaxed);
+        let misses = self.cache_misses.load(Ordering::Relaxed);
+        (hits + misses > 0).then(|| hits as f64 / (hits + misses) as f64)
+    }
+}
+
+#[test]
+fn test_render() {
+    let metrics = Metrics {
+        videos_summarized: AtomicU64::new(0),
+        videos_transcribed: AtomicU64::new(0),
+        cache_hits: AtomicU64::new(0),
+        cache_misses: AtomicU64::new(0),
+        openai_errors: AtomicU64::new(0),
+        tokens_used: AtomicU64::new(0),
+    };
+    assert_eq!(metrics.cache_hit_rate(), None);
+    increment(&metrics.videos_summarized);
+    increment(&metrics.videos_summarized);
+    increment(&metrics.openai_errors);
+
+    let rendered = metrics.render();
+    assert!(rendered.contains(
+        "# TYPE leonidas_videos_summarized_total counter\nleonidas_videos_summarized_total 2\n"
+    ));
+    assert!(rendered.contains("leonidas_videos_transcribed_total 0\n"));
+    assert!(rendered.contains("leonidas_openai_errors_total 1\n"));
+
+    increment(&metrics.cache_hits);
+    add(&metrics.cache_misses, 3);
+    add(&metrics.tokens_used, 1200);
+    assert_eq!(metrics.cache_hit_rate(), Some(0.25));
+    assert!(metrics
+        .render()
+        .contains("leonidas_openai_tokens_total 1200\n"));
+}
@@ -1,4 +1,8 @@
+use std::sync::OnceLock;
+
 use serde::{Deserialize, Serialize};
+use tiktoken_rs::tokenizer::{get_tokenizer, Tokenizer};
+use tiktoken_rs::CoreBPE;
 
 #[derive(Deserialize, Serialize, Clone)]
 pub struct ChatMessage {
@@ -8,13 +12,82 @@ pub struct ChatMessage {
 
 #[derive(Deserialize, Serialize, Clone)]
 pub struct ChatApiRequest {
-    pub model: &'static str,
+    pub model: String,
     pub messages: Vec<ChatMessage>,
+    /// Left to the API's default when `None`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f32>,
+    /// Most tokens the reply can be. Unlimited when `None`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_tokens: Option<u32>,
+}
+
+/// The same request with `stream: true`, so the reply arrives as
+/// server-sent events of `ChatStreamChunk`s.
+#[derive(Serialize)]
+pub struct ChatStreamRequest<'a> {
+    #[serde(flatten)]
+    pub request: &'a ChatApiRequest,
+    pub stream: bool,
 }
 
 #[derive(Deserialize)]
 pub struct ChatApiResponse {
     pub choices: Vec<ChatChoice>,
+    #[serde(default)]
+    pub usage: Usage,
+}
+
+/// Tokens billed for one or more requests.
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+pub struct Usage {
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    pub total_tokens: u64,
+}
+
+impl Usage {
+    /// Streamed replies don't report their usage, so it's counted locally.
+    pub fn estimate(model: &str, prompt: &[ChatMessage], reply: &str) -> Usage {
+        let prompt_tokens = count_tokens(model, prompt) as u64;
+        let completion_tokens = count_text_tokens(model, reply) as u64;
+        Usage {
+            prompt_tokens,
+            completion_tokens,
+            total_tokens: prompt_tokens + completion_tokens,
+        }
+    }
+
+    /// e.g. "~12,400 tokens", followed by "(~$0.37)" when the price of
+    /// 1,000 tokens is known.
+    pub fn describe(&self, cost_per_1k_tokens: Option<f64>) -> String {
+        let tokens = format!("~{} tokens", crate::utils::thousands(self.total_tokens));
+        match cost_per_1k_tokens {
+            Some(cost) => format!(
+                "{tokens} (~${:.2})",
+                self.total_tokens as f64 / 1000.0 * cost
+            ),
+            None => tokens,
+        }
+    }
+}
+
+impl std::ops::Add for Usage {
+    type Output = Usage;
+
+    fn add(self, other: Usage) -> Usage {
+        Usage {
+            prompt_tokens: self.prompt_tokens + other.prompt_tokens,
+            completion_tokens: self.completion_tokens + other.completion_tokens,
+            total_tokens: self.total_tokens + other.total_tokens,
+        }
+    }
+}
+
+impl std::iter::Sum for Usage {
+    fn sum<I: Iterator<Item = Usage>>(iter: I) -> Usage {
+        iter.fold(Usage::default(), |total, usage| total + usage)
+    }
 }
 
 #[derive(Deserialize)]
@@ -27,15 +100,201 @@ pub struct ChatMessageContent {
     pub content: String,
 }
 
-pub fn count_tokens(chat: &[ChatMessage]) -> usize {
-    use tiktoken_rs::{get_chat_completion_max_tokens, ChatCompletionRequestMessage};
-    let messages = chat
-        .iter()
-        .map(|message| ChatCompletionRequestMessage {
-            content: Some(message.content.clone()),
-            role: message.role.to_string(),
-            ..Default::default()
+#[derive(Deserialize)]
+pub struct ChatStreamChunk {
+    pub choices: Vec<ChatStreamChoice>,
+}
+
+#[derive(Deserialize)]
+pub struct ChatStreamChoice {
+    pub delta: ChatDelta,
+}
+
+#[derive(Deserialize)]
+pub struct ChatDelta {
+    /// Missing on the first chunk, which only carries the role.
+    #[serde(default)]
+    pub content: Option<String>,
+}
+
+/// Remove every complete line from `buffer` and return the payloads of its
+/// `data:` lines. A partial line at the end stays in the buffer until the
+/// rest of it arrives.
+pub fn take_sse_data(buffer: &mut Vec<u8>) -> Vec<String> {
+    let Some(end) = buffer.iter().rposition(|byte| *byte == b'\n') else {
+        return Vec::new();
+    };
+    let lines: Vec<u8> = buffer.drain(..=end).collect();
+    String::from_utf8_lossy(&lines)
+        .lines()
+        .filter_map(|line| line.strip_prefix("data:"))
+        .map(|data| data.trim().to_string())
+        .collect()
+}
+
+/// The tokenizer `model` uses. Building the BPE tables is slow, so each is
+/// built once and shared. Models tiktoken doesn't know yet are assumed to
+/// use the same one as GPT-4.
+fn encoder(model: &str) -> &'static CoreBPE {
+    static CL100K: OnceLock<CoreBPE> = OnceLock::new();
+    static P50K: OnceLock<CoreBPE> = OnceLock::new();
+    static P50K_EDIT: OnceLock<CoreBPE> = OnceLock::new();
+    static R50K: OnceLock<CoreBPE> = OnceLock::new();
+    match get_tokenizer(model).unwrap_or(Tokenizer::Cl100kBase) {
+        Tokenizer::Cl100kBase => CL100K.get_or_init(|| tiktoken_rs::cl100k_base().unwrap()),
+        Tokenizer::P50kBase => P50K.get_or_init(|| tiktoken_rs::p50k_base().unwrap()),
+        Tokenizer::P50kEdit => P50K_EDIT.get_or_init(|| tiktoken_rs::p50k_edit().unwrap()),
+        Tokenizer::R50kBase | Tokenizer::Gpt2 => {
+            R50K.get_or_init(|| tiktoken_rs::r50k_base().unwrap())
+        }
+    }
+}
+
+/// Tokens in the prompt when sent to `model`, the same as
+/// `tiktoken_rs::num_tokens_from_messages` but without rebuilding the
+/// encoder on every call.
+pub fn count_tokens(model: &str, chat: &[ChatMessage]) -> usize {
+    // Every message is wrapped in a few tokens, and every reply primed with 3.
+    let per_message = if model.starts_with("gpt-3.5") { 4 } else { 3 };
+    let encoder = encoder(model);
+    chat.iter()
+        .map(|message| {
+            per_message
+                + encoder.encode_with_special_tokens(message.role).len()
+                + encoder.encode_with_special_tokens(&message.content).len()
         })
-        .collect::<Vec<_>>();
-    get_chat_completion_max_tokens("gpt-4", &messages).unwrap()
+        .sum::<usize>()
+        + 3
+}
+
+pub fn count_tokens_many(model: &str, chats: &[&[ChatMessage]]) -> Vec<usize> {
+    chats.iter().map(|chat| count_tokens(model, chat)).collect()
+}
+
+pub fn count_text_tokens(model: &str, text: &str) -> usize {
+    encoder(model).encode_with_special_tokens(text).len()
+}
+
+#[test]
+fn test_count_tokens() {
+    use tiktoken_rs::{num_tokens_from_messages, ChatCompletionRequestMessage};
+    let chat = |content: &str| {
+        vec![
+            ChatMessage {
+                role: "system",
+                content: "You are a summarization assistant.".to_string(),
+            },
+            ChatMessage {
+                role: "user",
+                content: content.to_string(),
+            },
+        ]
+    };
+    let chats = [chat("Transcript: hello world"), chat(&"word ".repeat(500))];
+
+    for model in ["gpt-4-1106-preview", "gpt-4", "gpt-3.5-turbo"] {
+        let counts = count_tokens_many(model, &chats.iter().map(Vec::as_slice).collect::<Vec<_>>());
+        for (chat, count) in chats.iter().zip(counts) {
+            let messages = chat
+                .iter()
+                .map(|message| ChatCompletionRequestMessage {
+                    content: Some(message.content.clone()),
+                    role: message.role.to_string(),
+                    ..Default::default()
+                })
+                .collect::<Vec<_>>();
+            assert_eq!(count, num_tokens_from_messages(model, &messages).unwrap());
+        }
+    }
+
+    // "user" and "hello world" are 1 and 2 tokens, plus the wrapping.
+    let hello = [ChatMessage {
+        role: "user",
+        content: "hello world".to_string(),
+    }];
+    assert_eq!(count_tokens("gpt-4-1106-preview", &hello), 9);
+    assert_eq!(count_tokens("gpt-3.5-turbo", &hello), 10);
+}
+
+#[test]
+fn test_take_sse_data() {
+    let mut buffer = b"data: {\"a\": 1}\n\n: keep-alive\ndata: [DO".to_vec();
+    assert_eq!(take_sse_data(&mut buffer), vec![r#"{"a": 1}"#]);
+    assert_eq!(buffer, b"data: [DO");
+
+    buffer.extend_from_slice(b"NE]\n\n");
+    assert_eq!(take_sse_data(&mut buffer), vec!["[DONE]"]);
+    assert!(buffer.is_empty());
+
+    // A multi-byte character split across two network chunks.
+    let mut buffer = "data: ü".as_bytes()[..7].to_vec();
+    assert!(take_sse_data(&mut buffer).is_empty());
+    buffer.extend_from_slice(&"ü\n".as_bytes()[1..]);
+    assert_eq!(take_sse_data(&mut buffer), vec!["ü"]);
+}
+
+#[test]
+fn test_chat_api_request_defaults() {
+    let request = |temperature, max_tokens| ChatApiRequest {
+        model: "gpt-4".to_string(),
+        messages: vec![ChatMessage {
+            role: "user",
+            content: "Hi".to_string(),
+        }],
+        temperature,
+        max_tokens,
+    };
+
+    assert_eq!(
+        serde_json::to_string(&request(None, None)).unwrap(),
+        r#"{"model":"gpt-4","messages":[{"role":"user","content":"Hi"}]}"#
+    );
+    assert_eq!(
+        serde_json::to_string(&request(Some(0.0), Some(100))).unwrap(),
+        r#"{"model":"gpt-4","messages":[{"role":"user","content":"Hi"}],"temperature":0.0,"max_tokens":100}"#
+    );
+}
+
+#[test]
+fn test_usage() {
+    let response = serde_json::from_str::<ChatApiResponse>(
+        r#"{"choices":[{"message":{"content":"Hi"}}],"usage":{"prompt_tokens":12000,"completion_tokens":400,"total_tokens":12400}}"#,
+    )
+    .unwrap();
+    let usage = [response.usage, Usage::default()]
+        .into_iter()
+        .sum::<Usage>();
+    assert_eq!(usage.describe(None), "~12,400 tokens");
+    assert_eq!(usage.describe(Some(0.03)), "~12,400 tokens (~$0.37)");
+
+    let response =
+        serde_json::from_str::<ChatApiResponse>(r#"{"choices":[{"message":{"content":"Hi"}}]}"#)
+            .unwrap();
+    assert_eq!(response.usage, Usage::default());
+}
+
+#[test]
+fn test_encoder_is_shared() {
+    use std::time::Instant;
+
+    // Models on the same tokenizer share one encoder, built on first use.
+    assert!(std::ptr::eq(encoder("gpt-4"), encoder("gpt-3.5-turbo")));
+    assert!(std::ptr::eq(encoder("gpt-4"), encoder("some-future-model")));
+    assert!(!std::ptr::eq(encoder("gpt-4"), encoder("text-davinci-003")));
+
+    // Counting a chunk's worth of prompts takes less than building the
+    // encoder once, which is what every count used to cost.
+    let chunk = "word ".repeat(1500);
+    let started = Instant::now();
+    for _ in 0..20 {
+        count_text_tokens("gpt-4", &chunk);
+    }
+    let counting = started.elapsed();
+    let started = Instant::now();
+    tiktoken_rs::cl100k_base().unwrap();
+    let building = started.elapsed();
+    assert!(
+        counting < building,
+        "counting took {counting:?}, building {building:?}"
+    );
 }
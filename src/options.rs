@@ -0,0 +1,362 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SummaryLength {
+    Short,
+    Medium,
+    Long,
+    /// About this many words, however long the video is.
+    Words(u32),
+}
+
+impl SummaryLength {
+    /// `detailed` is the same as `long`, and a number asks for that many
+    /// words.
+    pub fn parse(s: &str) -> Option<SummaryLength> {
+        match s.trim().to_lowercase().as_str() {
+            "short" => Some(SummaryLength::Short),
+            "medium" => Some(SummaryLength::Medium),
+            "long" | "detailed" => Some(SummaryLength::Long),
+            words => words
+                .parse()
+                .ok()
+                .filter(|words| *words > 0)
+                .map(SummaryLength::Words),
+        }
+    }
+}
+
+/// How a summary is laid out.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SummaryStyle {
+    Prose,
+    Bullets,
+    /// A summary followed by a bullet list of key takeaways.
+    Notes,
+}
+
+impl SummaryStyle {
+    pub fn parse(s: &str) -> Option<SummaryStyle> {
+        match s.trim().to_lowercase().as_str() {
+            "prose" => Some(SummaryStyle::Prose),
+            "bullets" => Some(SummaryStyle::Bullets),
+            "notes" => Some(SummaryStyle::Notes),
+            _ => None,
+        }
+    }
+}
+
+/// A slice of a video by fraction of its runtime, stored in basis points
+/// (hundredths of a percent) so it can be hashed and compared exactly.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Portion {
+    pub start: u32,
+    pub end: u32,
+}
+
+impl Portion {
+    pub const WHOLE: Portion = Portion {
+        start: 0,
+        end: 10_000,
+    };
+
+    /// Accepts `<position>-<part>` like `first-half`, `middle-third` or
+    /// `last-quarter`, or a percentage range like `25%-50%`.
+    pub fn parse(s: &str) -> Result<Portion, String> {
+        let s = s.trim().to_lowercase();
+        let invalid = || {
+            format!(
+                "`{s}` isn't a portion I understand. Try something like `first-half`, `last-third` or `25%-50%`."
+            )
+        };
+        let (position, part) = s.split_once('-').ok_or_else(invalid)?;
+
+        if let (Some(start), Some(end)) = (position.strip_suffix('%'), part.strip_suffix('%')) {
+            let start: f64 = start.trim().parse().map_err(|_| invalid())?;
+            let end: f64 = end.trim().parse().map_err(|_| invalid())?;
+            if !(0.0..100.0).contains(&start) || !(0.0..=100.0).contains(&end) || start >= end {
+                return Err(format!(
+                    "`{s}` isn't a valid range; percentages must be between 0% and 100%, with the start before the end."
+                ));
+            }
+            return Ok(Portion {
+                start: (start * 100.0).round() as u32,
+                end: (end * 100.0).round() as u32,
+            });
+        }
+
+        let parts = match part {
+            "half" => 2,
+            "third" => 3,
+            "quarter" => 4,
+            "fifth" => 5,
+            _ => return Err(invalid()),
+        };
+        let index = match position {
+            "first" => 0,
+            "second" => 1,
+            "third" => 2,
+            "fourth" => 3,
+            "fifth" => 4,
+            "middle" if parts % 2 == 1 => parts / 2,
+            "last" => parts - 1,
+            _ => return Err(invalid()),
+        };
+        if index >= parts {
+            return Err(invalid());
+        }
+        Ok(Portion {
+            start: 10_000 * index / parts,
+            end: 10_000 * (index + 1) / parts,
+        })
+    }
+
+    pub fn start_fraction(&self) -> f64 {
+        self.start as f64 / 10_000.0
+    }
+
+    pub fn end_fraction(&self) -> f64 {
+        self.end as f64 / 10_000.0
+    }
+}
+
+/// Knobs that change what a summary looks like. Anything added here must
+/// also be reflected in `normalized`, since the cache keys on it.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct SummaryOptions {
+    pub length: Option<SummaryLength>,
+    pub language: Option<String>,
+    /// Only summarize this part of the video.
+    pub portion: Option<Portion>,
+    pub style: Option<SummaryStyle>,
+    /// The server's own system prompt, from `/setprompt`. Skipped when unset
+    /// so older cache keys and settings still match.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub system_prompt: Option<String>,
+}
+
+impl SummaryOptions {
+    /// Collapse options that mean the same thing into one representation,
+    /// e.g. `language: " EN "` and `language: "en"`, or an explicit medium
+    /// length and no length at all.
+    pub fn normalized(&self) -> SummaryOptions {
+        SummaryOptions {
+            length: match self.length {
+                Some(SummaryLength::Medium) => None,
+                length => length,
+            },
+            language: self
+                .language
+                .as_ref()
+                .map(|language| language.trim().to_lowercase())
+                .filter(|language| !language.is_empty()),
+            portion: self.portion.filter(|portion| *portion != Portion::WHOLE),
+            style: match self.style {
+                Some(SummaryStyle::Prose) => None,
+                style => style,
+            },
+            system_prompt: self.system_prompt.clone(),
+        }
+    }
+
+    /// Pull `key:value` directives like `length:long style:bullets` out of a
+    /// message. `to:en` is short for `language:en`. Unknown keys and
+    /// unparseable values are ignored.
+    pub fn parse(text: &str) -> SummaryOptions {
+        let mut options = SummaryOptions::default();
+        for word in text.split_whitespace() {
+            match word.split_once(':') {
+                Some(("length" | "summary", value)) => options.length = SummaryLength::parse(value),
+                Some(("language" | "to", value)) if !value.is_empty() => {
+                    options.language = Some(value.to_string())
+                }
+                Some(("portion", value)) => options.portion = Portion::parse(value).ok(),
+                Some(("style", value)) => options.style = SummaryStyle::parse(value),
+                _ => {}
+            }
+        }
+        options
+    }
+
+    /// Fill in whatever isn't set here from `fallback`.
+    pub fn or(self, fallback: &SummaryOptions) -> SummaryOptions {
+        SummaryOptions {
+            length: self.length.or(fallback.length),
+            language: self.language.or_else(|| fallback.language.clone()),
+            portion: self.portion.or(fallback.portion),
+            style: self.style.or(fallback.style),
+            system_prompt: self
+                .system_prompt
+                .or_else(|| fallback.system_prompt.clone()),
+        }
+    }
+}
+
+/// The `lang:es` directive in a message, asking for a transcript in that
+/// language.
+pub fn transcript_language(text: &str) -> Option<String> {
+    text.split_whitespace()
+        .find_map(|word| match word.split_once(':') {
+            Some(("lang", language)) if !language.is_empty() => Some(language.to_lowercase()),
+            _ => None,
+        })
+}
+
+/// Explicit options win over the channel's defaults, which win over the
+/// global defaults.
+pub fn resolve(
+    explicit: SummaryOptions,
+    channel_default: Option<&SummaryOptions>,
+    global_default: &SummaryOptions,
+) -> SummaryOptions {
+    let explicit = match channel_default {
+        Some(channel_default) => explicit.or(channel_default),
+        None => explicit,
+    };
+    explicit.or(global_default)
+}
+
+#[test]
+fn test_parse_options() {
+    assert_eq!(
+        SummaryOptions::parse("https://youtu.be/dQw4w9WgXcQ length:long language:es style:bullets"),
+        SummaryOptions {
+            length: Some(SummaryLength::Long),
+            language: Some("es".to_string()),
+            portion: None,
+            style: Some(SummaryStyle::Bullets),
+            system_prompt: None,
+        }
+    );
+    assert_eq!(
+        SummaryOptions::parse("see https://example.com length:huge"),
+        SummaryOptions::default()
+    );
+    assert_eq!(
+        SummaryOptions::parse("https://youtu.be/dQw4w9WgXcQ to:en").language,
+        Some("en".to_string())
+    );
+}
+
+#[test]
+fn test_parse_length() {
+    assert_eq!(
+        SummaryOptions::parse("summary:short").length,
+        Some(SummaryLength::Short)
+    );
+    assert_eq!(
+        SummaryOptions::parse("summary:Detailed").length,
+        Some(SummaryLength::Long)
+    );
+    assert_eq!(
+        SummaryOptions::parse("length:300").length,
+        Some(SummaryLength::Words(300))
+    );
+    assert_eq!(SummaryOptions::parse("summary:0").length, None);
+    assert_eq!(SummaryOptions::parse("summary:-5").length, None);
+}
+
+#[test]
+fn test_transcript_language() {
+    assert_eq!(
+        transcript_language("https://youtu.be/dQw4w9WgXcQ lang:ES"),
+        Some("es".to_string())
+    );
+    assert_eq!(transcript_language("language:es"), None);
+    assert_eq!(transcript_language("lang: es"), None);
+}
+
+#[test]
+fn test_resolve_options() {
+    let channel = SummaryOptions {
+        length: Some(SummaryLength::Short),
+        language: Some("de".to_string()),
+        portion: None,
+        style: None,
+        system_prompt: None,
+    };
+    let global = SummaryOptions {
+        length: Some(SummaryLength::Long),
+        language: Some("en".to_string()),
+        portion: None,
+        style: None,
+        system_prompt: None,
+    };
+    let explicit = SummaryOptions {
+        length: None,
+        language: Some("fr".to_string()),
+        portion: None,
+        style: None,
+        system_prompt: None,
+    };
+
+    assert_eq!(
+        resolve(explicit.clone(), Some(&channel), &global),
+        SummaryOptions {
+            length: Some(SummaryLength::Short),
+            language: Some("fr".to_string()),
+            portion: None,
+            style: None,
+            system_prompt: None,
+        }
+    );
+    assert_eq!(
+        resolve(explicit, None, &global),
+        SummaryOptions {
+            length: Some(SummaryLength::Long),
+            language: Some("fr".to_string()),
+            portion: None,
+            style: None,
+            system_prompt: None,
+        }
+    );
+}
+
+#[test]
+fn test_parse_portion() {
+    assert_eq!(
+        Portion::parse("first-half"),
+        Ok(Portion {
+            start: 0,
+            end: 5_000
+        })
+    );
+    assert_eq!(
+        Portion::parse("Last-Third"),
+        Ok(Portion {
+            start: 6_666,
+            end: 10_000
+        })
+    );
+    assert_eq!(
+        Portion::parse("middle-third"),
+        Ok(Portion {
+            start: 3_333,
+            end: 6_666
+        })
+    );
+    assert_eq!(
+        Portion::parse("second-quarter"),
+        Ok(Portion {
+            start: 2_500,
+            end: 5_000
+        })
+    );
+    assert_eq!(
+        Portion::parse("12.5%-50%"),
+        Ok(Portion {
+            start: 1_250,
+            end: 5_000
+        })
+    );
+    assert_eq!(Portion::parse("0%-100%"), Ok(Portion::WHOLE));
+
+    assert!(Portion::parse("middle-half").is_err());
+    assert!(Portion::parse("fifth-quarter").is_err());
+    assert!(Portion::parse("first-eighth").is_err());
+    assert!(Portion::parse("50%-25%").is_err());
+    assert!(Portion::parse("90%-120%").is_err());
+    assert!(Portion::parse("half").is_err());
+}
@@ -0,0 +1,118 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+
+use serenity::all::{MessageId, UserId};
+
+use crate::options::SummaryOptions;
+use crate::sources::Video;
+use crate::youtube::Action;
+
+/// The most outputs remembered. Older ones can't be regenerated.
+const MAX_OUTPUTS: usize = 1000;
+
+/// What one of the bot's output messages was made from, so it can be made
+/// again.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Output {
+    pub video: Video,
+    pub action: Action,
+    /// Only `language` is used for transcripts.
+    pub options: SummaryOptions,
+    pub requester: UserId,
+    /// The message holds all of the output, not one part of it or a note
+    /// about an attached file, so its embed can be refined on its own.
+    pub whole: bool,
+}
+
+impl Output {
+    pub fn summary(video: Video, options: &SummaryOptions, requester: UserId) -> Output {
+        Output {
+            video,
+            action: Action::Summarize,
+            options: options.clone(),
+            requester,
+            whole: false,
+        }
+    }
+
+    pub fn transcript(video: Video, language: Option<String>, requester: UserId) -> Output {
+        Output {
+            video,
+            action: Action::Transcribe,
+            options: SummaryOptions {
+                language,
+                ..SummaryOptions::default()
+            },
+            requester,
+            whole: false,
+        }
+    }
+}
+
+#[derive(Default)]
+struct Remembered {
+    outputs: HashMap<MessageId, Output>,
+    /// Oldest first, to know which to forget.
+    order: VecDeque<MessageId>,
+}
+
+/// The messages the bot posted summaries and transcripts in.
+pub struct Outputs {
+    remembered: Mutex<Remembered>,
+}
+
+impl Outputs {
+    pub fn new() -> Outputs {
+        Outputs {
+            remembered: Mutex::default(),
+        }
+    }
+
+    pub fn remember(&self, message_id: MessageId, output: Output) {
+        let mut remembered = self.remembered.lock().unwrap();
+        if remembered.outputs.insert(message_id, output).is_none() {
+            remembered.order.push_back(message_id);
+        }
+        while remembered.order.len() > MAX_OUTPUTS {
+            if let Some(oldest) = remembered.order.pop_front() {
+                remembered.outputs.remove(&oldest);
+            }
+        }
+    }
+
+    pub fn get(&self, message_id: MessageId) -> Option<Output> {
+        self.remembered
+            .lock()
+            .unwrap()
+            .outputs
+            .get(&message_id)
+            .cloned()
+    }
+}
+
+#[test]
+fn test_outputs() {
+    let outputs = Outputs::new();
+    let output = |index: u64| {
+        Output::transcript(
+            Video::new(&crate::youtube::YouTube, format!("video{index}")),
+            Some("es".to_string()),
+            UserId::new(1),
+        )
+    };
+    assert_eq!(outputs.get(MessageId::new(1)), None);
+
+    outputs.remember(MessageId::new(1), output(1));
+    assert_eq!(outputs.get(MessageId::new(1)), Some(output(1)));
+    assert_eq!(
+        outputs.get(MessageId::new(1)).unwrap().options.language,
+        Some("es".to_string())
+    );
+
+    // The oldest are forgotten once there are too many.
+    for index in 2..=MAX_OUTPUTS as u64 + 1 {
+        outputs.remember(MessageId::new(index), output(index));
+    }
+    assert_eq!(outputs.get(MessageId::new(1)), None);
+    assert_eq!(outputs.get(MessageId::new(2)), Some(output(2)));
+}
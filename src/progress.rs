@@ -0,0 +1,305 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use futures::future::AbortHandle;
+use serenity::all::{MessageId, UserId};
+
+use crate::triggers::Trigger;
+
+struct Jobs {
+    running: usize,
+    failed: bool,
+}
+
+/// Messages with jobs running on them, asked for by reaction or message
+/// command. Several people can ask about the same message, so the indicator
+/// is only shown for the first job and only settled once the last one is
+/// done.
+pub struct InProgress {
+    messages: Mutex<HashMap<MessageId, Jobs>>,
+}
+
+impl InProgress {
+    pub fn new() -> InProgress {
+        InProgress {
+            messages: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Whether this is the only job on the message, i.e. the indicator
+    /// should be shown.
+    pub fn start(&self, message_id: MessageId) -> bool {
+        let mut messages = self.messages.lock().unwrap();
+        let jobs = messages.entry(message_id).or_insert(Jobs {
+            running: 0,
+            failed: false,
+        });
+        jobs.running += 1;
+        jobs.running == 1
+    }
+
+    /// Once the last job on the message is done, whether all of them
+    /// succeeded. `None` while others are still running.
+    pub fn finish(&self, message_id: MessageId, succeeded: bool) -> Option<bool> {
+        let mut messages = self.messages.lock().unwrap();
+        let jobs = messages.get_mut(&message_id)?;
+        jobs.running -= 1;
+        jobs.failed |= !succeeded;
+        if jobs.running > 0 {
+            return None;
+        }
+        let failed = jobs.failed;
+        messages.remove(&message_id);
+        Some(!failed)
+    }
+}
+
+/// How many jobs are running, however they were asked for, so shutdown can
+/// wait for them to finish posting.
+pub struct Running {
+    count: AtomicUsize,
+}
+
+impl Running {
+    pub fn new() -> Running {
+        Running {
+            count: AtomicUsize::new(0),
+        }
+    }
+
+    /// Counts a job as running until the guard is dropped.
+    pub fn start(&self) -> RunningGuard<'_> {
+        self.count.fetch_add(1, Ordering::SeqCst);
+        RunningGuard { running: self }
+    }
+
+    pub fn is_idle(&self) -> bool {
+        self.count.load(Ordering::SeqCst) == 0
+    }
+}
+
+pub struct RunningGuard<'a> {
+    running: &'a Running,
+}
+
+impl Drop for RunningGuard<'_> {
+    fn drop(&mut self) {
+        self.running.count.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Running jobs, so whoever asked for one can call it off by taking back
+/// their reaction.
+pub struct Cancellable {
+    jobs: Mutex<HashMap<(MessageId, Trigger, UserId), AbortHandle>>,
+}
+
+impl Cancellable {
+    pub fn new() -> Cancellable {
+        Cancellable {
+            jobs: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn start(
+        &self,
+        message_id: MessageId,
+        trigger: Trigger,
+        user_id: UserId,
+        handle: AbortHandle,
+    ) {
+        self.jobs
+            .lock()
+            .unwrap()
+            .insert((message_id, trigger, user_id), handle);
+    }
+
+    pub fn finish(&self, message_id: MessageId, trigger: Trigger, user_id: UserId) {
+        self.jobs
+            .lock()
+            .unwrap()
+            .remove(&(message_id, trigger, user_id));
+    }
+
+    /// Whether there was a job running to cancel.
+    pub fn cancel(&self, message_id: MessageId, trigger: Trigger, user_id: UserId) -> bool {
+        match self
+            .jobs
+            .lock()
+            .unwrap()
+            .remove(&(message_id, trigger, user_id))
+        {
+            Some(handle) => {
+                handle.abort();
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// When a job asked for on a message was finished, or `None` while it's
+/// still running.
+type Requests = HashMap<(MessageId, Trigger), Option<Instant>>;
+
+/// Jobs asked for on each message, so that when several people react with
+/// the same emoji it's only done once. Finished jobs are forgotten after
+/// `window`, and failed ones straight away so they can be retried.
+pub struct Requested {
+    requests: Mutex<Requests>,
+    window: Duration,
+}
+
+impl Requested {
+    pub fn new(window: Duration) -> Requested {
+        Requested {
+            requests: Mutex::new(HashMap::new()),
+            window,
+        }
+    }
+
+    /// Whether the job is new, i.e. should be run. Callers must `finish` it.
+    pub fn claim(&self, message_id: MessageId, trigger: Trigger) -> bool {
+        let mut requests = self.requests.lock().unwrap();
+        requests.retain(|_, finished| finished.map_or(true, |at| at.elapsed() < self.window));
+        if requests.contains_key(&(message_id, trigger)) {
+            return false;
+        }
+        requests.insert((message_id, trigger), None);
+        true
+    }
+
+    pub fn finish(&self, message_id: MessageId, trigger: Trigger, succeeded: bool) {
+        let mut requests = self.requests.lock().unwrap();
+        if succeeded {
+            requests.insert((message_id, trigger), Some(Instant::now()));
+        } else {
+            requests.remove(&(message_id, trigger));
+        }
+    }
+}
+
+/// Messages that were offered the reactions, so a message delivered again
+/// (e.g. after the gateway resumes) isn't reacted to twice. Only the latest
+/// `capacity` are remembered.
+pub struct Offered {
+    messages: Mutex<(HashSet<MessageId>, VecDeque<MessageId>)>,
+    capacity: usize,
+}
+
+impl Offered {
+    pub fn new(capacity: usize) -> Offered {
+        Offered {
+            messages: Mutex::new((HashSet::new(), VecDeque::new())),
+            capacity,
+        }
+    }
+
+    /// Whether the message hasn't been offered the reactions yet.
+    pub fn offer(&self, message_id: MessageId) -> bool {
+        let mut guard = self.messages.lock().unwrap();
+        let (seen, order) = &mut *guard;
+        if !seen.insert(message_id) {
+            return false;
+        }
+        order.push_back(message_id);
+        while order.len() > self.capacity {
+            if let Some(oldest) = order.pop_front() {
+                seen.remove(&oldest);
+            }
+        }
+        true
+    }
+}
+
+#[test]
+fn test_offered() {
+    let offered = Offered::new(2);
+
+    assert!(offered.offer(MessageId::new(1)));
+    assert!(!offered.offer(MessageId::new(1)));
+    assert!(offered.offer(MessageId::new(2)));
+    assert!(offered.offer(MessageId::new(3)));
+    // The first has been forgotten to make room.
+    assert!(offered.offer(MessageId::new(1)));
+    assert!(!offered.offer(MessageId::new(3)));
+}
+
+#[test]
+fn test_cancellable() {
+    let cancellable = Cancellable::new();
+    let message_id = MessageId::new(1);
+    let user = UserId::new(2);
+
+    let (job, handle) = futures::future::abortable(std::future::pending::<()>());
+    cancellable.start(message_id, Trigger::Summarize, user, handle);
+    // Only whoever asked for it, and with the same reaction.
+    assert!(!cancellable.cancel(message_id, Trigger::Summarize, UserId::new(3)));
+    assert!(!cancellable.cancel(message_id, Trigger::Transcribe, user));
+    assert!(cancellable.cancel(message_id, Trigger::Summarize, user));
+    assert!(futures::executor::block_on(job).is_err());
+
+    // Finished jobs can't be cancelled.
+    let (_, handle) = futures::future::abortable(std::future::pending::<()>());
+    cancellable.start(message_id, Trigger::Summarize, user, handle);
+    cancellable.finish(message_id, Trigger::Summarize, user);
+    assert!(!cancellable.cancel(message_id, Trigger::Summarize, user));
+}
+
+#[test]
+fn test_requested() {
+    let requested = Requested::new(Duration::from_secs(60));
+    let message_id = MessageId::new(1);
+
+    assert!(requested.claim(message_id, Trigger::Transcribe));
+    assert!(!requested.claim(message_id, Trigger::Transcribe));
+    assert!(requested.claim(message_id, Trigger::Summarize));
+    assert!(requested.claim(MessageId::new(2), Trigger::Transcribe));
+
+    // Done, so it's not redone; failed, so it can be tried again.
+    requested.finish(message_id, Trigger::Transcribe, true);
+    assert!(!requested.claim(message_id, Trigger::Transcribe));
+    requested.finish(message_id, Trigger::Summarize, false);
+    assert!(requested.claim(message_id, Trigger::Summarize));
+
+    let requested = Requested::new(Duration::ZERO);
+    assert!(requested.claim(message_id, Trigger::Transcribe));
+    requested.finish(message_id, Trigger::Transcribe, true);
+    std::thread::sleep(Duration::from_millis(1));
+    assert!(requested.claim(message_id, Trigger::Transcribe));
+}
+
+#[test]
+fn test_in_progress() {
+    let in_progress = InProgress::new();
+    let message_id = MessageId::new(1);
+
+    assert!(in_progress.start(message_id));
+    assert_eq!(in_progress.finish(message_id, true), Some(true));
+
+    // A second reaction while the first is running shares its indicator, and
+    // a failure in either shows once both are done.
+    assert!(in_progress.start(message_id));
+    assert!(!in_progress.start(message_id));
+    assert_eq!(in_progress.finish(message_id, false), None);
+    assert_eq!(in_progress.finish(message_id, true), Some(false));
+
+    assert!(in_progress.start(message_id));
+    assert_eq!(in_progress.finish(message_id, true), Some(true));
+    assert_eq!(in_progress.finish(message_id, true), None);
+}
+
+#[test]
+fn test_running() {
+    let running = Running::new();
+    assert!(running.is_idle());
+
+    let first = running.start();
+    let second = running.start();
+    drop(first);
+    assert!(!running.is_idle());
+    drop(second);
+    assert!(running.is_idle());
+}
@@ -1,24 +1,227 @@
-use crate::openai::{self, ChatMessage};
+use std::sync::OnceLock;
 
-pub(crate) fn summarize(
-    raw_transcript: String,
-    title: Option<String>,
-    channel_name: Option<String>,
-) -> Result<(Vec<ChatMessage>, u64), String> {
-    let words: usize = raw_transcript.split(' ').count();
-    if words <= 200 {
+use crate::openai::ChatMessage;
+use crate::options::{SummaryLength, SummaryOptions, SummaryStyle};
+use crate::youtube::Error;
+
+/// Bump whenever a prompt changes in a way that should invalidate cached output.
+pub const PROMPT_VERSION: u32 = 4;
+
+/// Transcripts this many words long or shorter aren't summarized.
+pub const DEFAULT_MIN_SUMMARY_WORDS: usize = 200;
+
+/// Summaries aim for at least this many words, or the whole transcript's if
+/// it's shorter, so very short videos don't get a one-line summary.
+const MIN_GOAL_WORDS: usize = 25;
+
+static MIN_SUMMARY_WORDS: OnceLock<usize> = OnceLock::new();
+
+/// Set once at startup from `Config::min_summary_words`.
+pub fn set_min_summary_words(min_words: usize) {
+    let _ = MIN_SUMMARY_WORDS.set(min_words);
+}
+
+pub fn min_summary_words() -> usize {
+    MIN_SUMMARY_WORDS
+        .get()
+        .copied()
+        .unwrap_or(DEFAULT_MIN_SUMMARY_WORDS)
+}
+
+/// Roughly how many words a summary of `words` words of transcript should be.
+pub fn goal_length(
+    words: usize,
+    min_words: usize,
+    options: &SummaryOptions,
+) -> Result<usize, Error> {
+    if words <= min_words {
+        return Err(Error::TranscriptTooShort { words, min_words });
+    }
+    let goal = match options.length {
+        Some(SummaryLength::Short) => (words / 10).min(500),
+        Some(SummaryLength::Medium) | None => (words / 5).min(2000),
+        Some(SummaryLength::Long) => (words / 3).min(4000),
+        // Never more than the transcript itself.
+        Some(SummaryLength::Words(goal)) => (goal as usize).min(words / 2).min(4000),
+    };
+    Ok(goal.max(MIN_GOAL_WORDS.min(words)))
+}
+
+/// Two-letter codes for the languages people are most likely to ask for.
+fn iso_639_1(lang: whatlang::Lang) -> Option<&'static str> {
+    use whatlang::Lang;
+    Some(match lang {
+        Lang::Eng => "en",
+        Lang::Spa => "es",
+        Lang::Fra => "fr",
+        Lang::Deu => "de",
+        Lang::Ita => "it",
+        Lang::Por => "pt",
+        Lang::Nld => "nl",
+        Lang::Rus => "ru",
+        Lang::Ukr => "uk",
+        Lang::Pol => "pl",
+        Lang::Ces => "cs",
+        Lang::Swe => "sv",
+        Lang::Dan => "da",
+        Lang::Fin => "fi",
+        Lang::Tur => "tr",
+        Lang::Ell => "el",
+        Lang::Ron => "ro",
+        Lang::Hun => "hu",
+        Lang::Ara => "ar",
+        Lang::Heb => "he",
+        Lang::Hin => "hi",
+        Lang::Jpn => "ja",
+        Lang::Kor => "ko",
+        Lang::Cmn => "zh",
+        Lang::Vie => "vi",
+        Lang::Tha => "th",
+        Lang::Ind => "id",
+        _ => return None,
+    })
+}
+
+/// Whether `code`, as asked for with `language:` or `to:`, is `lang`. Takes
+/// two- or three-letter codes, or the language's English name.
+fn is_language(code: &str, lang: whatlang::Lang) -> bool {
+    let code = code.trim().to_lowercase();
+    iso_639_1(lang) == Some(code.as_str())
+        || lang.code() == code
+        || lang.eng_name().to_lowercase() == code
+}
+
+/// The language asked for, or else the transcript's own, so a French video
+/// gets a French summary. When detection isn't confident the model is left
+/// to pick. Asking for the language the transcript is already in is the
+/// same as not asking.
+fn language_instruction(
+    options: &SummaryOptions,
+    source_language: Option<whatlang::Lang>,
+) -> String {
+    let language = options
+        .language
+        .as_ref()
+        .filter(|language| !source_language.is_some_and(|lang| is_language(language, lang)));
+    match (language, source_language) {
+        (Some(language), Some(lang)) => format!(
+            " Write the summary in the language with code `{language}`, translating from {} as you go.",
+            lang.eng_name()
+        ),
+        (Some(language), None) => {
+            format!(" Write the summary in the language with code `{language}`.")
+        }
+        (None, Some(lang)) if lang != whatlang::Lang::Eng => format!(
+            " Write the summary in {}, the same language as the transcript.",
+            lang.eng_name()
+        ),
+        (None, _) => String::new(),
+    }
+}
+
+/// The rest of the prompt reads as if the transcript is English, which can
+/// throw the model off when it isn't.
+fn source_language_note(source_language: Option<whatlang::Lang>) -> String {
+    source_language
+        .filter(|lang| *lang != whatlang::Lang::Eng)
+        .map(|lang| format!(" The transcript is in {}.", lang.eng_name()))
+        .unwrap_or_default()
+}
+
+/// Placeholders a server's own summary prompt (see `/setprompt`) can use:
+/// the summary's length in words, the instruction on which language to write
+/// it in, and the note on which language the transcript is in.
+pub const SUMMARY_PLACEHOLDERS: [&str; 3] = ["{goal_length}", "{language}", "{source_language}"];
+
+/// The most tokens a server's own prompt can take once filled in, so it
+/// leaves each request about as much room as the built-in ones do.
+pub const MAX_CUSTOM_PROMPT_TOKENS: usize = 1000;
+
+fn fill_template(
+    template: &str,
+    goal_length: usize,
+    language: &str,
+    source_language: &str,
+) -> String {
+    template
+        .replace("{goal_length}", &goal_length.to_string())
+        .replace("{language}", language.trim())
+        .replace("{source_language}", source_language.trim())
+}
+
+fn custom_system_prompt(
+    options: &SummaryOptions,
+    goal_length: usize,
+    language: &str,
+    source_language: &str,
+) -> Option<String> {
+    options
+        .system_prompt
+        .as_deref()
+        .map(|template| fill_template(template, goal_length, language, source_language))
+}
+
+/// Why a `/setprompt` template can't be used: it's empty, has a `{...}`
+/// that isn't one of `placeholders`, or is too long once filled in.
+pub fn check_template(template: &str, placeholders: &[&str], model: &str) -> Result<(), String> {
+    if template.trim().is_empty() {
+        return Err("The prompt is empty.".to_string());
+    }
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        let Some(end) = rest[start..].find('}') else {
+            break;
+        };
+        let placeholder = &rest[start..=start + end];
+        if !placeholders.contains(&placeholder) {
+            return Err(if placeholders.is_empty() {
+                format!("`{placeholder}` isn't a placeholder; this prompt doesn't take any.")
+            } else {
+                format!(
+                    "`{placeholder}` isn't a placeholder; use {}.",
+                    placeholders.join(", ")
+                )
+            });
+        }
+        rest = &rest[start + end + 1..];
+    }
+    // The longest these are filled in with.
+    let filled = fill_template(
+        template,
+        4000,
+        " Write the summary in Portuguese, translating from Japanese as you go.",
+        " The transcript is in Japanese.",
+    );
+    let tokens = crate::openai::count_text_tokens(model, &filled);
+    if tokens > MAX_CUSTOM_PROMPT_TOKENS {
         return Err(format!(
-            "Transcript too short to summarize. ({words} words in transcript)"
+            "That prompt is ~{tokens} tokens once filled in, which is over the limit of {MAX_CUSTOM_PROMPT_TOKENS}."
         ));
     }
-    let goal_length = (words / 5).min(2000);
+    Ok(())
+}
+
+pub fn summarize(
+    raw_transcript: String,
+    title: Option<String>,
+    channel_name: Option<String>,
+    source_language: Option<whatlang::Lang>,
+    options: &SummaryOptions,
+) -> Result<Vec<ChatMessage>, Error> {
+    let goal_length = goal_length(
+        raw_transcript.split(' ').count(),
+        min_summary_words(),
+        options,
+    )?;
+    let language = language_instruction(options, source_language);
+    let source_language = source_language_note(source_language);
 
     let messages = vec![
         ChatMessage {
             role: "system",
-            content: format!(
-                "You are a summarization assistant. When the user gives you a message, you respond with a summary of the information inside. Just summarize the information without saying \"the speaker says\" or similar. The message will be an autogenerated transcript of a youtube video, and may have transcription errors and improperly separated speakers. Your summary should be about {goal_length} words.",
-            ),
+            content: custom_system_prompt(options, goal_length, &language, &source_language).unwrap_or_else(|| format!(
+                "You are a summarization assistant. When the user gives you a message, you respond with a summary of the information inside. Just summarize the information without saying \"the speaker says\" or similar. The message will be an autogenerated transcript of a youtube video, and may have transcription errors and improperly separated speakers.{source_language} Your summary should be about {goal_length} words.{language}",
+            )),
         },
         ChatMessage {
             role: "user",
@@ -31,33 +234,402 @@ pub(crate) fn summarize(
         },
     ];
 
-    let chat_tokens = openai::count_tokens(&messages);
+    Ok(messages)
+}
+
+/// Like `summarize`, but as a nested bullet list to skim instead of
+/// paragraphs.
+pub fn summarize_bullets(
+    raw_transcript: String,
+    title: Option<String>,
+    channel_name: Option<String>,
+    source_language: Option<whatlang::Lang>,
+    options: &SummaryOptions,
+) -> Result<Vec<ChatMessage>, Error> {
+    let goal_length = goal_length(
+        raw_transcript.split(' ').count(),
+        min_summary_words(),
+        options,
+    )?;
+    // A bullet point is a sentence or so.
+    let bullets = (goal_length / 20).max(3);
+    let language = language_instruction(options, source_language);
+    let source_language = source_language_note(source_language);
+
+    let messages = vec![
+        ChatMessage {
+            role: "system",
+            content: custom_system_prompt(options, goal_length, &language, &source_language).unwrap_or_else(|| format!(
+                "You are a summarization assistant. When the user gives you a message, you respond with a summary of the information inside as a nested markdown bullet list. Just summarize the information without saying \"the speaker says\" or similar. The message will be an autogenerated transcript of a youtube video, and may have transcription errors and improperly separated speakers.{source_language} Your summary should be about {bullets} bullet points.{language}",
+            )),
+        },
+        ChatMessage {
+            role: "user",
+            content: format!(
+                "{title}{channel}\n\nTranscript: {raw_transcript}\n\n\nSummarize the transcript as a markdown bullet list, with the main points as top-level bullets and supporting details nested under them. Keep each bullet to a single short sentence, without extra fluff like '{the_speaker} says' or 'in this video'. Emphasize the most important information in **bold**. Remember that your summary should be about {bullets} bullet points. Just return the list without repeating the Title or Channel, and don't write `Summary:`. The video may have sponsorships/embedded advertising, this is completely irrelevant to the user so do not include this information in the summary!",
+                title=title.map(|title| format!("Title: {title}")).unwrap_or_default(),
+                channel=channel_name.clone().map(|channel_name| format!("\nChannel: {channel_name}")).unwrap_or_default(),
+                the_speaker=channel_name.unwrap_or("the speaker".to_string()),
+            ),
+        },
+    ];
 
-    Ok((messages, chat_tokens as u64))
+    Ok(messages)
 }
 
-pub(crate) fn clean_transcript_one_prompt(
+/// Like `summarize`, followed by a short bullet list of the video's key
+/// takeaways, each under its own `##` heading.
+pub fn summarize_notes(
     raw_transcript: String,
     title: Option<String>,
     channel_name: Option<String>,
-) -> (Vec<ChatMessage>, u64) {
+    source_language: Option<whatlang::Lang>,
+    options: &SummaryOptions,
+) -> Result<Vec<ChatMessage>, Error> {
+    let goal_length = goal_length(
+        raw_transcript.split(' ').count(),
+        min_summary_words(),
+        options,
+    )?;
+    let takeaways = (goal_length / 50).clamp(3, 10);
+    let language = language_instruction(options, source_language);
+    let source_language = source_language_note(source_language);
+
     let messages = vec![
         ChatMessage {
             role: "system",
-            content:
-                "You are a transcription assistant. The user will send an autogenerated transcript of a youtube video, which may have transcription errors, punctuation errors, and improperly separated speakers. You respond with a cleaned-up version of the transcript. The channel name and video title will be included in the message for additional context, but you should not include them in your response".to_string(),
+            content: custom_system_prompt(options, goal_length, &language, &source_language).unwrap_or_else(|| format!(
+                "You are a summarization assistant. When the user gives you a message, you respond with a summary of the information inside, followed by its key takeaways. Just summarize the information without saying \"the speaker says\" or similar. The message will be an autogenerated transcript of a youtube video, and may have transcription errors and improperly separated speakers.{source_language} Your summary should be about {goal_length} words, followed by {takeaways} takeaways.{language}",
+            )),
         },
         ChatMessage {
             role: "user",
             content: format!(
-                "{title}{channel}\n\nTranscript: {raw_transcript}\n\n\nClean up the transcript above, fixing punctuation, transcription errors, and improperly separated speakers. Use full markdown syntax, and break it into paragraphs. Emphasize the most important information in **bold**. Just return the transcript without repeating the Title or Channel, and don't write `Transcript:`.",
+                "{title}{channel}\n\nTranscript: {raw_transcript}\n\n\nStart with a `## Summary` heading, followed by a summary of the transcript in markdown paragraphs of about {goal_length} words, without extra fluff like '{the_speaker} says' or 'in this video'. Then add a `## Key Takeaways` heading, followed by a markdown bullet list of the {takeaways} most important things to remember from the video, one short sentence each. Emphasize the most important information in **bold**. Don't repeat the Title or Channel, and don't add any other headings. The video may have sponsorships/embedded advertising, this is completely irrelevant to the user so do not include this information!",
                 title=title.map(|title| format!("Title: {title}")).unwrap_or_default(),
                 channel=channel_name.clone().map(|channel_name| format!("\nChannel: {channel_name}")).unwrap_or_default(),
+                the_speaker=channel_name.unwrap_or("the speaker".to_string()),
+            ),
+        },
+    ];
+
+    Ok(messages)
+}
+
+/// Like `summarize`, for a video whose description splits it into chapters.
+/// Takes each chapter's title and transcript, and asks for a summary of each
+/// under its title.
+pub fn summarize_chapters(
+    chapters: Vec<(String, String)>,
+    title: Option<String>,
+    channel_name: Option<String>,
+    source_language: Option<whatlang::Lang>,
+    options: &SummaryOptions,
+) -> Result<Vec<ChatMessage>, Error> {
+    let words = chapters
+        .iter()
+        .map(|(_, transcript)| transcript.split(' ').count())
+        .sum();
+    let goal_length = goal_length(words, min_summary_words(), options)?;
+    let language = language_instruction(options, source_language);
+    let source_language = source_language_note(source_language);
+    let chapters = chapters
+        .into_iter()
+        .map(|(title, transcript)| format!("Chapter: {title}\nTranscript: {transcript}"))
+        .collect::<Vec<_>>()
+        .join("\n\n");
+
+    let messages = vec![
+        ChatMessage {
+            role: "system",
+            content: custom_system_prompt(options, goal_length, &language, &source_language).unwrap_or_else(|| format!(
+                "You are a summarization assistant. The user will send an autogenerated transcript of a youtube video split into its chapters, which may have transcription errors and improperly separated speakers. You respond with a summary of each chapter, in order, under a markdown `##` header with the chapter's title. Just summarize the information without saying \"the speaker says\" or similar.{source_language} Your whole summary should be about {goal_length} words.{language}",
+            )),
+        },
+        ChatMessage {
+            role: "user",
+            content: format!(
+                "{title}{channel}\n\n{chapters}\n\n\nBe as concise as possible in your summary. Repeat the information as without extra fluff like '{the_speaker} says' or 'in this video'. Use full markdown syntax, with a `##` header for each chapter using its title exactly. Emphasize the most important information in **bold**. Remember that your whole summary should be about {goal_length} words, split between the chapters by how much happens in each. Just return the summary without repeating the Title or Channel, and don't write `Summary:`. The video may have sponsorships/embedded advertising, this is completely irrelevant to the user so do not include this information in the summary! A chapter that's only a sponsorship can be left out.",
+                title=title.map(|title| format!("Title: {title}")).unwrap_or_default(),
+                channel=channel_name.clone().map(|channel_name| format!("\nChannel: {channel_name}")).unwrap_or_default(),
+                the_speaker=channel_name.unwrap_or("the speaker".to_string()),
+            ),
+        },
+    ];
+
+    Ok(messages)
+}
+
+/// The last step of summarizing a transcript too long for one prompt: the
+/// summaries of its consecutive parts, in order, combined into one. `words`
+/// is how long the transcript they cover is.
+pub fn summarize_summaries(
+    summaries: Vec<String>,
+    words: usize,
+    title: Option<String>,
+    channel_name: Option<String>,
+    source_language: Option<whatlang::Lang>,
+    options: &SummaryOptions,
+) -> Result<Vec<ChatMessage>, Error> {
+    let goal_length = goal_length(words, min_summary_words(), options)?;
+    let language = language_instruction(options, source_language);
+    let source_language = source_language_note(source_language);
+    let (length, format) = match options.style {
+        Some(SummaryStyle::Bullets) => (
+            format!("about {} bullet points", (goal_length / 20).max(3)),
+            "Write it as a markdown bullet list, with the main points as top-level bullets and supporting details nested under them.",
+        ),
+        Some(SummaryStyle::Notes) => (
+            format!("about {goal_length} words"),
+            "Start with a `## Summary` heading followed by the summary in markdown paragraphs, then add a `## Key Takeaways` heading followed by a markdown bullet list of the most important things to remember, one short sentence each.",
+        ),
+        _ => (
+            format!("about {goal_length} words"),
+            "Use full markdown syntax, and break the summary into paragraphs.",
+        ),
+    };
+    let parts = summaries.len();
+    let summaries = summaries
+        .into_iter()
+        .enumerate()
+        .map(|(index, summary)| format!("Part {} of {parts}:\n{summary}", index + 1))
+        .collect::<Vec<_>>()
+        .join("\n\n");
+
+    let messages = vec![
+        ChatMessage {
+            role: "system",
+            content: format!(
+                "You are a summarization assistant. The user will send summaries of consecutive parts of a youtube video's transcript, in order. You respond with one summary of the whole video that combines them. Just summarize the information without saying \"the speaker says\" or similar.{source_language} Your summary should be {length}.{language}",
+            ),
+        },
+        ChatMessage {
+            role: "user",
+            content: format!(
+                "{title}{channel}\n\n{summaries}\n\n\nCombine the summaries above into one summary of the whole video, without repeating what several of them say. {format} Emphasize the most important information in **bold**. Remember that your summary should be {length}. Just return the summary without repeating the Title or Channel, and don't write `Summary:`.",
+                title=title.map(|title| format!("Title: {title}")).unwrap_or_default(),
+                channel=channel_name.map(|channel_name| format!("\nChannel: {channel_name}")).unwrap_or_default(),
             ),
         },
     ];
 
-    let chat_tokens = openai::count_tokens(&messages);
+    Ok(messages)
+}
+
+/// For videos with no speech to summarize, e.g. music videos.
+pub fn summarize_description(
+    description: String,
+    title: String,
+    channel_name: String,
+    options: &SummaryOptions,
+) -> Vec<ChatMessage> {
+    let language = options
+        .language
+        .as_ref()
+        .map(|language| format!(" Write the summary in the language with code `{language}`."))
+        .unwrap_or_default();
+    vec![
+        ChatMessage {
+            role: "system",
+            content: format!(
+                "You are a summarization assistant. The user will send the description of a youtube video that has no speech, such as a music video. You respond with a few sentences about what the video is, based only on the description. Leave out links, social media handles and sponsorships.{language}"
+            ),
+        },
+        ChatMessage {
+            role: "user",
+            content: format!("Title: {title}\nChannel: {channel_name}\n\nDescription: {description}"),
+        },
+    ]
+}
+
+pub fn refine_summary(summary: String, request: String) -> Vec<ChatMessage> {
+    vec![
+        ChatMessage {
+            role: "system",
+            content: "You are a summarization assistant. The user will send a summary of a youtube video you wrote earlier, followed by how they'd like it changed, e.g. shorter, more detailed, or as bullet points. Respond with the revised summary only, keeping the same markdown style. Don't add information that isn't in the summary.".to_string(),
+        },
+        ChatMessage {
+            role: "user",
+            content: format!("Summary: {summary}\n\n\nRequested change: {request}"),
+        },
+    ]
+}
+
+/// `system_prompt` is the server's own, from `/setprompt`.
+pub fn clean_transcript_messages(
+    raw_transcript: String,
+    title: Option<String>,
+    channel_name: Option<String>,
+    system_prompt: Option<&str>,
+) -> Vec<ChatMessage> {
+    vec![
+        ChatMessage {
+            role: "system",
+            content: system_prompt.map(str::to_string).unwrap_or_else(||
+                "You are a transcription assistant. The user will send an autogenerated transcript of a youtube video, which may have transcription errors, punctuation errors, and improperly separated speakers. You respond with a cleaned-up version of the transcript, in the same language as the transcript. The channel name and video title will be included in the message for additional context, but you should not include them in your response".to_string()),
+        },
+        ChatMessage {
+            role: "user",
+            content: format!(
+                "{title}{channel}\n\nTranscript: {raw_transcript}\n\n\nClean up the transcript above, fixing punctuation, transcription errors, and improperly separated speakers. Use full markdown syntax, and break it into paragraphs. Emphasize the most important information in **bold**. Just return the transcript without repeating the Title or Channel, and don't write `Transcript:`.",
+                title=title.map(|title| format!("Title: {title}")).unwrap_or_default(),
+                channel=channel_name.clone().map(|channel_name| format!("\nChannel: {channel_name}")).unwrap_or_default(),
+            ),
+        },
+    ]
+}
+
+/// Like `clean_transcript_messages`, but with each speaker's turns labelled.
+/// Transcripts cleaned up in parts pass on the `speakers` labelled so far
+/// and the one speaking at the end, so the labels stay the same throughout.
+pub fn clean_transcript_speakers_messages(
+    raw_transcript: String,
+    title: Option<String>,
+    channel_name: Option<String>,
+    speakers: &[String],
+    last_speaker: Option<&str>,
+    system_prompt: Option<&str>,
+) -> Vec<ChatMessage> {
+    let context = match last_speaker {
+        Some(last_speaker) => format!(
+            " This transcript continues an earlier part, in which the speakers were labelled {}. Keep using those labels for them. The last one speaking was {last_speaker}, who may still be speaking at the start.",
+            speakers.join(", "),
+        ),
+        None => String::new(),
+    };
+    vec![
+        ChatMessage {
+            role: "system",
+            content: system_prompt.map(str::to_string).unwrap_or_else(||
+                "You are a transcription assistant. The user will send an autogenerated transcript of a youtube video, which may have transcription errors, punctuation errors, and improperly separated speakers. You respond with a cleaned-up version of the transcript, in the same language as the transcript, with each speaker's turn in a paragraph of its own starting with their label in bold, e.g. `**Speaker A:**`. Use a speaker's name as their label when the transcript makes it clear, and otherwise Speaker A, Speaker B and so on. The channel name and video title will be included in the message for additional context, but you should not include them in your response".to_string()),
+        },
+        ChatMessage {
+            role: "user",
+            content: format!(
+                "{title}{channel}\n\nTranscript: {raw_transcript}\n\n\nClean up the transcript above, fixing punctuation and transcription errors, and separate it into the turns of each speaker, starting each with `**Label:**`.{context} Just return the transcript without repeating the Title or Channel, and don't write `Transcript:`.",
+                title=title.map(|title| format!("Title: {title}")).unwrap_or_default(),
+                channel=channel_name.map(|channel_name| format!("\nChannel: {channel_name}")).unwrap_or_default(),
+            ),
+        },
+    ]
+}
+
+pub fn answer_question(
+    raw_transcript: String,
+    title: Option<String>,
+    channel_name: Option<String>,
+    question: String,
+) -> Vec<ChatMessage> {
+    vec![
+        ChatMessage {
+            role: "system",
+            content:
+                "You are a research assistant. The user will send an autogenerated transcript of a youtube video, which may have transcription errors and improperly separated speakers, followed by a question about the video. Answer the question using only the information in the transcript, and back up your answer by quoting the relevant parts of the transcript as markdown blockquotes. If the transcript doesn't answer the question, say so rather than guessing.".to_string(),
+        },
+        ChatMessage {
+            role: "user",
+            content: format!(
+                "{title}{channel}\n\nTranscript: {raw_transcript}\n\n\nQuestion: {question}",
+                title=title.map(|title| format!("Title: {title}")).unwrap_or_default(),
+                channel=channel_name.map(|channel_name| format!("\nChannel: {channel_name}")).unwrap_or_default(),
+            ),
+        },
+    ]
+}
+
+#[test]
+fn test_goal_length() {
+    let options = SummaryOptions::default();
+    let short = SummaryOptions {
+        length: Some(SummaryLength::Short),
+        ..SummaryOptions::default()
+    };
+
+    assert_eq!(goal_length(1000, 200, &options).unwrap(), 200);
+    assert!(matches!(
+        goal_length(200, 200, &options),
+        Err(Error::TranscriptTooShort {
+            words: 200,
+            min_words: 200
+        })
+    ));
+    // With the minimum lowered, short videos still get a few sentences.
+    assert_eq!(goal_length(50, 20, &options).unwrap(), 25);
+    assert_eq!(goal_length(50, 20, &short).unwrap(), 25);
+    assert_eq!(goal_length(21, 20, &options).unwrap(), 21);
+}
+
+#[test]
+fn test_language_instruction() {
+    let options = SummaryOptions::default();
+    let spanish = SummaryOptions {
+        language: Some("es".to_string()),
+        ..SummaryOptions::default()
+    };
+
+    assert_eq!(
+        language_instruction(&options, Some(whatlang::Lang::Fra)),
+        " Write the summary in French, the same language as the transcript."
+    );
+    assert_eq!(
+        language_instruction(&options, Some(whatlang::Lang::Eng)),
+        ""
+    );
+    assert_eq!(language_instruction(&options, None), "");
+    assert_eq!(
+        language_instruction(&spanish, Some(whatlang::Lang::Fra)),
+        " Write the summary in the language with code `es`, translating from French as you go."
+    );
+    assert_eq!(
+        language_instruction(&spanish, None),
+        " Write the summary in the language with code `es`."
+    );
+
+    // Asking for the language it's already in doesn't ask for a translation.
+    assert_eq!(
+        language_instruction(&spanish, Some(whatlang::Lang::Spa)),
+        " Write the summary in Spanish, the same language as the transcript."
+    );
+    let english = SummaryOptions {
+        language: Some("EN".to_string()),
+        ..SummaryOptions::default()
+    };
+    assert_eq!(
+        language_instruction(&english, Some(whatlang::Lang::Eng)),
+        ""
+    );
+    assert!(is_language("deu", whatlang::Lang::Deu));
+    assert!(is_language("German", whatlang::Lang::Deu));
+    assert!(!is_language("en", whatlang::Lang::Deu));
+}
+
+#[test]
+fn test_custom_system_prompt() {
+    let options = SummaryOptions {
+        language: Some("es".to_string()),
+        system_prompt: Some(
+            "Summarize this casually in {goal_length} words. {language}".to_string(),
+        ),
+        ..SummaryOptions::default()
+    };
+    let transcript = vec!["word"; 1000].join(" ");
+    let messages = summarize(transcript, None, None, None, &options).unwrap();
+    assert_eq!(
+        messages[0].content,
+        "Summarize this casually in 200 words. Write the summary in the language with code `es`."
+    );
 
-    (messages, chat_tokens as u64)
+    let model = "gpt-4-1106-preview";
+    assert_eq!(
+        check_template(
+            "Be academic. {goal_length} words.",
+            &SUMMARY_PLACEHOLDERS,
+            model
+        ),
+        Ok(())
+    );
+    assert!(check_template("  ", &SUMMARY_PLACEHOLDERS, model).is_err());
+    assert!(check_template("Use {tone}.", &SUMMARY_PLACEHOLDERS, model).is_err());
+    assert!(check_template("{goal_length} words.", &[], model).is_err());
+    assert!(check_template(&"word ".repeat(2000), &[], model).is_err());
 }
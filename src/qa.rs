@@ -0,0 +1,99 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+
+use serenity::all::ChannelId;
+
+use crate::sources::Video;
+
+/// The most threads remembered. Questions in older ones go unanswered.
+const MAX_THREADS: usize = 1000;
+
+struct QaThread {
+    video: Video,
+    questions: usize,
+}
+
+#[derive(Default)]
+struct Remembered {
+    threads: HashMap<ChannelId, QaThread>,
+    /// Oldest first, to know which to forget.
+    order: VecDeque<ChannelId>,
+}
+
+/// Threads the bot opened for a video's summary, where any message is taken
+/// as a question about that video.
+pub struct QaThreads {
+    remembered: Mutex<Remembered>,
+    max_questions: usize,
+}
+
+pub enum Question {
+    /// Answer it about this video.
+    Ask(Video),
+    /// The thread has used up its questions.
+    LimitReached(usize),
+}
+
+impl QaThreads {
+    pub fn new(max_questions: usize) -> QaThreads {
+        QaThreads {
+            remembered: Mutex::default(),
+            max_questions,
+        }
+    }
+
+    pub fn register(&self, thread_id: ChannelId, video: Video) {
+        let mut remembered = self.remembered.lock().unwrap();
+        let thread = QaThread {
+            video,
+            questions: 0,
+        };
+        if remembered.threads.insert(thread_id, thread).is_none() {
+            remembered.order.push_back(thread_id);
+        }
+        while remembered.order.len() > MAX_THREADS {
+            if let Some(oldest) = remembered.order.pop_front() {
+                remembered.threads.remove(&oldest);
+            }
+        }
+    }
+
+    /// `None` if `channel_id` isn't one of our threads. Otherwise counts the
+    /// question against the thread's limit.
+    pub fn ask(&self, channel_id: ChannelId) -> Option<Question> {
+        let mut remembered = self.remembered.lock().unwrap();
+        let thread = remembered.threads.get_mut(&channel_id)?;
+        if thread.questions >= self.max_questions {
+            return Some(Question::LimitReached(self.max_questions));
+        }
+        thread.questions += 1;
+        Some(Question::Ask(thread.video.clone()))
+    }
+}
+
+#[test]
+fn test_question_limit() {
+    let threads = QaThreads::new(2);
+    let thread_id = ChannelId::new(1);
+    assert!(threads.ask(thread_id).is_none());
+
+    let video = Video::new(&crate::youtube::YouTube, "dQw4w9WgXcQ".to_string());
+    threads.register(thread_id, video.clone());
+    assert!(matches!(threads.ask(thread_id), Some(Question::Ask(asked)) if asked == video));
+    assert!(matches!(threads.ask(thread_id), Some(Question::Ask(_))));
+    assert!(matches!(
+        threads.ask(thread_id),
+        Some(Question::LimitReached(2))
+    ));
+}
+
+#[test]
+fn test_forgets_oldest_threads() {
+    let threads = QaThreads::new(2);
+    let video = Video::new(&crate::youtube::YouTube, "dQw4w9WgXcQ".to_string());
+    for index in 1..=MAX_THREADS as u64 + 1 {
+        threads.register(ChannelId::new(index), video.clone());
+    }
+    assert!(threads.ask(ChannelId::new(1)).is_none());
+    assert!(threads.ask(ChannelId::new(2)).is_some());
+}
@@ -0,0 +1,57 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use serenity::all::UserId;
+
+/// Caps how many jobs each user can start in a sliding window, since every
+/// one of them costs OpenAI requests.
+pub struct RateLimiter {
+    requests: Mutex<HashMap<UserId, Vec<Instant>>>,
+    max_requests: usize,
+    window: Duration,
+}
+
+impl RateLimiter {
+    pub fn new(max_requests: usize, window: Duration) -> RateLimiter {
+        RateLimiter {
+            requests: Mutex::new(HashMap::new()),
+            max_requests,
+            window,
+        }
+    }
+
+    /// Counts a request from `user_id` if they're under the limit. Otherwise
+    /// returns how long until they can make another.
+    pub fn check(&self, user_id: UserId) -> Result<(), Duration> {
+        let now = Instant::now();
+        let mut requests = self.requests.lock().unwrap();
+        let recent = requests.entry(user_id).or_default();
+        recent.retain(|at| now.duration_since(*at) < self.window);
+        if recent.len() >= self.max_requests {
+            return Err(recent.first().map_or(self.window, |first| {
+                self.window - now.duration_since(*first)
+            }));
+        }
+        recent.push(now);
+        Ok(())
+    }
+}
+
+#[test]
+fn test_rate_limiter() {
+    let limiter = RateLimiter::new(2, Duration::from_secs(600));
+    let user = UserId::new(1);
+    let other = UserId::new(2);
+
+    assert!(limiter.check(user).is_ok());
+    assert!(limiter.check(user).is_ok());
+    let wait = limiter.check(user).unwrap_err();
+    assert!(wait > Duration::from_secs(590) && wait <= Duration::from_secs(600));
+    assert!(limiter.check(other).is_ok());
+
+    let limiter = RateLimiter::new(1, Duration::ZERO);
+    assert!(limiter.check(user).is_ok());
+    std::thread::sleep(Duration::from_millis(1));
+    assert!(limiter.check(user).is_ok());
+}
@@ -0,0 +1,217 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+use serenity::all::{ChannelId, GuildId, UserId};
+use tracing::error;
+
+use crate::options::SummaryOptions;
+use crate::triggers::{Trigger, TriggerEmoji, Triggers};
+
+/// Where a user's summaries and transcripts are posted, from `/prefs`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Delivery {
+    /// In the channel, or a thread when it's long.
+    #[default]
+    Channel,
+    /// Always in a thread.
+    Thread,
+    /// In a DM to them.
+    Dm,
+}
+
+impl Delivery {
+    pub fn parse(s: &str) -> Option<Delivery> {
+        match s.trim().to_lowercase().as_str() {
+            "channel" => Some(Delivery::Channel),
+            "thread" => Some(Delivery::Thread),
+            "dm" => Some(Delivery::Dm),
+            _ => None,
+        }
+    }
+}
+
+/// A guild's `/emoji` choices. Unset ones use `Config::triggers`.
+#[derive(Default, Serialize, Deserialize)]
+struct GuildTriggers {
+    #[serde(default)]
+    summarize: Option<TriggerEmoji>,
+    #[serde(default)]
+    transcribe: Option<TriggerEmoji>,
+    #[serde(default)]
+    bullets: Option<TriggerEmoji>,
+    #[serde(default)]
+    notes: Option<TriggerEmoji>,
+}
+
+/// Which of a guild's system prompts `/setprompt` changes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PromptKind {
+    Summary,
+    Transcript,
+}
+
+impl PromptKind {
+    pub fn parse(s: &str) -> Option<PromptKind> {
+        match s.trim().to_lowercase().as_str() {
+            "summary" => Some(PromptKind::Summary),
+            "transcript" => Some(PromptKind::Transcript),
+            _ => None,
+        }
+    }
+}
+
+/// A guild's own system prompts, from `/setprompt`. Unset ones use the
+/// built-in prompts.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct GuildPrompts {
+    #[serde(default)]
+    pub summary: Option<String>,
+    #[serde(default)]
+    pub transcript: Option<String>,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct SettingsData {
+    #[serde(default)]
+    channel_defaults: HashMap<u64, SummaryOptions>,
+    /// Guilds that have been explicitly enabled or disabled. Guilds missing
+    /// from here fall back to `Config::guilds_enabled_by_default`.
+    #[serde(default)]
+    guilds: HashMap<u64, bool>,
+    /// Guilds that changed their trigger emoji with `/emoji`.
+    #[serde(default)]
+    triggers: HashMap<u64, GuildTriggers>,
+    /// Users that changed where their output goes with `/prefs`.
+    #[serde(default)]
+    delivery: HashMap<u64, Delivery>,
+    /// Guilds that changed their system prompts with `/setprompt`.
+    #[serde(default)]
+    prompts: HashMap<u64, GuildPrompts>,
+}
+
+/// Settings changed at runtime through commands, written back to a JSON file
+/// so they survive restarts.
+pub struct Settings {
+    path: PathBuf,
+    data: Mutex<SettingsData>,
+}
+
+impl Settings {
+    /// A missing or unreadable file just means nothing has been configured yet.
+    pub fn load(path: PathBuf) -> Settings {
+        let data = match std::fs::read_to_string(&path) {
+            Ok(contents) => serde_json::from_str(&contents).unwrap_or_else(|why| {
+                error!("Error parsing settings file {path:?}: {why:?}");
+                SettingsData::default()
+            }),
+            Err(_) => SettingsData::default(),
+        };
+        Settings {
+            path,
+            data: Mutex::new(data),
+        }
+    }
+
+    fn save(&self, data: &SettingsData) {
+        let contents = serde_json::to_string_pretty(data).unwrap();
+        if let Err(why) = std::fs::write(&self.path, contents) {
+            error!("Error writing settings file {:?}: {:?}", self.path, why);
+        }
+    }
+
+    pub fn channel_defaults(&self, channel_id: ChannelId) -> Option<SummaryOptions> {
+        let data = self.data.lock().unwrap();
+        data.channel_defaults.get(&channel_id.get()).cloned()
+    }
+
+    /// `None` clears the channel's defaults.
+    pub fn set_channel_defaults(&self, channel_id: ChannelId, options: Option<SummaryOptions>) {
+        let mut data = self.data.lock().unwrap();
+        match options {
+            Some(options) => data.channel_defaults.insert(channel_id.get(), options),
+            None => data.channel_defaults.remove(&channel_id.get()),
+        };
+        self.save(&data);
+    }
+
+    pub fn guild_enabled(&self, guild_id: GuildId, default: bool) -> bool {
+        let data = self.data.lock().unwrap();
+        data.guilds.get(&guild_id.get()).copied().unwrap_or(default)
+    }
+
+    pub fn set_guild_enabled(&self, guild_id: GuildId, enabled: bool) {
+        let mut data = self.data.lock().unwrap();
+        data.guilds.insert(guild_id.get(), enabled);
+        self.save(&data);
+    }
+
+    /// DMs always use the default emoji.
+    pub fn triggers(&self, guild_id: Option<GuildId>, default: &Triggers) -> Triggers {
+        let data = self.data.lock().unwrap();
+        let Some(guild) = guild_id.and_then(|guild_id| data.triggers.get(&guild_id.get())) else {
+            return default.clone();
+        };
+        Triggers {
+            summarize: guild.summarize.clone().unwrap_or(default.summarize.clone()),
+            transcribe: guild
+                .transcribe
+                .clone()
+                .unwrap_or(default.transcribe.clone()),
+            bullets: guild.bullets.clone().unwrap_or(default.bullets.clone()),
+            notes: guild.notes.clone().unwrap_or(default.notes.clone()),
+        }
+    }
+
+    pub fn delivery(&self, user_id: UserId) -> Delivery {
+        let data = self.data.lock().unwrap();
+        data.delivery
+            .get(&user_id.get())
+            .copied()
+            .unwrap_or_default()
+    }
+
+    pub fn set_delivery(&self, user_id: UserId, delivery: Delivery) {
+        let mut data = self.data.lock().unwrap();
+        match delivery {
+            Delivery::Channel => data.delivery.remove(&user_id.get()),
+            delivery => data.delivery.insert(user_id.get(), delivery),
+        };
+        self.save(&data);
+    }
+
+    /// DMs always use the built-in prompts.
+    pub fn prompts(&self, guild_id: Option<GuildId>) -> GuildPrompts {
+        let data = self.data.lock().unwrap();
+        guild_id
+            .and_then(|guild_id| data.prompts.get(&guild_id.get()))
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// `None` goes back to the built-in prompt.
+    pub fn set_prompt(&self, guild_id: GuildId, kind: PromptKind, prompt: Option<String>) {
+        let mut data = self.data.lock().unwrap();
+        let prompts = data.prompts.entry(guild_id.get()).or_default();
+        match kind {
+            PromptKind::Summary => prompts.summary = prompt,
+            PromptKind::Transcript => prompts.transcript = prompt,
+        }
+        self.save(&data);
+    }
+
+    /// `None` goes back to the default emoji for `trigger`.
+    pub fn set_trigger(&self, guild_id: GuildId, trigger: Trigger, emoji: Option<TriggerEmoji>) {
+        let mut data = self.data.lock().unwrap();
+        let triggers = data.triggers.entry(guild_id.get()).or_default();
+        match trigger {
+            Trigger::Summarize => triggers.summarize = emoji,
+            Trigger::Transcribe => triggers.transcribe = emoji,
+            Trigger::Bullets => triggers.bullets = emoji,
+            Trigger::Notes => triggers.notes = emoji,
+        }
+        self.save(&data);
+    }
+}
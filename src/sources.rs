@@ -0,0 +1,115 @@
+use std::fmt;
+
+use serenity::async_trait;
+use tokio::sync::watch;
+
+use crate::cache::Cache;
+use crate::openai;
+use crate::options::SummaryOptions;
+use crate::youtube::{self, Error, Estimate, TranscriptOptions, VideoInfo};
+
+/// A site whose videos can be summarized and transcribed. Supporting another
+/// one means implementing this and adding it to `SOURCES`.
+#[async_trait]
+pub trait TranscriptSource: Send + Sync {
+    /// Names the source in logs, and tells sources apart.
+    fn name(&self) -> &'static str;
+
+    /// The id of the video `url` links to, if it's one of this source's.
+    fn matches(&self, url: &str) -> Option<String>;
+
+    /// Where to watch the video, for embeds to link to.
+    fn video_url(&self, id: &str) -> String;
+
+    fn thumbnail_url(&self, id: &str) -> String;
+
+    /// The video's transcript as one block of text.
+    async fn fetch_transcript(&self, id: &str) -> Result<String, Error>;
+
+    async fn video_info(&self, id: &str) -> Result<VideoInfo, Error>;
+
+    /// The video summarized, sending the summary so far to `progress` as
+    /// it's written.
+    async fn summary(
+        &self,
+        id: &str,
+        options: &SummaryOptions,
+        cache: &Cache,
+        describe_no_speech: bool,
+        model: &str,
+        progress: Option<watch::Sender<String>>,
+    ) -> Result<((String, VideoInfo), openai::Usage), Error>;
+
+    /// The transcript cleaned up to read like an article.
+    async fn transcript(
+        &self,
+        id: &str,
+        cache: &Cache,
+        options: &TranscriptOptions,
+    ) -> Result<((String, VideoInfo), openai::Usage), Error>;
+
+    async fn estimate(
+        &self,
+        id: &str,
+        options: &SummaryOptions,
+        model: &str,
+    ) -> Result<Estimate, Error>;
+
+    async fn answer_question(
+        &self,
+        id: &str,
+        question: String,
+        cache: &Cache,
+        model: &str,
+    ) -> Result<(String, openai::Usage), Error>;
+}
+
+/// Tried in order for every link.
+pub static SOURCES: &[&dyn TranscriptSource] = &[&youtube::YouTube];
+
+/// A video, and the source to get everything about it from.
+#[derive(Clone)]
+pub struct Video {
+    pub source: &'static dyn TranscriptSource,
+    pub id: String,
+}
+
+impl Video {
+    pub fn new(source: &'static dyn TranscriptSource, id: String) -> Video {
+        Video { source, id }
+    }
+
+    pub fn url(&self) -> String {
+        self.source.video_url(&self.id)
+    }
+
+    pub fn thumbnail_url(&self) -> String {
+        self.source.thumbnail_url(&self.id)
+    }
+}
+
+impl PartialEq for Video {
+    fn eq(&self, other: &Video) -> bool {
+        self.source.name() == other.source.name() && self.id == other.id
+    }
+}
+
+impl fmt::Debug for Video {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} video {}", self.source.name(), self.id)
+    }
+}
+
+/// The video `url` links to, from the first source that recognizes it.
+pub fn find(url: &str) -> Option<Video> {
+    SOURCES
+        .iter()
+        .find_map(|source| Some(Video::new(*source, source.matches(url)?)))
+}
+
+/// The transcript as the source gives it, without any cleanup.
+pub async fn raw_transcript(video: &Video) -> Result<(String, VideoInfo), Error> {
+    let info = video.source.video_info(&video.id).await?;
+    let transcript = video.source.fetch_transcript(&video.id).await?;
+    Ok((transcript, info))
+}
@@ -0,0 +1,205 @@
+use serde::{Deserialize, Serialize};
+use serenity::all::{EmojiId, ReactionType};
+
+pub const TRANSCRIBE_EMOJI: &str = "📜";
+pub const SUMMARIZE_EMOJI: &str = "💭";
+pub const BULLETS_EMOJI: &str = "📋";
+pub const NOTES_EMOJI: &str = "📝";
+
+/// An emoji that triggers an action when reacted with. Custom guild emoji are
+/// matched by id, so renaming them doesn't break the trigger.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TriggerEmoji {
+    Unicode(String),
+    Custom {
+        id: u64,
+        name: String,
+        animated: bool,
+    },
+}
+
+impl TriggerEmoji {
+    /// Accepts a Unicode emoji, or a custom one as Discord sends it in a
+    /// message, e.g. `<:leonidas:123456789>` or `<a:leonidas:123456789>`.
+    pub fn parse(text: &str) -> Option<TriggerEmoji> {
+        let text = text.trim();
+        if let Some(custom) = text.strip_prefix('<').and_then(|s| s.strip_suffix('>')) {
+            let (animated, rest) = match custom.strip_prefix("a:") {
+                Some(rest) => (true, rest),
+                None => (false, custom.strip_prefix(':')?),
+            };
+            let (name, id) = rest.split_once(':')?;
+            return Some(TriggerEmoji::Custom {
+                id: id.parse().ok().filter(|id| *id != 0)?,
+                name: name.to_string(),
+                animated,
+            });
+        }
+        // Anything all-ASCII is plain text rather than an emoji.
+        if text.chars().all(|c| c.is_ascii()) {
+            return None;
+        }
+        Some(TriggerEmoji::Unicode(text.to_string()))
+    }
+
+    pub fn matches(&self, emoji: &ReactionType) -> bool {
+        match (self, emoji) {
+            (TriggerEmoji::Unicode(unicode), _) => emoji.unicode_eq(unicode),
+            (TriggerEmoji::Custom { id, .. }, ReactionType::Custom { id: emoji_id, .. }) => {
+                emoji_id.get() == *id
+            }
+            _ => false,
+        }
+    }
+
+    pub fn reaction(&self) -> ReactionType {
+        match self {
+            TriggerEmoji::Unicode(unicode) => ReactionType::Unicode(unicode.clone()),
+            TriggerEmoji::Custom { id, name, animated } => ReactionType::Custom {
+                animated: *animated,
+                id: EmojiId::new(*id),
+                name: Some(name.clone()),
+            },
+        }
+    }
+
+    /// How to show the emoji in a message.
+    pub fn display(&self) -> String {
+        match self {
+            TriggerEmoji::Unicode(unicode) => unicode.clone(),
+            TriggerEmoji::Custom { id, name, animated } => {
+                format!("<{}:{name}:{id}>", if *animated { "a" } else { "" })
+            }
+        }
+    }
+}
+
+/// What reacting with one of the `Triggers` asks for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Trigger {
+    Summarize,
+    Transcribe,
+    /// A summary as a bullet list.
+    Bullets,
+    /// A summary followed by its key takeaways.
+    Notes,
+}
+
+/// The emoji used for each action.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Triggers {
+    pub summarize: TriggerEmoji,
+    pub transcribe: TriggerEmoji,
+    pub bullets: TriggerEmoji,
+    pub notes: TriggerEmoji,
+}
+
+impl Triggers {
+    pub fn get(&self, trigger: Trigger) -> &TriggerEmoji {
+        match trigger {
+            Trigger::Summarize => &self.summarize,
+            Trigger::Transcribe => &self.transcribe,
+            Trigger::Bullets => &self.bullets,
+            Trigger::Notes => &self.notes,
+        }
+    }
+
+    /// Transcribe wins if a guild picked the same emoji for more than one.
+    pub fn matching(&self, emoji: &ReactionType) -> Option<Trigger> {
+        [
+            Trigger::Transcribe,
+            Trigger::Summarize,
+            Trigger::Bullets,
+            Trigger::Notes,
+        ]
+        .into_iter()
+        .find(|trigger| self.get(*trigger).matches(emoji))
+    }
+}
+
+#[test]
+fn test_parse_trigger_emoji() {
+    assert_eq!(
+        TriggerEmoji::parse(" 🔥 "),
+        Some(TriggerEmoji::Unicode("🔥".to_string()))
+    );
+    assert_eq!(
+        TriggerEmoji::parse("<:leonidas:123456789>"),
+        Some(TriggerEmoji::Custom {
+            id: 123456789,
+            name: "leonidas".to_string(),
+            animated: false,
+        })
+    );
+    assert_eq!(
+        TriggerEmoji::parse("<a:spin:42>"),
+        Some(TriggerEmoji::Custom {
+            id: 42,
+            name: "spin".to_string(),
+            animated: true,
+        })
+    );
+    assert_eq!(TriggerEmoji::parse("<:leonidas:abc>"), None);
+    assert_eq!(TriggerEmoji::parse("summarize"), None);
+    assert_eq!(TriggerEmoji::parse(""), None);
+}
+
+#[test]
+fn test_trigger_emoji_matches() {
+    let unicode = TriggerEmoji::Unicode(SUMMARIZE_EMOJI.to_string());
+    let custom = TriggerEmoji::parse("<:leonidas:123456789>").unwrap();
+
+    assert!(unicode.matches(&ReactionType::Unicode(SUMMARIZE_EMOJI.to_string())));
+    assert!(!unicode.matches(&ReactionType::Unicode(TRANSCRIBE_EMOJI.to_string())));
+    assert!(!unicode.matches(&custom.reaction()));
+
+    // Custom emoji match on id alone, whatever the name.
+    assert!(custom.matches(&ReactionType::Custom {
+        animated: false,
+        id: EmojiId::new(123456789),
+        name: Some("renamed".to_string()),
+    }));
+    assert!(!custom.matches(&ReactionType::Custom {
+        animated: false,
+        id: EmojiId::new(987654321),
+        name: Some("leonidas".to_string()),
+    }));
+    assert!(!custom.matches(&ReactionType::Unicode("leonidas".to_string())));
+    assert_eq!(custom.display(), "<:leonidas:123456789>");
+}
+
+#[test]
+fn test_matching_trigger() {
+    let unicode = |emoji: &str| TriggerEmoji::Unicode(emoji.to_string());
+    let triggers = Triggers {
+        summarize: unicode(SUMMARIZE_EMOJI),
+        transcribe: unicode(TRANSCRIBE_EMOJI),
+        bullets: unicode(BULLETS_EMOJI),
+        notes: unicode(NOTES_EMOJI),
+    };
+    let reaction = |emoji: &str| ReactionType::Unicode(emoji.to_string());
+
+    assert_eq!(
+        triggers.matching(&reaction(SUMMARIZE_EMOJI)),
+        Some(Trigger::Summarize)
+    );
+    assert_eq!(
+        triggers.matching(&reaction(BULLETS_EMOJI)),
+        Some(Trigger::Bullets)
+    );
+    assert_eq!(
+        triggers.matching(&reaction(NOTES_EMOJI)),
+        Some(Trigger::Notes)
+    );
+    assert_eq!(triggers.matching(&reaction("🔥")), None);
+
+    let clashing = Triggers {
+        transcribe: unicode(BULLETS_EMOJI),
+        ..triggers
+    };
+    assert_eq!(
+        clashing.matching(&reaction(BULLETS_EMOJI)),
+        Some(Trigger::Transcribe)
+    );
+}
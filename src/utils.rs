@@ -1,31 +1,315 @@
+/// `n` with commas between each group of three digits, e.g. "12,400".
+pub fn thousands(n: u64) -> String {
+    let digits = n.to_string();
+    let mut grouped = String::new();
+    for (index, digit) in digits.chars().enumerate() {
+        if index > 0 && (digits.len() - index) % 3 == 0 {
+            grouped.push(',');
+        }
+        grouped.push(digit);
+    }
+    grouped
+}
+
+/// `title` made safe to use as a file name: runs of anything but ASCII
+/// letters, digits and `_`, like spaces, slashes and emoji, become one `-`.
+pub fn file_stem(title: &str) -> String {
+    let mut stem = String::new();
+    for c in title.chars() {
+        if c.is_ascii_alphanumeric() || c == '_' {
+            stem.push(c);
+        } else if !stem.is_empty() && !stem.ends_with('-') {
+            stem.push('-');
+        }
+    }
+    let stem: String = stem.trim_end_matches('-').chars().take(100).collect();
+    if stem.is_empty() {
+        "output".to_string()
+    } else {
+        stem
+    }
+}
+
+/// Longest run of words that's collapsed when it's said twice in a row.
+const MAX_REPEATED_WORDS: usize = 8;
+
+/// `word` without case, for comparing. Punctuation still counts, so a
+/// phrase is only collapsed when the repeat reads the same, and no comma or
+/// period ends up somewhere it wasn't said.
+fn comparable(word: &str) -> String {
+    word.to_lowercase()
+}
+
+/// `text` with runs of whitespace made single spaces, and phrases of two or
+/// more words repeated straight after themselves said once, like the
+/// "going to going to the store" auto-generated captions have where one
+/// caption overlaps the next. Single repeated words ("very very") are kept.
+pub fn collapse_repeats(text: &str) -> String {
+    collapse_repeats_across(&[text]).remove(0)
+}
+
+/// Like [`collapse_repeats`], but over consecutive pieces of text, like
+/// caption lines, so a repeat spanning two of them is spotted too. Each
+/// piece keeps whichever of its words remain, and may end up empty.
+pub fn collapse_repeats_across(pieces: &[&str]) -> Vec<String> {
+    let mut words: Vec<(usize, &str)> = Vec::new();
+    let mut keys: Vec<String> = Vec::new();
+    for (piece, text) in pieces.iter().enumerate() {
+        for word in text.split_whitespace() {
+            words.push((piece, word));
+            keys.push(comparable(word));
+            let len = keys.len();
+            for n in (2..=MAX_REPEATED_WORDS.min(len / 2)).rev() {
+                if keys[len - 2 * n..len - n] == keys[len - n..] {
+                    words.truncate(len - n);
+                    keys.truncate(len - n);
+                    break;
+                }
+            }
+        }
+    }
+    let mut collapsed = vec![String::new(); pieces.len()];
+    for (piece, word) in words {
+        if !collapsed[piece].is_empty() {
+            collapsed[piece].push(' ');
+        }
+        collapsed[piece].push_str(word);
+    }
+    collapsed
+}
+
+/// Words ending in a period that don't end a sentence, compared in
+/// lowercase without the period.
+const ABBREVIATIONS: &[&str] = &[
+    "mr", "mrs", "ms", "dr", "prof", "st", "jr", "sr", "vs", "etc", "approx", "vol", "fig", "inc",
+    "ltd", "co", "corp", "dept", "mt", "ft", "jan", "feb", "mar", "apr", "jun", "jul", "aug",
+    "sep", "sept", "oct", "nov", "dec",
+];
+
+/// Whether `word` ends a sentence when followed by `next`. Abbreviations
+/// ("Dr."), initials ("J.") and dotted ones ("U.S.", "e.g.") don't, and
+/// neither does anything followed by a lowercase word.
+fn ends_sentence(word: &str, next: &str) -> bool {
+    let word = word.trim_end_matches(['"', '\'', '”', '’', ')', '*', '_']);
+    if !word.ends_with(['.', '!', '?']) {
+        return false;
+    }
+    if !next
+        .chars()
+        .find(|c| c.is_alphanumeric())
+        .is_some_and(|c| !c.is_lowercase())
+    {
+        return false;
+    }
+    let Some(stem) = word.strip_suffix('.') else {
+        return true;
+    };
+    let stem = stem.trim_start_matches(|c: char| !c.is_alphanumeric());
+    let is_initial = stem.chars().count() == 1 && stem.chars().all(char::is_alphabetic);
+    !(is_initial || stem.contains('.') || ABBREVIATIONS.contains(&stem.to_lowercase().as_str()))
+}
+
+/// The sentences in `paragraph`, with runs of whitespace made single spaces.
+pub fn sentences(paragraph: &str) -> Vec<String> {
+    let words = paragraph.split_whitespace().collect::<Vec<_>>();
+    let mut sentences = Vec::new();
+    let mut start = 0;
+    for (index, pair) in words.windows(2).enumerate() {
+        if ends_sentence(pair[0], pair[1]) {
+            sentences.push(words[start..=index].join(" "));
+            start = index + 1;
+        }
+    }
+    if start < words.len() {
+        sentences.push(words[start..].join(" "));
+    }
+    sentences
+}
+
+/// Headings, lists and quotes are left as they are by `paragraphs`.
+fn is_structured(block: &str) -> bool {
+    let block = block.trim_start();
+    block.contains('\n')
+        || ["#", "- ", "* ", "> "]
+            .iter()
+            .any(|prefix| block.starts_with(prefix))
+}
+
+/// `text` laid out as paragraphs of up to `sentences_per_paragraph`
+/// sentences each, splitting the ones that are longer. With 0, it's left as
+/// it is.
+pub fn paragraphs(text: &str, sentences_per_paragraph: usize) -> String {
+    if sentences_per_paragraph == 0 {
+        return text.to_string();
+    }
+    text.split("\n\n")
+        .map(str::trim)
+        .filter(|block| !block.is_empty())
+        .flat_map(|block| {
+            if is_structured(block) {
+                return vec![block.to_string()];
+            }
+            sentences(block)
+                .chunks(sentences_per_paragraph)
+                .map(|sentences| sentences.join(" "))
+                .collect()
+        })
+        .collect::<Vec<_>>()
+        .join("\n\n")
+}
+
+/// Split `s` into pieces of at most `max_characters` characters.
+fn split_at_chars(s: &str, max_characters: usize) -> Vec<&str> {
+    let mut pieces = Vec::new();
+    let mut rest = s;
+    while let Some((end, _)) = rest.char_indices().nth(max_characters) {
+        let (piece, remainder) = rest.split_at(end);
+        pieces.push(piece);
+        rest = remainder;
+    }
+    pieces.push(rest);
+    pieces
+}
+
+/// A line's words, each with its trailing space, except that a `**bold**` or
+/// `*italic*` run is kept together as one.
+fn markdown_words(line: &str) -> Vec<&str> {
+    let mut words = Vec::new();
+    let (mut start, mut end) = (0, 0);
+    let (mut bold, mut italic) = (false, false);
+    for word in line.split_inclusive(' ') {
+        end += word.len();
+        bold ^= word.matches("**").count() % 2 == 1;
+        italic ^= word.replace("**", "").matches('*').count() % 2 == 1;
+        if !bold && !italic {
+            words.push(&line[start..end]);
+            start = end;
+        }
+    }
+    if start < line.len() {
+        words.push(&line[start..]);
+    }
+    words
+}
+
+/// Room kept in a chunk for closing a code block and reopening it in the next
+/// one.
+const FENCE_RESERVE: usize = 32;
+
+/// What a chunk is packed from: lines, the breaks between them, and for lines
+/// too long to fit in a chunk, their words. Prose is double-spaced, while
+/// code blocks keep their single line breaks and indentation. Code fences
+/// are flagged so chunks can track whether they're in a code block.
+fn chunk_pieces(s: &str, max_characters: usize) -> Vec<(String, bool)> {
+    let mut pieces = Vec::new();
+    let mut in_code = false;
+    for (index, line) in s.split('\n').enumerate() {
+        if index > 0 {
+            let line_break = if in_code { "\n" } else { "\n\n" };
+            pieces.push((line_break.to_string(), false));
+        }
+        if line.trim_start().starts_with("```") {
+            in_code = !in_code;
+            pieces.push((line.trim().to_string(), true));
+        } else if in_code {
+            let max_characters = max_characters.saturating_sub(FENCE_RESERVE).max(1);
+            pieces.extend(
+                split_at_chars(line.trim_end(), max_characters)
+                    .into_iter()
+                    .map(|piece| (piece.to_string(), false)),
+            );
+        } else if line.trim().chars().count() <= max_characters {
+            pieces.push((line.trim().to_string(), false));
+        } else {
+            // Anything too long to fit in a chunk on its own, like a long
+            // URL, is cut up.
+            for words in markdown_words(line.trim()) {
+                let words = if words.chars().count() <= max_characters {
+                    vec![words]
+                } else {
+                    words.split_inclusive(' ').collect()
+                };
+                for word in words {
+                    pieces.extend(
+                        split_at_chars(word, max_characters)
+                            .into_iter()
+                            .map(|piece| (piece.to_string(), false)),
+                    );
+                }
+            }
+        }
+    }
+    pieces
+}
+
+/// Split `s` into chunks of at most `max_characters_per_chunk` characters,
+/// at line breaks where possible, then between words. Chunks don't end
+/// inside bold or italic text or with a heading, and a code block split over
+/// several chunks is closed at the end of each and reopened at the start of
+/// the next.
 pub fn break_text_into_chunks(s: String, max_characters_per_chunk: usize) -> Vec<String> {
     let mut chunks = Vec::new();
     let mut current_chunk = String::new();
+    // The opening fence of the code block the current chunk ends in, if any.
+    let mut fence: Option<String> = None;
+    // Whether the current chunk has anything in it but a reopened fence.
+    let mut started = false;
+    // Where the heading the current chunk ends with starts, if it does.
+    let mut heading: Option<usize> = None;
 
-    let paragraphs = s
-        .split('\n')
-        .map(|paragraph| paragraph.trim())
-        .intersperse("\n\n")
-        .flat_map(|paragraph| {
-            if paragraph.chars().count() <= max_characters_per_chunk {
-                vec![paragraph]
-            } else {
-                paragraph.split(' ').collect::<Vec<_>>()
-            }
-        })
-        .collect::<Vec<_>>();
+    for (piece, is_fence) in chunk_pieces(&s, max_characters_per_chunk) {
+        let fence_after = match (is_fence, &fence) {
+            (true, Some(_)) => None,
+            (true, None) => Some(piece.clone()),
+            (false, fence) => fence.clone(),
+        };
+        let closing = if fence_after.is_some() {
+            "\n```".len()
+        } else {
+            0
+        };
 
-    for paragraph in paragraphs {
-        // If we can't add the current paragraph to the current chunk, push the current chunk and start a new one
-        if !current_chunk.is_empty()
-            && current_chunk.chars().count() + paragraph.chars().count() > max_characters_per_chunk
+        // If we can't add the current piece to the current chunk, push the current chunk and start a new one
+        if started
+            && current_chunk.chars().count() + piece.chars().count() + closing
+                > max_characters_per_chunk
         {
+            if fence.is_some() {
+                current_chunk.truncate(current_chunk.trim_end().len());
+                current_chunk.push_str("\n```");
+            }
+            // A heading goes with the section under it, if they fit.
+            let carried = match heading {
+                Some(start)
+                    if start > 0
+                        && current_chunk[start..].chars().count() + piece.chars().count()
+                            <= max_characters_per_chunk =>
+                {
+                    current_chunk.split_off(start)
+                }
+                _ => String::new(),
+            };
             chunks.push(current_chunk.trim().to_string());
-            current_chunk = String::new();
+            started = !carried.is_empty();
+            heading = None;
+            current_chunk = match &fence {
+                Some(fence) => format!("{fence}\n"),
+                None => carried,
+            };
+        }
+        // A line break has nothing to separate at the start of a chunk.
+        if !started && piece.trim().is_empty() {
+            continue;
         }
 
-        // If we can add the current paragraph to the current chunk, do so
-        current_chunk.push_str(paragraph);
+        if !piece.trim().is_empty() {
+            let is_heading =
+                piece.starts_with('#') && piece.trim_start_matches('#').starts_with(' ');
+            heading = (is_heading && !is_fence && fence.is_none()).then_some(current_chunk.len());
+        }
+        current_chunk.push_str(&piece);
+        fence = fence_after;
+        started = true;
     }
     chunks.push(current_chunk);
 
@@ -40,3 +324,183 @@ pub fn break_text_into_chunks(s: String, max_characters_per_chunk: usize) -> Vec
 
     chunks
 }
+
+#[test]
+fn test_break_text_into_chunks_long_word() {
+    let word = "a".repeat(5000);
+    let chunks = break_text_into_chunks(word.clone(), 4096);
+    assert!(chunks.iter().all(|chunk| chunk.chars().count() <= 4096));
+    assert_eq!(chunks.concat(), word);
+
+    let chunks = break_text_into_chunks(format!("see {word} here"), 4096);
+    assert!(chunks.iter().all(|chunk| chunk.chars().count() <= 4096));
+    assert_eq!(chunks.concat().replace(' ', ""), format!("see{word}here"));
+
+    // Multi-byte characters are split between characters, not bytes.
+    let chunks = break_text_into_chunks("é".repeat(10), 4);
+    assert_eq!(chunks, vec!["éééé", "éééé", "éé"]);
+}
+
+#[test]
+fn test_break_text_into_chunks_bold() {
+    let text = format!("{}**a bold phrase here** and the rest", "word ".repeat(15));
+    let chunks = break_text_into_chunks(text, 90);
+    assert!(chunks.len() > 1);
+    for chunk in &chunks {
+        assert!(chunk.chars().count() <= 90);
+        assert_eq!(chunk.matches("**").count() % 2, 0, "{chunk:?}");
+    }
+    assert!(chunks
+        .iter()
+        .any(|chunk| chunk.contains("**a bold phrase here**")));
+}
+
+#[test]
+fn test_break_text_into_chunks_code_block() {
+    let code = (0..30)
+        .map(|i| format!("    let x{i} = {i};"))
+        .collect::<Vec<_>>()
+        .join("\n");
+    let text = format!("Some code:\n```rust\n{code}\n```\nThe end.");
+    let chunks = break_text_into_chunks(text, 120);
+    assert!(chunks.len() > 2);
+    for chunk in &chunks {
+        assert!(chunk.chars().count() <= 120, "{chunk:?}");
+        let fences = chunk.lines().filter(|line| line.starts_with("```")).count();
+        assert_eq!(fences % 2, 0, "{chunk:?}");
+    }
+    // Indentation and single line breaks survive inside the block.
+    assert!(chunks[1].starts_with("```rust\n    let x"));
+    assert!(!chunks[1].contains("\n\n"));
+    assert!(chunks.last().unwrap().ends_with("The end."));
+}
+
+#[test]
+fn test_break_text_into_chunks_heading() {
+    let text = "## Summary\nSome text here.\n## Key Takeaways\n- First point here";
+    assert_eq!(
+        break_text_into_chunks(text.to_string(), 50),
+        vec![
+            "## Summary\n\nSome text here.",
+            "## Key Takeaways\n\n- First point here"
+        ]
+    );
+    // Unless the heading and what's after it don't fit in a chunk together.
+    assert_eq!(
+        break_text_into_chunks(text.to_string(), 30),
+        vec![
+            "## Summary\n\nSome text here.",
+            "## Key Takeaways",
+            "- First point here"
+        ]
+    );
+}
+
+#[test]
+fn test_thousands() {
+    assert_eq!(thousands(0), "0");
+    assert_eq!(thousands(999), "999");
+    assert_eq!(thousands(1000), "1,000");
+    assert_eq!(thousands(12400), "12,400");
+    assert_eq!(thousands(1234567), "1,234,567");
+}
+
+#[test]
+fn test_file_stem() {
+    assert_eq!(file_stem("Rust in 100 Seconds"), "Rust-in-100-Seconds");
+    assert_eq!(file_stem("AC/DC - Live 🎸 (2023)"), "AC-DC-Live-2023");
+    assert_eq!(file_stem("🎸🎸"), "output");
+    assert_eq!(file_stem(&"a".repeat(300)).len(), 100);
+}
+
+#[test]
+fn test_collapse_repeats() {
+    assert_eq!(
+        collapse_repeats("we're going to going to the store"),
+        "we're going to the store"
+    );
+    assert_eq!(
+        collapse_repeats("and then we  went\nand then we went home"),
+        "and then we went home"
+    );
+    // Case doesn't stop a repeat being spotted, but punctuation does, so
+    // it's never moved.
+    assert_eq!(
+        collapse_repeats("So what I mean so what I mean is"),
+        "So what I mean is"
+    );
+    assert_eq!(
+        collapse_repeats("So what I mean. so what I mean is"),
+        "So what I mean. so what I mean is"
+    );
+    assert_eq!(
+        collapse_repeats("it was very very good, no no"),
+        "it was very very good, no no"
+    );
+    assert_eq!(collapse_repeats("  one two   three "), "one two three");
+
+    let overlapping = "so the next thing the next thing we need to do is \
+        we need to do is check check the the oil";
+    let collapsed = collapse_repeats(overlapping);
+    assert_eq!(
+        collapsed,
+        "so the next thing we need to do is check check the the oil"
+    );
+    let model = "gpt-3.5-turbo";
+    assert!(
+        crate::openai::count_text_tokens(model, &collapsed)
+            < crate::openai::count_text_tokens(model, overlapping)
+    );
+}
+
+#[test]
+fn test_collapse_repeats_across() {
+    assert_eq!(
+        collapse_repeats_across(&["we're going to", "going to the", "the store"]),
+        vec!["we're going to", "the", "the store"]
+    );
+    assert_eq!(
+        collapse_repeats_across(&["and then we", "and then we", "went home"]),
+        vec!["and then we", "", "went home"]
+    );
+}
+
+#[test]
+fn test_sentences() {
+    assert_eq!(
+        sentences("Dr. Smith met the U.S. government. It went well!  Did it? Yes."),
+        vec![
+            "Dr. Smith met the U.S. government.",
+            "It went well!",
+            "Did it?",
+            "Yes."
+        ]
+    );
+    // Decimals, initials and lowercase continuations don't end sentences.
+    assert_eq!(
+        sentences("Version 3.5 is out. J. R. R. Tolkien wrote it, e.g. this one. etc. and more"),
+        vec![
+            "Version 3.5 is out.",
+            "J. R. R. Tolkien wrote it, e.g. this one. etc. and more"
+        ]
+    );
+    assert_eq!(
+        sentences("He said \"stop.\" Then **it ended.** The end"),
+        vec!["He said \"stop.\"", "Then **it ended.**", "The end"]
+    );
+}
+
+#[test]
+fn test_paragraphs() {
+    let text = "One. Two. Three. Four.\n\n## Heading\n\nFive. Mr. Six.";
+    assert_eq!(
+        paragraphs(text, 2),
+        "One. Two.\n\nThree. Four.\n\n## Heading\n\nFive. Mr. Six."
+    );
+    assert_eq!(
+        paragraphs(text, 1),
+        "One.\n\nTwo.\n\nThree.\n\nFour.\n\n## Heading\n\nFive.\n\nMr. Six."
+    );
+    assert_eq!(paragraphs(text, 0), text);
+    assert_eq!(paragraphs("- a. B.\n- c. D.", 1), "- a. B.\n- c. D.");
+}
@@ -0,0 +1,88 @@
+use std::time::Duration;
+
+use hmac::{Hmac, Mac};
+use serde::Serialize;
+use sha2::Sha256;
+use tracing::warn;
+
+use crate::youtube::{self, Action};
+
+const ATTEMPTS: u32 = 3;
+
+#[derive(Clone)]
+pub struct WebhookConfig {
+    pub url: String,
+    /// When set, the body is signed with HMAC-SHA256 and the hex digest is
+    /// sent as `X-Leonidas-Signature: sha256=<digest>`.
+    pub secret: Option<String>,
+    /// Skip posting to Discord and only deliver to the webhook.
+    pub only: bool,
+}
+
+#[derive(Serialize)]
+pub struct WebhookPayload {
+    pub video_id: String,
+    pub title: String,
+    pub channel: String,
+    pub action: Action,
+    pub summary: String,
+    pub tokens: usize,
+    pub requester: u64,
+}
+
+fn sign(secret: &str, body: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).unwrap();
+    mac.update(body);
+    hex::encode(mac.finalize().into_bytes())
+}
+
+async fn post(config: &WebhookConfig, body: &[u8]) -> Result<(), String> {
+    let client = youtube::client();
+    let mut request = client
+        .http
+        .post(&config.url)
+        .timeout(client.timeout)
+        .header("Content-Type", "application/json")
+        .body(body.to_vec());
+    if let Some(secret) = &config.secret {
+        request = request.header(
+            "X-Leonidas-Signature",
+            format!("sha256={}", sign(secret, body)),
+        );
+    }
+    let response = request.send().await.map_err(|e| e.to_string())?;
+    if response.status().is_success() {
+        Ok(())
+    } else {
+        Err(format!("webhook responded with {}", response.status()))
+    }
+}
+
+/// Deliver the payload in the background so a slow or failing webhook never
+/// holds up the Discord response.
+pub fn dispatch(config: &WebhookConfig, payload: WebhookPayload) {
+    let config = config.clone();
+    tokio::spawn(async move {
+        let body = serde_json::to_vec(&payload).unwrap();
+        for attempt in 1..=ATTEMPTS {
+            match post(&config, &body).await {
+                Ok(()) => return,
+                Err(why) => {
+                    warn!("Error delivering webhook (attempt {attempt}/{ATTEMPTS}): {why}");
+                    if attempt < ATTEMPTS {
+                        tokio::time::sleep(Duration::from_secs(2u64.pow(attempt))).await;
+                    }
+                }
+            }
+        }
+    });
+}
+
+#[test]
+fn test_sign() {
+    // HMAC-SHA256 test vector from RFC 4231, test case 2.
+    assert_eq!(
+        sign("Jefe", b"what do ya want for nothing?"),
+        "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+    );
+}
@@ -1,42 +1,288 @@
-use std::{env, time::Duration};
+use std::{
+    env,
+    sync::{Arc, OnceLock},
+    time::{Duration, Instant},
+};
 
+use chrono::{DateTime, Utc};
+use futures::stream::{self, StreamExt};
+use reqwest::header::{HeaderMap, RETRY_AFTER};
 use serde::{Deserialize, Serialize};
+use serenity::async_trait;
+use tokio::sync::{watch, Semaphore};
+use tracing::{debug, error, warn};
 
+use crate::cache::{Cache, CacheKey, ChunkKey};
+use crate::chapters::{self, Chapter};
+use crate::metrics::{self, METRICS};
 use crate::openai;
+use crate::options::{Portion, SummaryOptions, SummaryStyle};
 use crate::prompts;
+use crate::sources::TranscriptSource;
+use crate::utils;
 
-fn youtube_token() -> Option<String> {
-    for (key, value) in env::vars() {
-        if key == "YOUTUBE_API_TOKEN" {
-            return Some(value);
+#[derive(Debug)]
+pub enum Error {
+    TranscriptTooShort {
+        words: usize,
+        min_words: usize,
+    },
+    TranscriptTooLong {
+        tokens: u64,
+    },
+    /// Nothing but `[Music]` and the like in the transcript.
+    NoSpeech,
+    /// Captions in the requested language don't exist.
+    NoTranscriptIn(String),
+    /// The transcript API said it succeeded, but sent an error message or
+    /// nothing at all in place of a transcript.
+    TranscriptUnavailable,
+    VideoNotFound,
+    /// The named API token isn't set.
+    MissingToken(&'static str),
+    /// The OpenAI API answered with an error status.
+    ApiStatus {
+        status: u16,
+        body: String,
+        /// How long a 429 said to wait before trying again.
+        retry_after: Option<Duration>,
+    },
+    /// A 429 asked us to wait this long, more than `RetryPolicy::max_wait`.
+    RateLimited(Duration),
+    TimedOut(Duration),
+    /// Processing the video panicked, with the panic's message.
+    Panicked(String),
+    /// Why each of several transcript endpoints failed, in the order they
+    /// were tried.
+    TranscriptSources(Vec<Error>),
+    Http(reqwest::Error),
+    /// The transcript or OpenAI API returned an error, or something we
+    /// couldn't understand.
+    Api(String),
+    /// The OpenAI API said it succeeded, but sent something other than a
+    /// reply. Asking again is unlikely to help.
+    InvalidResponse(String),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Error::TranscriptTooShort { words, min_words } => write!(
+                f,
+                "The transcript is too short to summarize ({words} words, but it takes more than {min_words})."
+            ),
+            Error::TranscriptTooLong { tokens } => {
+                write!(f, "The transcript is too long ({tokens} tokens).")
+            }
+            Error::NoSpeech => write!(
+                f,
+                "This video appears to have no spoken content (music/instrumental), so there's nothing to summarize."
+            ),
+            Error::NoTranscriptIn(language) => {
+                write!(f, "This video has no transcript in `{language}`.")
+            }
+            Error::TranscriptUnavailable => {
+                write!(f, "There's no transcript available for this video.")
+            }
+            Error::VideoNotFound => {
+                write!(f, "Couldn't find that video. It may be private or deleted.")
+            }
+            Error::MissingToken(name) => write!(f, "The bot isn't configured with `{name}`."),
+            // The body is logged where the error is made, not shown.
+            Error::ApiStatus { status: 429, .. } => {
+                write!(f, "OpenAI is rate-limiting us. Try again in a little while.")
+            }
+            Error::ApiStatus {
+                status: 401 | 403, ..
+            } => write!(f, "OpenAI rejected the bot's API key."),
+            Error::ApiStatus { status, .. } if *status >= 500 => {
+                write!(f, "OpenAI is having trouble (status {status}). Try again later.")
+            }
+            Error::ApiStatus { status, .. } => write!(f, "The OpenAI API returned {status}."),
+            Error::RateLimited(retry_after) => write!(
+                f,
+                "OpenAI is rate-limiting us for another {} minutes, so this was given up on. Try again after that.",
+                retry_after.as_secs().div_ceil(60)
+            ),
+            Error::TimedOut(timeout) => {
+                write!(f, "Timed out after {} seconds.", timeout.as_secs())
+            }
+            Error::Panicked(message) => write!(f, "Something went wrong on our end ({message})."),
+            Error::TranscriptSources(failures) => {
+                write!(f, "Couldn't get the transcript from any source:")?;
+                for (index, why) in failures.iter().enumerate() {
+                    write!(f, "\n- Source {}: {why}", index + 1)?;
+                }
+                Ok(())
+            }
+            Error::Http(_) => write!(f, "Couldn't reach an API we depend on."),
+            Error::Api(message) | Error::InvalidResponse(message) => write!(f, "{message}"),
         }
     }
-    None
 }
 
-fn openai_token() -> Option<String> {
+impl Error {
+    /// Whether the same request might work if tried again: network trouble,
+    /// rate limits and server errors, but not e.g. a rejected API key.
+    fn is_retryable(&self) -> bool {
+        match self {
+            Error::Http(_) => true,
+            Error::ApiStatus { status, .. } => *status == 429 || *status >= 500,
+            _ => false,
+        }
+    }
+}
+
+/// `Retry-After` is either a number of seconds or an HTTP date.
+fn parse_retry_after(value: &str) -> Option<Duration> {
+    if let Ok(seconds) = value.trim().parse::<f64>() {
+        return (seconds.is_finite() && seconds >= 0.0).then(|| Duration::from_secs_f64(seconds));
+    }
+    let at = DateTime::parse_from_rfc2822(value.trim()).ok()?;
+    Some(
+        (at.with_timezone(&Utc) - Utc::now())
+            .to_std()
+            .unwrap_or_default(),
+    )
+}
+
+/// An `ApiStatus` for a response that wasn't a success.
+fn status_error(status: reqwest::StatusCode, headers: &HeaderMap, body: String) -> Error {
+    let retry_after = headers
+        .get(RETRY_AFTER)
+        .filter(|_| status == reqwest::StatusCode::TOO_MANY_REQUESTS)
+        .and_then(|value| value.to_str().ok())
+        .and_then(parse_retry_after);
+    error!("OpenAI API returned {status}: {body}");
+    Error::ApiStatus {
+        status: status.as_u16(),
+        body,
+        retry_after,
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Http(why) => Some(why),
+            _ => None,
+        }
+    }
+}
+
+impl From<reqwest::Error> for Error {
+    /// The URL is dropped, since YouTube's has the API key in it.
+    fn from(why: reqwest::Error) -> Error {
+        let why = why.without_url();
+        error!("HTTP request failed: {why:?}");
+        Error::Http(why)
+    }
+}
+
+fn env_token(name: &str) -> Option<String> {
     for (key, value) in env::vars() {
-        if key == "OPENAI_API_TOKEN" {
+        if key == name {
             return Some(value);
         }
     }
     None
 }
 
+pub struct YouTube;
+
+#[async_trait]
+impl TranscriptSource for YouTube {
+    fn name(&self) -> &'static str {
+        "YouTube"
+    }
+
+    fn matches(&self, url: &str) -> Option<String> {
+        video_id(url)
+    }
+
+    fn video_url(&self, id: &str) -> String {
+        video_url(id)
+    }
+
+    fn thumbnail_url(&self, id: &str) -> String {
+        thumbnail_url(id)
+    }
+
+    async fn fetch_transcript(&self, id: &str) -> Result<String, Error> {
+        Ok(paragraphs(&client().transcript(id, None).await?))
+    }
+
+    async fn video_info(&self, id: &str) -> Result<VideoInfo, Error> {
+        client().video_info(id).await
+    }
+
+    async fn summary(
+        &self,
+        id: &str,
+        options: &SummaryOptions,
+        cache: &Cache,
+        describe_no_speech: bool,
+        model: &str,
+        progress: Option<watch::Sender<String>>,
+    ) -> Result<((String, VideoInfo), openai::Usage), Error> {
+        get_video_summary(id, options, cache, describe_no_speech, model, progress).await
+    }
+
+    async fn transcript(
+        &self,
+        id: &str,
+        cache: &Cache,
+        options: &TranscriptOptions,
+    ) -> Result<((String, VideoInfo), openai::Usage), Error> {
+        get_video_transcript(id, cache, options).await
+    }
+
+    async fn estimate(
+        &self,
+        id: &str,
+        options: &SummaryOptions,
+        model: &str,
+    ) -> Result<Estimate, Error> {
+        estimate(id, options, model).await
+    }
+
+    async fn answer_question(
+        &self,
+        id: &str,
+        question: String,
+        cache: &Cache,
+        model: &str,
+    ) -> Result<(String, openai::Usage), Error> {
+        answer_question(id, question, cache, model).await
+    }
+}
+
+/// Video ids are always 11 characters, so whatever follows them (`&list=`,
+/// `?t=30`, ...) is never part of the id.
 pub fn video_id(url: &str) -> Option<String> {
-    regex::Regex::new(r"(?:https://(?:(?:www|m)\.)?youtube\.com/watch\?v=|https://youtu\.be/|https://(?:www|m).youtube.com/shorts/)(?P<id>[a-zA-Z0-9_-]+).*")
-                    .unwrap()
-                    .captures(url)
-                    .and_then(|captures| captures.name("id"))
-                    .map(|id| id.as_str().to_string())
+    regex::Regex::new(
+        r"^https?://(?:(?:(?:www|m|music)\.)?youtube\.com/(?:watch\?(?:[^#]*&)?v=|shorts/|embed/|live/)|youtu\.be/)(?P<id>[a-zA-Z0-9_-]{11})(?:[^a-zA-Z0-9_-]|$)",
+    )
+    .unwrap()
+    .captures(url)
+    .and_then(|captures| captures.name("id"))
+    .map(|id| id.as_str().to_string())
 }
 
-#[derive(Serialize, Deserialize)]
+pub fn playlist_id(url: &str) -> Option<String> {
+    regex::Regex::new(
+        r"https://(?:(?:www|m)\.)?youtube\.com/playlist\?(?:.*&)?list=(?P<id>[a-zA-Z0-9_-]+)",
+    )
+    .unwrap()
+    .captures(url)
+    .and_then(|captures| captures.name("id"))
+    .map(|id| id.as_str().to_string())
+}
+
+#[derive(Clone, Serialize, Deserialize)]
 struct TranscriptItem {
     text: String,
-    #[allow(dead_code)]
     start: f64,
-    #[allow(dead_code)]
     duration: f64,
 }
 
@@ -47,16 +293,21 @@ enum TranscriptResponse {
     Error { message: String },
 }
 
+/// Shorts and finished live streams sometimes come back with fields missing
+/// or null, so none of them are required.
 #[derive(Serialize, Deserialize)]
 struct Snippet {
-    title: String,
+    title: Option<String>,
     #[serde(rename = "channelTitle")]
-    channel_title: String,
+    channel_title: Option<String>,
+    #[serde(rename = "publishedAt")]
+    published_at: Option<String>,
+    description: Option<String>,
 }
 
 #[derive(Serialize, Deserialize)]
 struct Item {
-    snippet: Snippet,
+    snippet: Option<Snippet>,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -64,149 +315,2224 @@ struct VideoResponse {
     items: Vec<Item>,
 }
 
-#[derive(Debug)]
+#[derive(Deserialize)]
+struct PlaylistContentDetails {
+    #[serde(rename = "videoId")]
+    video_id: String,
+}
+
+#[derive(Deserialize)]
+struct PlaylistItem {
+    #[serde(rename = "contentDetails")]
+    content_details: PlaylistContentDetails,
+}
+
+#[derive(Deserialize)]
+struct PlaylistItemsResponse {
+    items: Vec<PlaylistItem>,
+    #[serde(rename = "nextPageToken")]
+    next_page_token: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Action {
+    Summarize,
+    Transcribe,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct VideoInfo {
     pub title: String,
     pub channel_name: String,
+    pub published_at: Option<DateTime<Utc>>,
+    pub description: String,
 }
 
-async fn get_transcript(video_id: &str) -> Result<String, String> {
-    let url = format!(
-        "https://zl319yz4a6.execute-api.us-east-1.amazonaws.com/Prod/youtube/transcript/{}",
-        video_id
-    );
-    let response = reqwest::get(&url).await.map_err(|e| e.to_string())?;
-    let data: TranscriptResponse = response.json().await.map_err(|e| e.to_string())?;
+/// The short link to a video, e.g. `https://youtu.be/dQw4w9WgXcQ`.
+pub fn video_url(video_id: &str) -> String {
+    format!("https://youtu.be/{video_id}")
+}
+
+pub fn thumbnail_url(video_id: &str) -> String {
+    format!("https://i.ytimg.com/vi/{video_id}/hqdefault.jpg")
+}
+
+/// YouTube reports `publishedAt` as an RFC 3339 timestamp,
+/// e.g. `2023-10-18T15:00:07Z`.
+fn parse_published_at(published_at: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(published_at)
+        .ok()
+        .map(|published_at| published_at.with_timezone(&Utc))
+}
+
+/// What the transcript API has been seen to send as the whole transcript
+/// when there isn't one, compared in lowercase.
+const BOILERPLATE: &[&str] = &[
+    "no transcript available",
+    "transcript is not available",
+    "transcripts are disabled",
+    "transcript is disabled",
+    "subtitles are disabled",
+    "could not retrieve a transcript",
+    "no captions available",
+    "video unavailable",
+];
+
+/// Real transcripts with one of `BOILERPLATE` in them are longer than this.
+const MAX_BOILERPLATE_ITEMS: usize = 3;
+
+/// Whether a "successful" transcript is really an error: a few captions
+/// saying there's no transcript, or captions with no text at all.
+fn is_boilerplate(items: &[TranscriptItem]) -> bool {
+    if items.is_empty() {
+        return false;
+    }
+    if items.iter().all(|item| item.text.trim().is_empty()) {
+        return true;
+    }
+    let text = items
+        .iter()
+        .map(|item| item.text.to_lowercase())
+        .collect::<Vec<_>>()
+        .join(" ");
+    items.len() <= MAX_BOILERPLATE_ITEMS && BOILERPLATE.iter().any(|pattern| text.contains(pattern))
+}
+
+/// Caption lines that aren't speech, like `[Music]`, `[Applause]` or `♪♪`.
+fn is_non_speech(text: &str) -> bool {
+    let mut depth = 0;
+    let mut speech = false;
+    for c in text.chars() {
+        match c {
+            '[' | '(' => depth += 1,
+            ']' | ')' => depth = (depth - 1).max(0),
+            '♪' | '♫' | '♬' => {}
+            c if depth == 0 && !c.is_whitespace() => speech = true,
+            _ => {}
+        }
+    }
+    !speech
+}
+
+fn filter_non_speech(items: Vec<TranscriptItem>) -> Vec<TranscriptItem> {
+    items
+        .into_iter()
+        .filter(|item| !is_non_speech(&item.text))
+        .collect()
+}
 
-    match data {
-        TranscriptResponse::Success { transcript } => Ok(transcript
+/// The items' text as one string, without the words repeated where one
+/// caption overlaps the next.
+fn join_transcript(items: &[TranscriptItem]) -> String {
+    utils::collapse_repeats(
+        &items
             .iter()
             .map(|item| item.text.clone())
             .collect::<Vec<String>>()
-            .join(" ")),
-        TranscriptResponse::Error { message } => {
-            eprintln!("Error fetching transcript: {}", message);
-            Err(message)
+            .join(" "),
+    )
+}
+
+/// The items without the words repeated where one caption overlaps the
+/// next, dropping any left with no words.
+fn collapse_items(items: &[TranscriptItem]) -> Vec<TranscriptItem> {
+    let texts = items
+        .iter()
+        .map(|item| item.text.as_str())
+        .collect::<Vec<_>>();
+    items
+        .iter()
+        .zip(utils::collapse_repeats_across(&texts))
+        .filter(|(_, text)| !text.is_empty())
+        .map(|(item, text)| TranscriptItem {
+            text,
+            ..item.clone()
+        })
+        .collect()
+}
+
+/// A pause this long before a caption starts a new paragraph.
+const PARAGRAPH_PAUSE: f64 = 2.0;
+/// Paragraphs are broken at the next caption past this many words, pause or
+/// not, since auto-generated captions rarely have punctuation to go by.
+const MAX_PARAGRAPH_WORDS: usize = 120;
+
+/// The items' text, broken into paragraphs where the speaker pauses, for
+/// reading without a cleanup.
+fn paragraphs(items: &[TranscriptItem]) -> String {
+    let mut paragraphs: Vec<&[TranscriptItem]> = Vec::new();
+    let mut start = 0;
+    let mut words = 0;
+    for (index, item) in items.iter().enumerate() {
+        if index > start {
+            let previous = &items[index - 1];
+            let pause = item.start - (previous.start + previous.duration);
+            if pause >= PARAGRAPH_PAUSE || words >= MAX_PARAGRAPH_WORDS {
+                paragraphs.push(&items[start..index]);
+                start = index;
+                words = 0;
+            }
         }
+        words += item.text.split_whitespace().count();
     }
+    paragraphs.push(&items[start..]);
+    paragraphs
+        .into_iter()
+        .map(join_transcript)
+        .filter(|paragraph| !paragraph.is_empty())
+        .collect::<Vec<_>>()
+        .join("\n\n")
 }
 
-async fn get_video_info(video_id: &str) -> Result<VideoInfo, reqwest::Error> {
-    let url = format!(
-        "https://www.googleapis.com/youtube/v3/videos?id={}&key={}&part=snippet",
-        video_id,
-        youtube_token().unwrap()
-    );
-    let response = reqwest::get(&url).await?;
-    let video_response: VideoResponse = response.json().await?;
-    let item = &video_response.items[0];
+/// Keep the items that start within `portion` of the video's runtime.
+fn slice_transcript(items: Vec<TranscriptItem>, portion: Portion) -> Vec<TranscriptItem> {
+    let runtime = items
+        .iter()
+        .map(|item| item.start + item.duration)
+        .fold(0.0, f64::max);
+    let start = runtime * portion.start_fraction();
+    let end = runtime * portion.end_fraction();
+    items
+        .into_iter()
+        .filter(|item| {
+            item.start >= start && (item.start < end || portion.end == Portion::WHOLE.end)
+        })
+        .collect()
+}
+
+/// Each chapter's title and the transcript of it, leaving out chapters with
+/// nothing said in them.
+fn chapter_transcripts(items: &[TranscriptItem], chapters: &[Chapter]) -> Vec<(String, String)> {
+    chapters
+        .iter()
+        .enumerate()
+        .filter_map(|(index, chapter)| {
+            let end = chapters
+                .get(index + 1)
+                .map_or(f64::INFINITY, |next| next.start as f64);
+            let chapter_items = items
+                .iter()
+                .filter(|item| item.start >= chapter.start as f64 && item.start < end)
+                .cloned()
+                .collect::<Vec<_>>();
+            let transcript = join_transcript(&chapter_items);
+            (!transcript.trim().is_empty()).then(|| (chapter.title.clone(), transcript))
+        })
+        .collect()
+}
+
+/// Best-effort guess at the transcript's language. Returns `None` when the
+/// detector isn't confident, e.g. for very short transcripts.
+fn detect_language(transcript: &str) -> Option<whatlang::Lang> {
+    // The start of the transcript is plenty to go on.
+    let sample: String = transcript.chars().take(2000).collect();
+    whatlang::detect(&sample)
+        .filter(|info| info.is_reliable())
+        .map(|info| info.lang())
+}
+
+/// Stands in for a title or channel name the API didn't return.
+const UNKNOWN: &str = "Unknown";
+
+/// Deleted, private and region-blocked videos come back with no items.
+fn video_info(video_response: VideoResponse) -> Result<VideoInfo, Error> {
+    let item = video_response.items.first().ok_or(Error::VideoNotFound)?;
+    let snippet = item.snippet.as_ref();
+    let text = |field: Option<&String>| {
+        field
+            .map(|text| text.trim())
+            .filter(|text| !text.is_empty())
+            .unwrap_or(UNKNOWN)
+            .to_string()
+    };
     Ok(VideoInfo {
-        title: item.snippet.title.clone(),
-        channel_name: item.snippet.channel_title.clone(),
+        title: text(snippet.and_then(|snippet| snippet.title.as_ref())),
+        channel_name: text(snippet.and_then(|snippet| snippet.channel_title.as_ref())),
+        published_at: snippet
+            .and_then(|snippet| snippet.published_at.as_deref())
+            .and_then(parse_published_at),
+        description: snippet
+            .and_then(|snippet| snippet.description.clone())
+            .unwrap_or_default(),
     })
 }
 
-async fn chat(chat_api_request: openai::ChatApiRequest) -> Result<String, String> {
-    async fn chat_once(chat_api_request: openai::ChatApiRequest) -> Result<String, String> {
-        let client = reqwest::Client::new();
-        let api_response = client
-            .post("https://zl319yz4a6.execute-api.us-east-1.amazonaws.com/Prod/v1/chat/completions")
-            .header("Content-Type", "application/json")
-            .header(
-                "Authorization",
-                format!("Bearer {}", openai_token().unwrap()),
-            )
-            .json(&chat_api_request)
+/// How failed OpenAI requests are retried.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Tries in total, including the first.
+    pub attempts: u32,
+    /// How long to wait before the first retry. Each one after waits twice
+    /// as long as the last.
+    pub base_delay: Duration,
+    /// The longest a rate limit's `Retry-After` is waited out. Past this the
+    /// request fails instead, rather than holding the job up.
+    pub max_wait: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> RetryPolicy {
+        RetryPolicy {
+            attempts: 4,
+            base_delay: Duration::from_secs(2),
+            max_wait: Duration::from_secs(60),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// The wait after the `attempt`th try fails, plus up to half again at
+    /// random so jobs that failed together don't all retry together.
+    fn delay(&self, attempt: u32) -> Duration {
+        let delay = self
+            .base_delay
+            .saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)));
+        let nanos = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .subsec_nanos();
+        delay + delay.mul_f64(f64::from(nanos % 1000) / 2000.0)
+    }
+
+    /// The wait after the `attempt`th try fails with `why`: exactly as long
+    /// as a rate limit asked for, up to `max_wait`, and otherwise `delay`.
+    fn delay_for(&self, why: &Error, attempt: u32) -> Result<Duration, Error> {
+        match why {
+            Error::ApiStatus {
+                retry_after: Some(retry_after),
+                ..
+            } if *retry_after > self.max_wait => Err(Error::RateLimited(*retry_after)),
+            Error::ApiStatus {
+                retry_after: Some(retry_after),
+                ..
+            } => Ok(*retry_after),
+            _ => Ok(self.delay(attempt)),
+        }
+    }
+}
+
+static RETRY_POLICY: OnceLock<RetryPolicy> = OnceLock::new();
+
+/// Set once at startup from `Config::retry_policy`.
+pub fn set_retry_policy(policy: RetryPolicy) {
+    let _ = RETRY_POLICY.set(policy);
+}
+
+/// Where the transcript proxy is, up to the video id.
+pub const DEFAULT_TRANSCRIPT_BASE: &str =
+    "https://zl319yz4a6.execute-api.us-east-1.amazonaws.com/Prod/youtube/transcript";
+/// Where the OpenAI proxy is, up to `/chat/completions`.
+pub const DEFAULT_OPENAI_BASE: &str =
+    "https://zl319yz4a6.execute-api.us-east-1.amazonaws.com/Prod/v1";
+const DEFAULT_YOUTUBE_BASE: &str = "https://www.googleapis.com/youtube/v3";
+
+/// OpenAI requests in flight at once by default.
+pub const DEFAULT_OPENAI_CONCURRENCY: usize = 4;
+pub const DEFAULT_HTTP_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The HTTP client and the services it talks to, so tests can point them at
+/// a mock server.
+#[derive(Clone)]
+pub struct YoutubeClient {
+    pub http: reqwest::Client,
+    /// Tried in order until one has the transcript.
+    pub transcript_bases: Vec<String>,
+    pub openai_base: String,
+    pub youtube_base: String,
+    /// Shared by every job, so bursts of them queue up instead of all
+    /// hitting OpenAI's rate limits at once.
+    pub openai_permits: Arc<Semaphore>,
+    /// How long connecting may take, and how long transcript and video info
+    /// requests may take in all. OpenAI replies can take longer, and are
+    /// only limited by the job's timeout.
+    pub timeout: Duration,
+    /// From the environment by default, or `Config` at startup.
+    pub youtube_token: Option<String>,
+    pub openai_token: Option<String>,
+}
+
+impl Default for YoutubeClient {
+    fn default() -> YoutubeClient {
+        YoutubeClient::with_timeout(DEFAULT_HTTP_TIMEOUT)
+    }
+}
+
+static CLIENT: OnceLock<YoutubeClient> = OnceLock::new();
+
+/// Set once at startup, before any requests. Until then the default
+/// endpoints are used.
+pub fn set_client(client: YoutubeClient) {
+    let _ = CLIENT.set(client);
+}
+
+/// The client everything in the bot goes through.
+pub fn client() -> &'static YoutubeClient {
+    CLIENT.get_or_init(YoutubeClient::default)
+}
+
+impl YoutubeClient {
+    pub fn with_timeout(timeout: Duration) -> YoutubeClient {
+        YoutubeClient {
+            http: reqwest::Client::builder()
+                .connect_timeout(timeout)
+                .build()
+                .expect("Couldn't build the HTTP client"),
+            transcript_bases: vec![DEFAULT_TRANSCRIPT_BASE.to_string()],
+            openai_base: DEFAULT_OPENAI_BASE.to_string(),
+            youtube_base: DEFAULT_YOUTUBE_BASE.to_string(),
+            openai_permits: Arc::new(Semaphore::new(DEFAULT_OPENAI_CONCURRENCY)),
+            timeout,
+            youtube_token: env_token("YOUTUBE_API_TOKEN"),
+            openai_token: env_token("OPENAI_API_TOKEN"),
+        }
+    }
+
+    fn youtube_token(&self) -> Result<&str, Error> {
+        self.youtube_token
+            .as_deref()
+            .ok_or(Error::MissingToken("YOUTUBE_API_TOKEN"))
+    }
+
+    fn openai_token(&self) -> Result<&str, Error> {
+        self.openai_token
+            .as_deref()
+            .ok_or(Error::MissingToken("OPENAI_API_TOKEN"))
+    }
+
+    /// A reqwest error, as a `TimedOut` if it was `timeout` running out.
+    fn http_error(&self, why: reqwest::Error) -> Error {
+        if why.is_timeout() {
+            Error::TimedOut(self.timeout)
+        } else {
+            Error::from(why)
+        }
+    }
+
+    /// GET `url` with `query` encoded onto it, and parse its JSON body, all
+    /// within `timeout`.
+    async fn get_json<T: serde::de::DeserializeOwned>(
+        &self,
+        url: &str,
+        query: &[(&str, &str)],
+    ) -> Result<T, Error> {
+        let response = self
+            .http
+            .get(url)
+            .query(query)
+            .timeout(self.timeout)
             .send()
             .await
-            .map_err(|e| e.to_string())?;
+            .map_err(|why| self.http_error(why))?;
+        response.json().await.map_err(|why| self.http_error(why))
+    }
+
+    /// With no `language`, the transcript API picks one. Each of
+    /// `transcript_bases` is tried in turn, and if they all fail the error
+    /// says why each did.
+    async fn transcript(
+        &self,
+        video_id: &str,
+        language: Option<&str>,
+    ) -> Result<Vec<TranscriptItem>, Error> {
+        let mut failures = Vec::new();
+        for base in &self.transcript_bases {
+            match self.transcript_from(base, video_id, language).await {
+                Ok(transcript) => return Ok(transcript),
+                Err(why) => failures.push(why),
+            }
+        }
+        match failures.len() {
+            1 => Err(failures.pop().unwrap()),
+            _ => Err(Error::TranscriptSources(failures)),
+        }
+    }
+
+    async fn transcript_from(
+        &self,
+        base: &str,
+        video_id: &str,
+        language: Option<&str>,
+    ) -> Result<Vec<TranscriptItem>, Error> {
+        let url = format!("{base}/{video_id}");
+        // The language comes from users, so it's encoded rather than pasted in.
+        let query = match language {
+            Some(language) => vec![("lang", language)],
+            None => Vec::new(),
+        };
+        let data: TranscriptResponse = self.get_json(&url, &query).await?;
+
+        match data {
+            TranscriptResponse::Success { transcript } if is_boilerplate(&transcript) => {
+                warn!("Transcript from {base} is an error message in disguise");
+                Err(Error::TranscriptUnavailable)
+            }
+            TranscriptResponse::Success { transcript } => Ok(transcript),
+            TranscriptResponse::Error { message } => {
+                warn!("Error fetching transcript from {base}: {}", message);
+                match language {
+                    Some(language) => Err(Error::NoTranscriptIn(language.to_string())),
+                    None => Err(Error::Api(message)),
+                }
+            }
+        }
+    }
 
-        let text = api_response.text().await.map_err(|e| e.to_string())?;
-        let data = serde_json::from_str::<openai::ChatApiResponse>(&text)
-            .map_err(|e| format!("deserialization of `{text:?}` failed: {}", e.to_string()))?;
+    /// Up to `max` of the playlist's videos, in playlist order, and whether
+    /// there were more than that.
+    pub async fn playlist_video_ids(
+        &self,
+        playlist_id: &str,
+        max: usize,
+    ) -> Result<(Vec<String>, bool), Error> {
+        let mut video_ids = Vec::new();
+        let mut page_token: Option<String> = None;
+        loop {
+            let url = format!(
+                "{}/playlistItems?playlistId={}&key={}&part=contentDetails&maxResults=50{}",
+                self.youtube_base,
+                playlist_id,
+                self.youtube_token()?,
+                page_token
+                    .map(|token| format!("&pageToken={token}"))
+                    .unwrap_or_default()
+            );
+            let page: PlaylistItemsResponse = self.get_json(&url, &[]).await?;
+            video_ids.extend(
+                page.items
+                    .into_iter()
+                    .map(|item| item.content_details.video_id),
+            );
+            if video_ids.len() > max {
+                video_ids.truncate(max);
+                return Ok((video_ids, true));
+            }
+            match page.next_page_token {
+                Some(_) if video_ids.len() == max => return Ok((video_ids, true)),
+                Some(token) => page_token = Some(token),
+                None => return Ok((video_ids, false)),
+            }
+        }
+    }
+
+    async fn video_info(&self, video_id: &str) -> Result<VideoInfo, Error> {
+        let url = format!(
+            "{}/videos?id={}&key={}&part=snippet",
+            self.youtube_base,
+            video_id,
+            self.youtube_token()?
+        );
+        video_info(self.get_json(&url, &[]).await?)
+    }
 
+    fn completions_url(&self) -> String {
+        format!("{}/chat/completions", self.openai_base)
+    }
+
+    async fn chat_once(
+        &self,
+        chat_api_request: &openai::ChatApiRequest,
+    ) -> Result<(String, openai::Usage), Error> {
+        let token = self.openai_token()?;
+        let _permit = self.openai_permits.acquire().await.unwrap();
+        let api_response = self
+            .http
+            .post(self.completions_url())
+            .header("Content-Type", "application/json")
+            .header("Authorization", format!("Bearer {token}"))
+            .json(chat_api_request)
+            .send()
+            .await?;
+
+        let status = api_response.status();
+        let headers = api_response.headers().clone();
+        let text = api_response.text().await?;
+        if !status.is_success() {
+            return Err(status_error(status, &headers, text));
+        }
+        let data = serde_json::from_str::<openai::ChatApiResponse>(&text).map_err(|e| {
+            Error::InvalidResponse(format!("deserialization of `{text:?}` failed: {e}"))
+        })?;
+
+        metrics::add(&METRICS.tokens_used, data.usage.total_tokens);
         if let Some(first_choice) = data.choices.get(0) {
-            Ok(first_choice.message.content.clone())
+            Ok((first_choice.message.content.clone(), data.usage))
         } else {
-            Err("No choices in response".to_string())
+            Err(Error::InvalidResponse("No choices in response".to_string()))
+        }
+    }
+
+    /// The reply, and the tokens it took.
+    async fn chat(
+        &self,
+        chat_api_request: openai::ChatApiRequest,
+    ) -> Result<(String, openai::Usage), Error> {
+        let policy = RETRY_POLICY.get().copied().unwrap_or_default();
+        let mut attempt = 1;
+        loop {
+            let result = self.chat_once(&chat_api_request).await;
+            if result.is_err() {
+                metrics::increment(&METRICS.openai_errors);
+            }
+            match result {
+                Err(why) if why.is_retryable() && attempt < policy.attempts => {
+                    let delay = policy.delay_for(&why, attempt)?;
+                    warn!("OpenAI request failed, retrying in {delay:?}: {why}");
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                }
+                result => return result,
+            }
         }
     }
-    match chat_once(chat_api_request.clone()).await {
-        Ok(response) => Ok(response),
-        Err(_e) => {
-            // Try again in one minute
-            tokio::time::sleep(Duration::from_secs(60)).await;
-            chat_once(chat_api_request).await
+
+    async fn stream_once(
+        &self,
+        chat_api_request: &openai::ChatApiRequest,
+        progress: &watch::Sender<String>,
+    ) -> Result<String, Error> {
+        let token = self.openai_token()?;
+        // Held until the whole reply has arrived.
+        let _permit = self.openai_permits.acquire().await.unwrap();
+        let mut api_response = self
+            .http
+            .post(self.completions_url())
+            .header("Content-Type", "application/json")
+            .header("Authorization", format!("Bearer {token}"))
+            .json(&openai::ChatStreamRequest {
+                request: chat_api_request,
+                stream: true,
+            })
+            .send()
+            .await?;
+        let status = api_response.status();
+        if !status.is_success() {
+            let headers = api_response.headers().clone();
+            return Err(status_error(status, &headers, api_response.text().await?));
         }
+
+        let mut reply = String::new();
+        let mut buffer = Vec::new();
+        while let Some(bytes) = api_response.chunk().await? {
+            buffer.extend_from_slice(&bytes);
+            for data in openai::take_sse_data(&mut buffer) {
+                if data == "[DONE]" {
+                    return Ok(reply);
+                }
+                let chunk =
+                    serde_json::from_str::<openai::ChatStreamChunk>(&data).map_err(|e| {
+                        Error::InvalidResponse(format!("deserialization of `{data:?}` failed: {e}"))
+                    })?;
+                if let Some(content) = chunk
+                    .choices
+                    .into_iter()
+                    .find_map(|choice| choice.delta.content)
+                {
+                    reply.push_str(&content);
+                    progress.send_replace(reply.clone());
+                }
+            }
+        }
+        Err(Error::Api("The reply ended early".to_string()))
     }
+
+    /// Like `chat`, but sends the reply so far to `progress` as it's
+    /// generated. Falls back to `chat` if streaming fails before anything
+    /// arrives.
+    async fn chat_streaming(
+        &self,
+        chat_api_request: openai::ChatApiRequest,
+        progress: &watch::Sender<String>,
+    ) -> Result<(String, openai::Usage), Error> {
+        let result = self.stream_once(&chat_api_request, progress).await;
+        if result.is_err() {
+            metrics::increment(&METRICS.openai_errors);
+        }
+        match result {
+            Ok(reply) => {
+                let usage = openai::Usage::estimate(
+                    &chat_api_request.model,
+                    &chat_api_request.messages,
+                    &reply,
+                );
+                metrics::add(&METRICS.tokens_used, usage.total_tokens);
+                Ok((reply, usage))
+            }
+            Err(why @ Error::MissingToken(_)) => Err(why),
+            Err(why) if !progress.borrow().is_empty() => Err(why),
+            Err(why) => {
+                warn!("Streaming failed, retrying without it: {why}");
+                self.chat(chat_api_request).await
+            }
+        }
+    }
+}
+
+/// The most tokens sent in one request, which is well within the context
+/// window of the models used.
+const MAX_PROMPT_TOKENS: u64 = 50_000;
+
+/// Caps what a summary can cost. The longest summaries asked for are about
+/// 4,000 words, which is well under this.
+const SUMMARY_MAX_TOKENS: u32 = 6000;
+
+/// The prompt summarizing `transcript` in one go, and the language it's in.
+/// It's summarized chapter by chapter when there are at least two
+/// `chapters` with speech in them, unless bullet points or notes were asked
+/// for.
+fn summary_prompt(
+    transcript: &[TranscriptItem],
+    chapters: &[Chapter],
+    title: Option<String>,
+    channel_name: Option<String>,
+    options: &SummaryOptions,
+) -> Result<(Vec<openai::ChatMessage>, Option<whatlang::Lang>), Error> {
+    let raw_transcript = join_transcript(transcript);
+    let source_language = detect_language(&raw_transcript);
+    let chapters = chapter_transcripts(transcript, chapters);
+    let messages = if options.style == Some(SummaryStyle::Bullets) {
+        prompts::summarize_bullets(
+            raw_transcript,
+            title,
+            channel_name,
+            source_language,
+            options,
+        )?
+    } else if options.style == Some(SummaryStyle::Notes) {
+        prompts::summarize_notes(
+            raw_transcript,
+            title,
+            channel_name,
+            source_language,
+            options,
+        )?
+    } else if chapters.len() >= 2 {
+        prompts::summarize_chapters(chapters, title, channel_name, source_language, options)?
+    } else {
+        prompts::summarize(
+            raw_transcript,
+            title,
+            channel_name,
+            source_language,
+            options,
+        )?
+    };
+    Ok((messages, source_language))
 }
 
 async fn summarize(
-    raw_transcript: String,
+    transcript: &[TranscriptItem],
+    chapters: &[Chapter],
+    title: Option<String>,
+    channel_name: Option<String>,
+    options: &SummaryOptions,
+    model: &str,
+    progress: Option<&watch::Sender<String>>,
+) -> Result<(String, openai::Usage), Error> {
+    let (messages, source_language) = summary_prompt(
+        transcript,
+        chapters,
+        title.clone(),
+        channel_name.clone(),
+        options,
+    )?;
+
+    if openai::count_tokens(model, &messages) as u64 > MAX_PROMPT_TOKENS {
+        return summarize_in_parts(
+            transcript,
+            title,
+            channel_name,
+            source_language,
+            options,
+            model,
+            progress,
+        )
+        .await;
+    }
+
+    let chat_api_request = openai::ChatApiRequest {
+        model: model.to_string(),
+        messages,
+        temperature: None,
+        max_tokens: Some(SUMMARY_MAX_TOKENS),
+    };
+
+    match progress {
+        Some(progress) => client().chat_streaming(chat_api_request, progress).await,
+        None => client().chat(chat_api_request).await,
+    }
+}
+
+/// Words of transcript summarized at a time when a video is too long for
+/// one prompt.
+const SUMMARY_CHUNK_WORDS: usize = 20_000;
+
+/// For transcripts too long for one prompt: summarize them a chunk at a time,
+/// then summarize those summaries. If even they don't fit in one prompt,
+/// neighbouring ones are combined in pairs until they do.
+async fn summarize_in_parts(
+    transcript: &[TranscriptItem],
     title: Option<String>,
     channel_name: Option<String>,
-) -> Result<String, String> {
-    let (messages, tokens) = prompts::summarize(raw_transcript, title, channel_name)?;
-
-    let model = if tokens > 50_000 {
-        return Err(format!(
-            "Transcript too long to summarize. ({} tokens)",
-            tokens
-        ));
+    source_language: Option<whatlang::Lang>,
+    options: &SummaryOptions,
+    model: &str,
+    progress: Option<&watch::Sender<String>>,
+) -> Result<(String, openai::Usage), Error> {
+    let mut chunks = transcript_chunks(transcript, SUMMARY_CHUNK_WORDS);
+    // A short last chunk is too little to summarize on its own.
+    if chunks.len() > 1
+        && chunks[chunks.len() - 1].text.split(' ').count() <= prompts::min_summary_words()
+    {
+        let last = chunks.pop().unwrap();
+        let previous = chunks.last_mut().unwrap();
+        previous.text.push(' ');
+        previous.text.push_str(&last.text);
+    }
+    // Only the final summary is in the style asked for.
+    let part_options = SummaryOptions {
+        style: None,
+        ..options.clone()
+    };
+    let summarize_part = |messages: Vec<openai::ChatMessage>| async move {
+        let tokens = openai::count_tokens(model, &messages) as u64;
+        if tokens > MAX_PROMPT_TOKENS {
+            return Err(Error::TranscriptTooLong { tokens });
+        }
+        client()
+            .chat(openai::ChatApiRequest {
+                model: model.to_string(),
+                messages,
+                temperature: None,
+                max_tokens: Some(SUMMARY_MAX_TOKENS),
+            })
+            .await
+    };
+
+    let results = stream::iter(chunks)
+        .map(|chunk| {
+            let words = chunk.text.split(' ').count();
+            let prompt = prompts::summarize(
+                chunk.text,
+                title.clone(),
+                channel_name.clone(),
+                source_language,
+                &part_options,
+            );
+            async move {
+                let (summary, usage) = summarize_part(prompt?).await?;
+                Ok(((summary, words), usage))
+            }
+        })
+        .buffered(CLEANUP_CONCURRENCY)
+        .collect::<Vec<Result<((String, usize), openai::Usage), Error>>>()
+        .await;
+    let mut parts = Vec::new();
+    let mut usage = openai::Usage::default();
+    for result in results {
+        let (part, part_usage) = result?;
+        parts.push(part);
+        usage = usage + part_usage;
+    }
+
+    loop {
+        let words = parts.iter().map(|(_, words)| words).sum();
+        let summaries = parts.iter().map(|(summary, _)| summary.clone()).collect();
+        let messages = prompts::summarize_summaries(
+            summaries,
+            words,
+            title.clone(),
+            channel_name.clone(),
+            source_language,
+            options,
+        )?;
+        let tokens = openai::count_tokens(model, &messages) as u64;
+        if tokens <= MAX_PROMPT_TOKENS {
+            let chat_api_request = openai::ChatApiRequest {
+                model: model.to_string(),
+                messages,
+                temperature: None,
+                max_tokens: Some(SUMMARY_MAX_TOKENS),
+            };
+            let (summary, final_usage) = match progress {
+                Some(progress) => client().chat_streaming(chat_api_request, progress).await?,
+                None => client().chat(chat_api_request).await?,
+            };
+            return Ok((summary, usage + final_usage));
+        }
+        if parts.len() == 1 {
+            return Err(Error::TranscriptTooLong { tokens });
+        }
+
+        let mut combined = Vec::new();
+        for pair in parts.chunks(2) {
+            if let [part] = pair {
+                combined.push(part.clone());
+                continue;
+            }
+            let words = pair.iter().map(|(_, words)| words).sum();
+            let messages = prompts::summarize_summaries(
+                pair.iter().map(|(summary, _)| summary.clone()).collect(),
+                words,
+                title.clone(),
+                channel_name.clone(),
+                source_language,
+                &part_options,
+            )?;
+            let (summary, pair_usage) = summarize_part(messages).await?;
+            combined.push((summary, words));
+            usage = usage + pair_usage;
+        }
+        parts = combined;
+    }
+}
+
+async fn summarize_description(
+    info: &VideoInfo,
+    options: &SummaryOptions,
+    model: &str,
+) -> Result<(String, openai::Usage), Error> {
+    let messages = prompts::summarize_description(
+        info.description.clone(),
+        info.title.clone(),
+        info.channel_name.clone(),
+        options,
+    );
+    let chat_api_request = openai::ChatApiRequest {
+        model: model.to_string(),
+        messages,
+        temperature: None,
+        max_tokens: Some(SUMMARY_MAX_TOKENS),
+    };
+    let (summary, usage) = client().chat(chat_api_request).await?;
+    Ok((
+        format!("*There's no speech in this video, so this is summarized from its description.*\n\n{summary}"),
+        usage,
+    ))
+}
+
+/// Transcripts are cleaned up this many words at a time, since the model
+/// can't write out a whole long transcript in one response.
+const CLEANUP_CHUNK_WORDS: usize = 1500;
+
+/// Most chunks of one transcript cleaned up at once, so long videos don't
+/// fire off dozens of OpenAI requests together.
+const CLEANUP_CONCURRENCY: usize = 4;
+
+struct TranscriptChunk {
+    /// Seconds into the video where the chunk starts.
+    start: f64,
+    text: String,
+    /// How many words into the chunk each caption line starts, and when.
+    starts: Vec<(usize, f64)>,
+}
+
+impl TranscriptChunk {
+    /// Roughly when the part of the chunk `fraction` of the way through its
+    /// words was said, for placing a timestamp on a cleaned-up paragraph
+    /// that no longer matches the captions word for word.
+    fn time_at(&self, fraction: f64) -> f64 {
+        let words = self.text.split_whitespace().count();
+        let word = (fraction * words as f64) as usize;
+        self.starts
+            .iter()
+            .take_while(|(offset, _)| *offset <= word)
+            .last()
+            .map_or(self.start, |(_, start)| *start)
+    }
+}
+
+/// Split a transcript into chunks of whole caption lines, each with about
+/// `max_words` words. This must stay deterministic so cleanup checkpoint
+/// indices from a failed run line up with a retry. Words repeated where
+/// captions overlap are dropped first.
+fn transcript_chunks(items: &[TranscriptItem], max_words: usize) -> Vec<TranscriptChunk> {
+    let mut chunks: Vec<TranscriptChunk> = Vec::new();
+    let mut words = 0;
+    for item in collapse_items(items) {
+        match chunks.last_mut() {
+            Some(chunk) if words < max_words => {
+                chunk.starts.push((words, item.start));
+                chunk.text.push(' ');
+                chunk.text.push_str(&item.text);
+            }
+            _ => {
+                chunks.push(TranscriptChunk {
+                    start: item.start,
+                    text: item.text.clone(),
+                    starts: vec![(0, item.start)],
+                });
+                words = 0;
+            }
+        }
+        words += item.text.split_whitespace().count();
+    }
+    chunks
+}
+
+/// `[mm:ss](link)`, or `[h:mm:ss](link)` past an hour, linking to that point
+/// in the video.
+fn timestamp_link(video_id: &str, start: f64) -> String {
+    let seconds = start as u64;
+    let (hours, minutes, seconds) = (seconds / 3600, seconds / 60 % 60, seconds % 60);
+    let label = if hours > 0 {
+        format!("{hours}:{minutes:02}:{seconds:02}")
     } else {
-        "gpt-4-1106-preview"
+        format!("{minutes:02}:{seconds:02}")
     };
+    format!(
+        "[{label}](https://www.youtube.com/watch?v={video_id}&t={}s)",
+        start as u64
+    )
+}
 
-    let chat_api_request = openai::ChatApiRequest { model, messages };
+/// Each of a cleaned-up chunk's paragraphs, starting with a link to about
+/// where it's said in the video.
+fn timestamp_paragraphs(video_id: &str, text: &str, chunk: &TranscriptChunk) -> Vec<String> {
+    let paragraphs = text
+        .split("\n\n")
+        .map(str::trim)
+        .filter(|paragraph| !paragraph.is_empty())
+        .collect::<Vec<_>>();
+    let total = paragraphs
+        .iter()
+        .map(|paragraph| paragraph.split_whitespace().count())
+        .sum::<usize>()
+        .max(1);
+    let mut words = 0;
+    paragraphs
+        .into_iter()
+        .map(|paragraph| {
+            let start = chunk.time_at(words as f64 / total as f64);
+            words += paragraph.split_whitespace().count();
+            format!("{} {paragraph}", timestamp_link(video_id, start))
+        })
+        .collect()
+}
 
-    chat(chat_api_request).await
+/// What's returned when cleaning up some chunks of a transcript fails.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PartialTranscripts {
+    /// Nothing, just the error.
+    #[default]
+    Off,
+    /// What was cleaned up before the first failure.
+    UpToFailure,
+    /// Every chunk that worked, with a marker where each failed one was.
+    SkipFailures,
 }
 
+impl PartialTranscripts {
+    pub fn parse(s: &str) -> PartialTranscripts {
+        match s.trim().to_lowercase().as_str() {
+            "true" => PartialTranscripts::UpToFailure,
+            "best-effort" => PartialTranscripts::SkipFailures,
+            _ => PartialTranscripts::Off,
+        }
+    }
+}
+
+/// Stands in for a chunk that couldn't be cleaned up, with
+/// `PartialTranscripts::SkipFailures`.
+const OMITTED_CHUNK: &str = "*[section omitted due to error]*";
+
+/// How transcripts are fetched, cleaned up and laid out.
+#[derive(Clone, Debug, Default)]
+pub struct TranscriptOptions {
+    pub model: String,
+    /// The caption language to fetch, e.g. `es`.
+    pub language: Option<String>,
+    pub partial: PartialTranscripts,
+    /// Start each chunk with a link to where it starts in the video.
+    pub timestamps: bool,
+    /// Label who's speaking, as `**Speaker A:**` blocks.
+    pub speakers: bool,
+    /// Sentences per paragraph, or 0 to keep the model's paragraphs. Not
+    /// used with `speakers`, whose turns are paragraphs already.
+    pub paragraph_sentences: usize,
+    /// The server's own system prompt, from `/setprompt`.
+    pub system_prompt: Option<String>,
+}
+
+/// Each chunk's output is checkpointed in the cache as soon as it's done, so
+/// if a later chunk fails, retrying the job picks up where it left off.
+///
+/// With `options.partial`, failures return what could be cleaned up instead
+/// of an error (see `finish_cleanup`). The flag in the result is false when
+/// that happened. The usage covers every chunk that was cleaned up rather
+/// than taken from a checkpoint.
 async fn clean_transcript(
-    raw_transcript: String,
+    video_id: &str,
+    items: &[TranscriptItem],
     title: Option<String>,
     channel_name: Option<String>,
-) -> Result<String, String> {
-    let (messages, tokens) =
-        prompts::clean_transcript_one_prompt(raw_transcript, title, channel_name);
-
-    let model = if tokens > 50_000 {
-        return Err(format!(
-            "Transcript too long to clean up. ({} tokens)",
-            tokens
-        ));
-    } else {
-        "gpt-4-1106-preview"
+    cache: &Cache,
+    options: &TranscriptOptions,
+) -> Result<(String, bool, openai::Usage), Error> {
+    let chunks = transcript_chunks(items, CLEANUP_CHUNK_WORDS);
+    let prompts = chunks
+        .iter()
+        .map(|chunk| {
+            if options.speakers {
+                prompts::clean_transcript_speakers_messages(
+                    chunk.text.clone(),
+                    title.clone(),
+                    channel_name.clone(),
+                    &[],
+                    None,
+                    options.system_prompt.as_deref(),
+                )
+            } else {
+                prompts::clean_transcript_messages(
+                    chunk.text.clone(),
+                    title.clone(),
+                    channel_name.clone(),
+                    options.system_prompt.as_deref(),
+                )
+            }
+        })
+        .collect::<Vec<_>>();
+    // Speakers' turns already come as paragraphs of their own.
+    let laid_out = |cleaned: &str| {
+        if options.speakers {
+            cleaned.to_string()
+        } else {
+            utils::paragraphs(cleaned, options.paragraph_sentences)
+        }
+    };
+    let assemble = |cleaned: Vec<String>| {
+        if !options.timestamps {
+            return match options.speakers {
+                true => cleaned.join("\n\n"),
+                false => laid_out(&cleaned.join(" ")),
+            };
+        }
+        cleaned
+            .iter()
+            .zip(&chunks)
+            .flat_map(|(cleaned, chunk)| timestamp_paragraphs(video_id, &laid_out(cleaned), chunk))
+            .collect::<Vec<_>>()
+            .join("\n\n")
     };
+    let tokens = openai::count_tokens_many(
+        &options.model,
+        &prompts.iter().map(Vec::as_slice).collect::<Vec<_>>(),
+    );
+
+    if let Some(&tokens) = tokens
+        .iter()
+        .find(|tokens| **tokens as u64 > MAX_PROMPT_TOKENS)
+    {
+        return Err(Error::TranscriptTooLong {
+            tokens: tokens as u64,
+        });
+    }
 
-    let chat_api_request = openai::ChatApiRequest { model, messages };
-    let transcript = chat(chat_api_request).await?;
+    if options.speakers {
+        // Checked above with the longest hint-free prompts; the hints only
+        // add a line.
+        let results =
+            clean_chunks_with_speakers(video_id, &chunks, title, channel_name, cache, options)
+                .await;
+        return finish_cleanup(video_id, results, chunks.len(), options.partial, assemble);
+    }
 
-    let transcript = transcript.replace(". ", ".\n\n");
+    // Chunks are cleaned up concurrently, but `buffered` hands the results
+    // back in order.
+    let results = stream::iter(chunks.iter().zip(prompts).enumerate())
+        .map(|(index, (chunk, messages))| async move {
+            let key = ChunkKey::new(video_id, index, &chunk.text)
+                .with_system_prompt(options.system_prompt.as_deref())
+                .with_model(&options.model);
+            if let Some(checkpoint) = cache.get_chunk(&key) {
+                return Ok((checkpoint, openai::Usage::default()));
+            }
+            // The cleanup should stick to what was said, not get creative.
+            let chat_api_request = openai::ChatApiRequest {
+                model: options.model.clone(),
+                messages,
+                temperature: Some(0.0),
+                max_tokens: None,
+            };
+            let (chunk_transcript, usage) = client().chat(chat_api_request).await?;
+            cache.insert_chunk(key, chunk_transcript.clone());
+            Ok((chunk_transcript, usage))
+        })
+        .buffered(CLEANUP_CONCURRENCY)
+        .collect::<Vec<Result<(String, openai::Usage), Error>>>()
+        .await;
 
-    Ok(transcript)
+    finish_cleanup(video_id, results, chunks.len(), options.partial, assemble)
 }
 
-pub async fn get_video_transcript(video_id: &str) -> Result<(String, VideoInfo), String> {
-    let info = get_video_info(video_id).await.map_err(|e| e.to_string())?;
-    let transcript = get_transcript(video_id).await?;
-    let summary = clean_transcript(
-        transcript,
+/// Put the cleaned-up chunks back together, in order. When some failed,
+/// `partial` decides whether that's an error, the chunks up to the first
+/// failure ending in a note saying where it stopped, or every chunk that
+/// worked with `OMITTED_CHUNK` in place of the others. It's an error when
+/// none worked.
+fn finish_cleanup(
+    video_id: &str,
+    results: Vec<Result<(String, openai::Usage), Error>>,
+    chunks: usize,
+    partial: PartialTranscripts,
+    assemble: impl Fn(Vec<String>) -> String,
+) -> Result<(String, bool, openai::Usage), Error> {
+    let mut cleaned = Vec::new();
+    let mut usage = openai::Usage::default();
+    let mut omitted = 0;
+    let mut first_error = None;
+    for (index, result) in results.into_iter().enumerate() {
+        match result {
+            Ok((chunk_transcript, chunk_usage)) => {
+                cleaned.push(chunk_transcript);
+                usage = usage + chunk_usage;
+            }
+            Err(why) if partial == PartialTranscripts::SkipFailures => {
+                warn!(
+                    "Omitting part {} of {} of the transcript of {video_id}: {why}",
+                    index + 1,
+                    chunks
+                );
+                cleaned.push(OMITTED_CHUNK.to_string());
+                omitted += 1;
+                first_error.get_or_insert(why);
+            }
+            Err(why) if partial == PartialTranscripts::UpToFailure && !cleaned.is_empty() => {
+                warn!("Returning partial transcript of {video_id}: {why}");
+                let transcript = assemble(cleaned);
+                let note = format!(
+                    "\n\n*Cleanup failed at part {} of {}, so the transcript stops here. ({why})*",
+                    index + 1,
+                    chunks
+                );
+                return Ok((transcript + &note, false, usage));
+            }
+            Err(why) => return Err(why),
+        }
+    }
+
+    match first_error {
+        Some(why) if omitted == cleaned.len() => Err(why),
+        _ => Ok((assemble(cleaned), omitted == 0, usage)),
+    }
+}
+
+/// Speaker-labelled chunks are cleaned up one after another, each told who
+/// was labelled in the ones before. Unless failed chunks are to be skipped,
+/// it stops at the first failure, since later ones would only be thrown away.
+async fn clean_chunks_with_speakers(
+    video_id: &str,
+    chunks: &[TranscriptChunk],
+    title: Option<String>,
+    channel_name: Option<String>,
+    cache: &Cache,
+    options: &TranscriptOptions,
+) -> Vec<Result<(String, openai::Usage), Error>> {
+    let mut results = Vec::new();
+    let mut speakers: Vec<String> = Vec::new();
+    let mut last_speaker = None;
+    for (index, chunk) in chunks.iter().enumerate() {
+        let key = ChunkKey::new(video_id, index, &chunk.text)
+            .with_speakers(true)
+            .with_system_prompt(options.system_prompt.as_deref())
+            .with_model(&options.model);
+        let result = match cache.get_chunk(&key) {
+            Some(checkpoint) => Ok((checkpoint, openai::Usage::default())),
+            None => {
+                let chat_api_request = openai::ChatApiRequest {
+                    model: options.model.clone(),
+                    messages: prompts::clean_transcript_speakers_messages(
+                        chunk.text.clone(),
+                        title.clone(),
+                        channel_name.clone(),
+                        &speakers,
+                        last_speaker.as_deref(),
+                        options.system_prompt.as_deref(),
+                    ),
+                    temperature: Some(0.0),
+                    max_tokens: None,
+                };
+                client().chat(chat_api_request).await
+            }
+        };
+        match result {
+            Ok((chunk_transcript, usage)) => {
+                cache.insert_chunk(key, chunk_transcript.clone());
+                for label in speaker_labels(&chunk_transcript) {
+                    if !speakers.contains(&label) {
+                        speakers.push(label.clone());
+                    }
+                    last_speaker = Some(label);
+                }
+                results.push(Ok((chunk_transcript, usage)));
+            }
+            Err(why) => {
+                results.push(Err(why));
+                if options.partial != PartialTranscripts::SkipFailures {
+                    break;
+                }
+            }
+        }
+    }
+    results
+}
+
+/// The `**Label:**` that starts each speaker's turn, in order.
+fn speaker_labels(transcript: &str) -> Vec<String> {
+    transcript
+        .lines()
+        .filter_map(|line| line.trim().strip_prefix("**")?.split_once(":**"))
+        .map(|(label, _)| label.trim().to_string())
+        .filter(|label| !label.is_empty())
+        .collect()
+}
+
+/// Where a finished summary made with `options` by `model` is cached.
+pub fn summary_cache_key(video_id: &str, options: &SummaryOptions, model: &str) -> CacheKey {
+    CacheKey::new(video_id, Action::Summarize, options).with_model(model)
+}
+
+/// Where a finished transcript made with `options` is cached.
+pub fn transcript_cache_key(video_id: &str, options: &TranscriptOptions) -> CacheKey {
+    CacheKey::new(
+        video_id,
+        Action::Transcribe,
+        &SummaryOptions {
+            language: options.language.clone(),
+            system_prompt: options.system_prompt.clone(),
+            ..SummaryOptions::default()
+        },
+    )
+    .with_model(&options.model)
+    .with_speakers(options.speakers)
+    .with_timestamps(options.timestamps)
+    .with_paragraph_sentences(options.paragraph_sentences)
+}
+
+/// Partial transcripts (see `clean_transcript`) aren't cached, so asking
+/// again resumes from the failed chunk. The usage is zero for cached
+/// transcripts.
+#[tracing::instrument(skip_all, fields(video_id = %video_id))]
+pub async fn get_video_transcript(
+    video_id: &str,
+    cache: &Cache,
+    options: &TranscriptOptions,
+) -> Result<((String, VideoInfo), openai::Usage), Error> {
+    let key = transcript_cache_key(video_id, options);
+    if let Some(cached) = cache.get(&key) {
+        debug!("Using cached transcript");
+        metrics::increment(&METRICS.cache_hits);
+        return Ok((cached, openai::Usage::default()));
+    }
+    metrics::increment(&METRICS.cache_misses);
+
+    let info = client().video_info(video_id).await?;
+    let transcript = filter_non_speech(
+        client()
+            .transcript(video_id, options.language.as_deref())
+            .await?,
+    );
+    if transcript.is_empty() {
+        return Err(Error::NoSpeech);
+    }
+    // Follow-up questions are answered from the default transcript.
+    if options.language.is_none() {
+        cache.insert_transcript(video_id, (join_transcript(&transcript), info.clone()));
+    }
+    let (summary, complete, usage) = clean_transcript(
+        video_id,
+        &transcript,
         Some(info.title.clone()),
         Some(info.channel_name.clone()),
+        cache,
+        options,
     )
     .await?;
-    Ok((summary, info))
+    if complete {
+        cache.insert(key, (summary.clone(), info.clone()));
+        cache.clear_chunks(video_id);
+    }
+    Ok(((summary, info), usage))
 }
 
-pub async fn get_video_summary(video_id: &str) -> Result<(String, VideoInfo), String> {
-    let info = get_video_info(video_id).await.map_err(|e| e.to_string())?;
-    let transcript = get_transcript(video_id).await?;
-    let summary = summarize(
-        transcript,
+/// With `describe_no_speech`, videos without any speech (e.g. music) are
+/// summarized from their description instead.
+///
+/// With `progress`, the summary is streamed to it as it's written. It's
+/// dropped once the summary is done, so receivers know to stop waiting.
+///
+/// The usage is zero for cached summaries.
+#[tracing::instrument(skip_all, fields(video_id = %video_id))]
+pub async fn get_video_summary(
+    video_id: &str,
+    options: &SummaryOptions,
+    cache: &Cache,
+    describe_no_speech: bool,
+    model: &str,
+    progress: Option<watch::Sender<String>>,
+) -> Result<((String, VideoInfo), openai::Usage), Error> {
+    let key = summary_cache_key(video_id, options, model);
+    if let Some(cached) = cache.get(&key) {
+        debug!("Using cached summary");
+        metrics::increment(&METRICS.cache_hits);
+        return Ok((cached, openai::Usage::default()));
+    }
+    metrics::increment(&METRICS.cache_misses);
+
+    let info = client().video_info(video_id).await?;
+    let mut transcript = client().transcript(video_id, None).await?;
+    cache.insert_transcript(video_id, (join_transcript(&transcript), info.clone()));
+    if let Some(portion) = options.portion {
+        transcript = slice_transcript(transcript, portion);
+    }
+    let transcript = filter_non_speech(transcript);
+    if transcript.is_empty() {
+        if !describe_no_speech || info.description.trim().is_empty() {
+            return Err(Error::NoSpeech);
+        }
+        let (summary, usage) = summarize_description(&info, options, model).await?;
+        cache.insert(key, (summary.clone(), info.clone()));
+        return Ok(((summary, info), usage));
+    }
+    let chapters = chapters::parse_chapters(&info.description);
+    let (summary, usage) = summarize(
+        &transcript,
+        &chapters,
         Some(info.title.clone()),
         Some(info.channel_name.clone()),
+        options,
+        model,
+        progress.as_ref(),
     )
     .await?;
-    Ok((summary, info))
+    cache.insert(key, (summary.clone(), info.clone()));
+    Ok(((summary, info), usage))
+}
+
+/// What summarizing and transcribing a video would take, worked out
+/// without calling OpenAI.
+pub struct Estimate {
+    pub info: VideoInfo,
+    pub summary: openai::Usage,
+    /// Too long for one prompt, so it'd be summarized in parts, which takes
+    /// somewhat more than `summary`.
+    pub summary_in_parts: bool,
+    pub transcript: openai::Usage,
+    /// A part of the transcript is too long to clean up.
+    pub transcript_too_long: bool,
+}
+
+/// Build the prompts a summary and transcript would use and count their
+/// tokens. Replies are guessed at: the summary's target length, and about
+/// as long as the transcript for the cleanup.
+pub async fn estimate(
+    video_id: &str,
+    options: &SummaryOptions,
+    model: &str,
+) -> Result<Estimate, Error> {
+    let info = client().video_info(video_id).await?;
+    let transcript = filter_non_speech(client().transcript(video_id, None).await?);
+    if transcript.is_empty() {
+        return Err(Error::NoSpeech);
+    }
+    let raw_transcript = join_transcript(&transcript);
+
+    let summary_transcript = match options.portion {
+        Some(portion) => filter_non_speech(slice_transcript(transcript.clone(), portion)),
+        None => transcript.clone(),
+    };
+    let (messages, _) = summary_prompt(
+        &summary_transcript,
+        &chapters::parse_chapters(&info.description),
+        Some(info.title.clone()),
+        Some(info.channel_name.clone()),
+        options,
+    )?;
+    let summary_words = prompts::goal_length(
+        join_transcript(&summary_transcript).split(' ').count(),
+        prompts::min_summary_words(),
+        options,
+    )?;
+    let summary = usage(
+        openai::count_tokens(model, &messages) as u64,
+        summary_words as u64 * 4 / 3,
+    );
+
+    let cleanup = transcript_chunks(&transcript, CLEANUP_CHUNK_WORDS)
+        .into_iter()
+        .map(|chunk| {
+            prompts::clean_transcript_messages(
+                chunk.text,
+                Some(info.title.clone()),
+                Some(info.channel_name.clone()),
+                None,
+            )
+        })
+        .collect::<Vec<_>>();
+    let cleanup_tokens = openai::count_tokens_many(
+        model,
+        &cleanup.iter().map(Vec::as_slice).collect::<Vec<_>>(),
+    );
+    let transcript = usage(
+        cleanup_tokens.iter().sum::<usize>() as u64,
+        openai::count_text_tokens(model, &raw_transcript) as u64,
+    );
+
+    Ok(Estimate {
+        summary_in_parts: summary.prompt_tokens > MAX_PROMPT_TOKENS,
+        transcript_too_long: cleanup_tokens
+            .iter()
+            .any(|tokens| *tokens as u64 > MAX_PROMPT_TOKENS),
+        info,
+        summary,
+        transcript,
+    })
+}
+
+fn usage(prompt_tokens: u64, completion_tokens: u64) -> openai::Usage {
+    openai::Usage {
+        prompt_tokens,
+        completion_tokens,
+        total_tokens: prompt_tokens + completion_tokens,
+    }
+}
+
+/// Answer a question about a video from its transcript, re-fetching the
+/// transcript if it's no longer cached.
+pub async fn answer_question(
+    video_id: &str,
+    question: String,
+    cache: &Cache,
+    model: &str,
+) -> Result<(String, openai::Usage), Error> {
+    let (transcript, info) = match cache.get_transcript(video_id) {
+        Some(cached) => cached,
+        None => {
+            let info = client().video_info(video_id).await?;
+            let transcript = join_transcript(&client().transcript(video_id, None).await?);
+            cache.insert_transcript(video_id, (transcript.clone(), info.clone()));
+            (transcript, info)
+        }
+    };
+
+    let messages = prompts::answer_question(
+        transcript,
+        Some(info.title),
+        Some(info.channel_name),
+        question,
+    );
+    let tokens = openai::count_tokens(model, &messages) as u64;
+
+    let model = if tokens > MAX_PROMPT_TOKENS {
+        return Err(Error::TranscriptTooLong { tokens });
+    } else {
+        model.to_string()
+    };
+
+    let chat_api_request = openai::ChatApiRequest {
+        model,
+        messages,
+        temperature: None,
+        max_tokens: None,
+    };
+
+    client().chat(chat_api_request).await
+}
+
+/// Rewrite an already-written summary following the user's `request`.
+pub async fn refine_summary(
+    summary: String,
+    request: String,
+    model: &str,
+) -> Result<String, Error> {
+    let chat_api_request = openai::ChatApiRequest {
+        model: model.to_string(),
+        messages: prompts::refine_summary(summary, request),
+        temperature: None,
+        max_tokens: Some(SUMMARY_MAX_TOKENS),
+    };
+    let (summary, _) = client().chat(chat_api_request).await?;
+    Ok(summary)
+}
+
+pub struct StageTiming {
+    pub stage: &'static str,
+    pub elapsed: Duration,
+    pub result: Result<(), Error>,
+}
+
+async fn time_stage<T>(
+    stage: &'static str,
+    timings: &mut Vec<StageTiming>,
+    future: impl std::future::Future<Output = Result<T, Error>>,
+) -> Option<T> {
+    let start = Instant::now();
+    let result = future.await;
+    let elapsed = start.elapsed();
+    let (result, value) = match result {
+        Ok(value) => (Ok(()), Some(value)),
+        Err(why) => (Err(why), None),
+    };
+    timings.push(StageTiming {
+        stage,
+        elapsed,
+        result,
+    });
+    value
+}
+
+/// Run the summary pipeline against `video_id` without touching the cache,
+/// timing each external call. Stops at the first stage that fails.
+pub async fn self_test(video_id: &str, model: &str) -> Vec<StageTiming> {
+    let mut timings = Vec::new();
+    let Some(info) = time_stage("Video info (YouTube API)", &mut timings, async {
+        client().video_info(video_id).await
+    })
+    .await
+    else {
+        return timings;
+    };
+    let Some(transcript) = time_stage(
+        "Transcript (transcript API)",
+        &mut timings,
+        client().transcript(video_id, None),
+    )
+    .await
+    else {
+        return timings;
+    };
+    time_stage(
+        "Completion (OpenAI)",
+        &mut timings,
+        summarize(
+            &transcript,
+            &[],
+            Some(info.title),
+            Some(info.channel_name),
+            &SummaryOptions::default(),
+            model,
+            None,
+        ),
+    )
+    .await;
+    timings
+}
+
+#[test]
+fn test_transcript_chunks() {
+    // Two words a second.
+    let items = (0..2000)
+        .map(|i| TranscriptItem {
+            text: format!("{} {}", i * 2, i * 2 + 1),
+            start: i as f64,
+            duration: 1.0,
+        })
+        .collect::<Vec<_>>();
+    let chunks = transcript_chunks(&items, CLEANUP_CHUNK_WORDS);
+    assert_eq!(chunks.len(), 3);
+    assert_eq!(chunks[1].text.split(' ').next(), Some("1500"));
+    assert_eq!(chunks[1].start, 750.0);
+    assert_eq!(
+        chunks.iter().map(|chunk| &chunk.text).collect::<Vec<_>>(),
+        transcript_chunks(&items, CLEANUP_CHUNK_WORDS)
+            .iter()
+            .map(|chunk| &chunk.text)
+            .collect::<Vec<_>>()
+    );
+
+    let overlapping = ["we're going to", "going to the", "going to the", "store"]
+        .iter()
+        .enumerate()
+        .map(|(i, text)| TranscriptItem {
+            text: text.to_string(),
+            start: i as f64,
+            duration: 1.0,
+        })
+        .collect::<Vec<_>>();
+    let chunks = transcript_chunks(&overlapping, CLEANUP_CHUNK_WORDS);
+    assert_eq!(chunks[0].text, "we're going to the store");
+    assert_eq!(chunks[0].starts, vec![(0, 0.0), (3, 1.0), (4, 3.0)]);
+}
+
+#[test]
+fn test_timestamp_link() {
+    assert_eq!(
+        timestamp_link("dQw4w9WgXcQ", 83.6),
+        "[01:23](https://www.youtube.com/watch?v=dQw4w9WgXcQ&t=83s)"
+    );
+    assert_eq!(
+        timestamp_link("dQw4w9WgXcQ", 3723.0),
+        "[1:02:03](https://www.youtube.com/watch?v=dQw4w9WgXcQ&t=3723s)"
+    );
+}
+
+#[test]
+fn test_is_non_speech() {
+    assert!(is_non_speech("[Music]"));
+    assert!(is_non_speech(" [Música] "));
+    assert!(is_non_speech("♪♪"));
+    assert!(is_non_speech("[Applause] (laughter)"));
+    assert!(is_non_speech(""));
+    assert!(!is_non_speech("[Music] hello there"));
+    assert!(!is_non_speech("♪ never gonna give you up ♪"));
+}
+
+#[test]
+fn test_video_id() {
+    let cases = [
+        (
+            "https://www.youtube.com/watch?v=dQw4w9WgXcQ",
+            Some("dQw4w9WgXcQ"),
+        ),
+        (
+            "https://youtube.com/watch?v=dQw4w9WgXcQ",
+            Some("dQw4w9WgXcQ"),
+        ),
+        (
+            "http://www.youtube.com/watch?v=dQw4w9WgXcQ",
+            Some("dQw4w9WgXcQ"),
+        ),
+        (
+            "https://m.youtube.com/watch?v=dQw4w9WgXcQ",
+            Some("dQw4w9WgXcQ"),
+        ),
+        (
+            "https://music.youtube.com/watch?v=dQw4w9WgXcQ&feature=share",
+            Some("dQw4w9WgXcQ"),
+        ),
+        (
+            "https://www.youtube.com/watch?v=dQw4w9WgXcQ&list=PL123&index=3",
+            Some("dQw4w9WgXcQ"),
+        ),
+        (
+            "https://www.youtube.com/watch?v=dQw4w9WgXcQ&t=30s",
+            Some("dQw4w9WgXcQ"),
+        ),
+        (
+            "https://www.youtube.com/watch?feature=share&v=dQw4w9WgXcQ",
+            Some("dQw4w9WgXcQ"),
+        ),
+        (
+            "https://www.youtube.com/watch?v=dQw4w9WgXcQ#t=30",
+            Some("dQw4w9WgXcQ"),
+        ),
+        ("https://youtu.be/dQw4w9WgXcQ", Some("dQw4w9WgXcQ")),
+        ("https://youtu.be/dQw4w9WgXcQ?t=30", Some("dQw4w9WgXcQ")),
+        (
+            "https://youtu.be/dQw4w9WgXcQ?si=abcdef",
+            Some("dQw4w9WgXcQ"),
+        ),
+        (
+            "https://www.youtube.com/shorts/arj7oStGLkU",
+            Some("arj7oStGLkU"),
+        ),
+        (
+            "https://youtube.com/shorts/arj7oStGLkU?feature=share",
+            Some("arj7oStGLkU"),
+        ),
+        (
+            "https://m.youtube.com/shorts/arj7oStGLkU",
+            Some("arj7oStGLkU"),
+        ),
+        (
+            "https://www.youtube.com/embed/dQw4w9WgXcQ?start=30",
+            Some("dQw4w9WgXcQ"),
+        ),
+        (
+            "https://www.youtube.com/live/dQw4w9WgXcQ?si=abc",
+            Some("dQw4w9WgXcQ"),
+        ),
+        ("https://www.youtube.com/watch?v=short", None),
+        ("https://youtu.be/dQw4w9WgXc", None),
+        ("https://www.youtube.com/watch?v=dQw4w9WgXcQextra", None),
+        // Only letters, digits, `_` and `-` are ever in an id.
+        ("https://youtu.be/dQw4w9W$XcQ", None),
+        ("https://www.youtube.com/watch?v=dQw4w9%20gXcQ", None),
+        ("https://www.youtube.com/shorts/dQw4w9W.XcQ", None),
+        ("https://youtu.be/a_b-c_d-e_f", Some("a_b-c_d-e_f")),
+        ("https://www.youtube.com/playlist?list=PL123", None),
+        ("https://www.youtube.com/@channel", None),
+        ("https://notyoutube.com/watch?v=dQw4w9WgXcQ", None),
+        ("https://example.com/?u=https://youtu.be/dQw4w9WgXcQ", None),
+    ];
+    for (url, expected) in cases {
+        assert_eq!(video_id(url).as_deref(), expected, "{url}");
+    }
+}
+
+#[test]
+fn test_playlist_id() {
+    assert_eq!(
+        playlist_id("https://www.youtube.com/playlist?list=PLFgquLnL59alCl_2TQvOiD5Vgm1hCaGSI"),
+        Some("PLFgquLnL59alCl_2TQvOiD5Vgm1hCaGSI".to_string())
+    );
+    assert_eq!(
+        playlist_id("https://youtube.com/playlist?si=abc&list=PL123"),
+        Some("PL123".to_string())
+    );
+    assert_eq!(
+        playlist_id("https://www.youtube.com/watch?v=dQw4w9WgXcQ&list=PL123"),
+        None
+    );
+}
+
+#[test]
+fn test_retry_policy() {
+    let policy = RetryPolicy::default();
+    for (attempt, base) in [(1, 2), (2, 4), (3, 8)] {
+        let delay = policy.delay(attempt);
+        let base = Duration::from_secs(base);
+        assert!(delay >= base && delay <= base + base / 2, "{delay:?}");
+    }
+
+    let status = |status| Error::ApiStatus {
+        status,
+        body: String::new(),
+        retry_after: None,
+    };
+    assert!(status(500).is_retryable());
+    assert!(status(503).is_retryable());
+    assert!(status(429).is_retryable());
+    assert!(!status(400).is_retryable());
+    assert!(!status(401).is_retryable());
+    assert!(!Error::MissingToken("OPENAI_API_TOKEN").is_retryable());
+    assert!(!Error::InvalidResponse("No choices in response".to_string()).is_retryable());
+}
+
+#[test]
+fn test_retry_after() {
+    let policy = RetryPolicy::default();
+    let mut headers = HeaderMap::new();
+    headers.insert(RETRY_AFTER, "5".parse().unwrap());
+
+    let rate_limited = status_error(
+        reqwest::StatusCode::TOO_MANY_REQUESTS,
+        &headers,
+        String::new(),
+    );
+    assert_eq!(
+        policy.delay_for(&rate_limited, 1).unwrap(),
+        Duration::from_secs(5)
+    );
+    assert_eq!(
+        policy.delay_for(&rate_limited, 3).unwrap(),
+        Duration::from_secs(5)
+    );
+    // A wait longer than `max_wait` isn't sat out.
+    let strict = RetryPolicy {
+        max_wait: Duration::from_secs(4),
+        ..policy
+    };
+    assert!(matches!(
+        strict.delay_for(&rate_limited, 1),
+        Err(Error::RateLimited(wait)) if wait == Duration::from_secs(5)
+    ));
+    // Only a 429's `Retry-After` is followed; anything else backs off.
+    let server_error = status_error(
+        reqwest::StatusCode::SERVICE_UNAVAILABLE,
+        &headers,
+        String::new(),
+    );
+    assert!(policy.delay_for(&server_error, 1).unwrap() >= Duration::from_secs(2));
+
+    assert_eq!(parse_retry_after("0.5"), Some(Duration::from_millis(500)));
+    assert_eq!(
+        parse_retry_after("Thu, 01 Jan 1970 00:00:00 GMT"),
+        Some(Duration::ZERO)
+    );
+    assert_eq!(parse_retry_after("soon"), None);
+    assert_eq!(parse_retry_after("-1"), None);
+}
+
+#[test]
+fn test_video_info() {
+    let response = |json: &str| serde_json::from_str::<VideoResponse>(json).unwrap();
+
+    assert!(matches!(
+        video_info(response(r#"{"items": []}"#)),
+        Err(Error::VideoNotFound)
+    ));
+    let info = video_info(response(
+        r#"{"items": [{"snippet": {"title": "A title", "channelTitle": "A channel"}}]}"#,
+    ))
+    .unwrap();
+    assert_eq!(info.title, "A title");
+    assert_eq!(info.channel_name, "A channel");
+    assert_eq!(info.published_at, None);
+
+    // A Short, without a channel name or description.
+    let info = video_info(response(
+        r#"{"kind": "youtube#videoListResponse", "items": [{"kind": "youtube#video", "id": "abcdefghijk", "snippet": {"publishedAt": "2023-10-18T15:00:07Z", "title": "Wait for it #shorts", "thumbnails": {}, "categoryId": "24"}}]}"#,
+    ))
+    .unwrap();
+    assert_eq!(info.title, "Wait for it #shorts");
+    assert_eq!(info.channel_name, "Unknown");
+    assert_eq!(info.description, "");
+    assert!(info.published_at.is_some());
+
+    // A finished live stream, with null fields.
+    let info = video_info(response(
+        r#"{"items": [{"id": "abcdefghijk", "snippet": {"publishedAt": null, "title": null, "channelTitle": "A channel", "description": null, "liveBroadcastContent": "none"}, "liveStreamingDetails": {"actualEndTime": "2023-10-18T17:00:07Z"}}]}"#,
+    ))
+    .unwrap();
+    assert_eq!(info.title, "Unknown");
+    assert_eq!(info.channel_name, "A channel");
+    assert_eq!(info.published_at, None);
+
+    // No snippet at all.
+    let info = video_info(response(r#"{"items": [{"snippet": null}, {}]}"#)).unwrap();
+    assert_eq!(info.title, "Unknown");
+}
+
+#[test]
+fn test_parse_published_at() {
+    let published_at = parse_published_at("2023-10-18T15:00:07Z").unwrap();
+    assert_eq!(published_at.timestamp(), 1697641207);
+    let published_at = parse_published_at("2023-10-18T17:00:07+02:00").unwrap();
+    assert_eq!(published_at.timestamp(), 1697641207);
+    assert_eq!(parse_published_at("last tuesday"), None);
+}
+
+#[test]
+fn test_slice_transcript() {
+    let items = (0..10)
+        .map(|i| TranscriptItem {
+            text: i.to_string(),
+            start: i as f64 * 6.0,
+            duration: 6.0,
+        })
+        .collect::<Vec<_>>();
+    let slice = |portion: &str| {
+        join_transcript(&slice_transcript(
+            items.clone(),
+            Portion::parse(portion).unwrap(),
+        ))
+    };
+
+    assert_eq!(slice("first-half"), "0 1 2 3 4");
+    assert_eq!(slice("last-half"), "5 6 7 8 9");
+    assert_eq!(slice("90%-100%"), "9");
+}
+
+#[test]
+fn test_speaker_labels() {
+    let transcript = "**Lex Fridman:** Welcome back.\n\n**Speaker B:** Thanks for having me.\n\nIt's **great** to be here.\n\n**Lex Fridman:** Let's start.";
+    assert_eq!(
+        speaker_labels(transcript),
+        vec!["Lex Fridman", "Speaker B", "Lex Fridman"]
+    );
+    assert!(speaker_labels("No speakers here. **Bold** text: fine.").is_empty());
+}
+
+#[test]
+fn test_chapter_transcripts() {
+    let items = (0..6)
+        .map(|i| TranscriptItem {
+            text: i.to_string(),
+            start: i as f64 * 10.0,
+            duration: 10.0,
+        })
+        .collect::<Vec<_>>();
+    let chapter = |start, title: &str| Chapter {
+        start,
+        title: title.to_string(),
+    };
+    let chapters = [
+        chapter(0, "Intro"),
+        chapter(20, "Middle"),
+        chapter(25, "Nothing said"),
+        chapter(30, "Late"),
+        chapter(40, "End"),
+    ];
+
+    assert_eq!(
+        chapter_transcripts(&items, &chapters),
+        vec![
+            ("Intro".to_string(), "0 1".to_string()),
+            ("Middle".to_string(), "2".to_string()),
+            ("Late".to_string(), "3".to_string()),
+            ("End".to_string(), "4 5".to_string()),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn test_transcript_responses() {
+    use wiremock::matchers::{path, query_param};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    let server = MockServer::start().await;
+    Mock::given(path("/transcript/ok"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "transcript": [
+                { "text": "Hello", "start": 0.0, "duration": 1.0 },
+                { "text": "world", "start": 1.0, "duration": 1.0 },
+            ]
+        })))
+        .mount(&server)
+        .await;
+    Mock::given(path("/transcript/none"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .set_body_json(serde_json::json!({ "message": "Transcripts are disabled" })),
+        )
+        .mount(&server)
+        .await;
+    // Some videos get a "success" that's only an error message.
+    Mock::given(path("/transcript/boilerplate"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "transcript": [
+                { "text": "No transcript available for this video.", "start": 0.0, "duration": 0.0 },
+            ]
+        })))
+        .mount(&server)
+        .await;
+    Mock::given(path("/transcript/blank"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "transcript": [{ "text": " ", "start": 0.0, "duration": 0.0 }]
+        })))
+        .mount(&server)
+        .await;
+    Mock::given(path("/transcript/ok"))
+        .and(query_param("lang", "es&lang=en"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "transcript": [{ "text": "Encoded", "start": 0.0, "duration": 1.0 }]
+        })))
+        .with_priority(1)
+        .mount(&server)
+        .await;
+    Mock::given(path("/transcript/ok"))
+        .and(query_param("lang", "es"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .set_body_json(serde_json::json!({ "message": "No Spanish captions" })),
+        )
+        .with_priority(1)
+        .mount(&server)
+        .await;
+    let client = YoutubeClient {
+        transcript_bases: vec![format!("{}/transcript", server.uri())],
+        ..YoutubeClient::default()
+    };
+
+    let transcript = client.transcript("ok", None).await.unwrap();
+    assert_eq!(join_transcript(&transcript), "Hello world");
+    assert!(matches!(
+        client.transcript("none", None).await,
+        Err(Error::Api(message)) if message == "Transcripts are disabled"
+    ));
+    assert!(matches!(
+        client.transcript("boilerplate", None).await,
+        Err(Error::TranscriptUnavailable)
+    ));
+    assert!(matches!(
+        client.transcript("blank", None).await,
+        Err(Error::TranscriptUnavailable)
+    ));
+    assert!(matches!(
+        client.transcript("ok", Some("es")).await,
+        Err(Error::NoTranscriptIn(language)) if language == "es"
+    ));
+    // A language can't add parameters of its own.
+    let transcript = client.transcript("ok", Some("es&lang=en")).await.unwrap();
+    assert_eq!(join_transcript(&transcript), "Encoded");
+
+    // A later endpoint is used when an earlier one fails, and when every
+    // one does, the error covers all of them.
+    let client = YoutubeClient {
+        transcript_bases: vec![
+            format!("{}/down", server.uri()),
+            format!("{}/transcript", server.uri()),
+        ],
+        ..YoutubeClient::default()
+    };
+    let transcript = client.transcript("ok", None).await.unwrap();
+    assert_eq!(join_transcript(&transcript), "Hello world");
+    let failure = client.transcript("none", None).await.unwrap_err();
+    assert!(matches!(&failure, Error::TranscriptSources(failures) if failures.len() == 2));
+    assert!(failure
+        .to_string()
+        .ends_with("\n- Source 2: Transcripts are disabled"));
+}
+
+#[test]
+fn test_finish_cleanup() {
+    let results = || {
+        vec![
+            Ok(("one".to_string(), openai::Usage::default())),
+            Err(Error::TimedOut(Duration::from_secs(1))),
+            Ok(("three".to_string(), openai::Usage::default())),
+        ]
+    };
+    let assemble = |chunks: Vec<String>| chunks.join("\n");
+
+    let (transcript, complete, _) = finish_cleanup(
+        "id",
+        results(),
+        3,
+        PartialTranscripts::SkipFailures,
+        assemble,
+    )
+    .unwrap();
+    assert_eq!(transcript, format!("one\n{OMITTED_CHUNK}\nthree"));
+    assert!(!complete);
+
+    let (transcript, complete, _) = finish_cleanup(
+        "id",
+        results(),
+        3,
+        PartialTranscripts::UpToFailure,
+        assemble,
+    )
+    .unwrap();
+    assert!(transcript.starts_with("one\n\n*Cleanup failed at part 2 of 3"));
+    assert!(!complete);
+
+    assert!(finish_cleanup("id", results(), 3, PartialTranscripts::Off, assemble).is_err());
+
+    let failed = vec![Err(Error::VideoNotFound), Err(Error::NoSpeech)];
+    assert!(matches!(
+        finish_cleanup("id", failed, 2, PartialTranscripts::SkipFailures, assemble),
+        Err(Error::VideoNotFound)
+    ));
+}
+
+#[tokio::test]
+async fn test_transcript_timeout() {
+    use wiremock::{matchers::path, Mock, MockServer, ResponseTemplate};
+
+    let server = MockServer::start().await;
+    Mock::given(path("/transcript/slow"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .set_body_json(serde_json::json!({ "transcript": [] }))
+                .set_delay(Duration::from_secs(5)),
+        )
+        .mount(&server)
+        .await;
+    let timeout = Duration::from_millis(200);
+    let client = YoutubeClient {
+        transcript_bases: vec![format!("{}/transcript", server.uri())],
+        ..YoutubeClient::with_timeout(timeout)
+    };
+
+    let started = Instant::now();
+    let result = client.transcript("slow", None).await;
+    assert!(matches!(result, Err(Error::TimedOut(after)) if after == timeout));
+    assert!(started.elapsed() < Duration::from_secs(5));
+}
+
+#[test]
+fn test_paragraphs() {
+    let item = |text: &str, start: f64| TranscriptItem {
+        text: text.to_string(),
+        start,
+        duration: 1.0,
+    };
+    let items = vec![
+        item("so today we're", 0.0),
+        item("going to going to talk", 1.0),
+        // A long pause before the next caption.
+        item("first thing", 5.0),
+        item("is this", 6.0),
+    ];
+    assert_eq!(
+        paragraphs(&items),
+        "so today we're going to talk\n\nfirst thing is this"
+    );
+
+    // Without pauses, long stretches are still broken up.
+    let items = (0..100)
+        .map(|i| item(&format!("word {i} said"), i as f64))
+        .collect::<Vec<_>>();
+    let text = paragraphs(&items);
+    assert_eq!(text.split("\n\n").count(), 3);
+    assert_eq!(text.split_whitespace().count(), 300);
+    assert_eq!(paragraphs(&[]), "");
+}
+
+#[test]
+fn test_is_boilerplate() {
+    let items = |texts: &[&str]| {
+        texts
+            .iter()
+            .map(|text| TranscriptItem {
+                text: text.to_string(),
+                start: 0.0,
+                duration: 1.0,
+            })
+            .collect::<Vec<_>>()
+    };
+    assert!(is_boilerplate(&items(&["Video unavailable"])));
+    assert!(is_boilerplate(&items(&["", "  "])));
+    assert!(!is_boilerplate(&items(&[])));
+    assert!(!is_boilerplate(&items(&["Hello", "world"])));
+    // A real transcript that happens to say it.
+    assert!(!is_boilerplate(&items(&[
+        "this one says",
+        "no transcript available",
+        "but then",
+        "keeps going"
+    ])));
+}
+
+#[test]
+fn test_api_status_hides_body() {
+    let error = |status| Error::ApiStatus {
+        status,
+        body: "{\"error\": \"org-123 quota\"}".to_string(),
+        retry_after: None,
+    };
+    for status in [400, 401, 429, 503] {
+        assert!(!error(status).to_string().contains("org-123"));
+    }
+    assert!(error(429).to_string().contains("rate-limiting"));
+}
+
+#[test]
+fn test_timestamp_paragraphs() {
+    // Two words a second.
+    let items = (0..100)
+        .map(|i| TranscriptItem {
+            text: format!("w{} w{}", i * 2, i * 2 + 1),
+            start: i as f64,
+            duration: 1.0,
+        })
+        .collect::<Vec<_>>();
+    let chunk = &transcript_chunks(&items, CLEANUP_CHUNK_WORDS)[0];
+    // Cleaned up into two paragraphs of the same length.
+    let first = vec!["word"; 100].join(" ");
+    let second = vec!["word"; 100].join(" ");
+    let paragraphs = timestamp_paragraphs("dQw4w9WgXcQ", &format!("{first}\n\n{second}"), chunk);
+    assert_eq!(paragraphs.len(), 2);
+    assert!(paragraphs[0].starts_with("[00:00]("));
+    assert!(paragraphs[1].starts_with("[00:50]("));
+}
+
+#[test]
+fn test_transcript_cache_key() {
+    let options = TranscriptOptions::default();
+    let key = |options: &TranscriptOptions| transcript_cache_key("dQw4w9WgXcQ", options);
+    assert_eq!(key(&options), key(&options.clone()));
+    assert_ne!(
+        key(&options),
+        key(&TranscriptOptions {
+            timestamps: true,
+            ..options.clone()
+        })
+    );
+    assert_ne!(
+        key(&options),
+        key(&TranscriptOptions {
+            model: "gpt-4".to_string(),
+            ..options.clone()
+        })
+    );
+    assert_ne!(
+        key(&options),
+        key(&TranscriptOptions {
+            paragraph_sentences: 3,
+            ..options.clone()
+        })
+    );
 }